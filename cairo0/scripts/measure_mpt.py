@@ -0,0 +1,49 @@
+#!venv/bin/python3
+"""Runs the compiled mpt.cairo on its fixture and writes the execution resources of the run.
+
+Compile the verifier with `make run` first. The resources are written to
+build/mpt_input_resources.json, which the `test_estimate_matches_cairo_run` test of the
+Rust service compares the cost estimate against.
+"""
+
+import json
+import os
+
+from starkware.cairo.lang.compiler.program import Program
+from starkware.cairo.lang.vm.cairo_runner import CairoRunner
+
+BUILD_DIR = "build"
+COMPILED_PATH = os.path.join(BUILD_DIR, "compiled_cairo_files", "mpt.json")
+INPUT_PATH = os.path.join("src", "mpt_input.json")
+RESOURCES_PATH = os.path.join(BUILD_DIR, "mpt_input_resources.json")
+LAYOUT = "recursive_with_poseidon"
+
+
+def main():
+    with open(COMPILED_PATH) as file:
+        program = Program.Schema().load(json.load(file))
+    with open(INPUT_PATH) as file:
+        program_input = json.load(file)
+
+    runner = CairoRunner(program=program, layout=LAYOUT, proof_mode=False)
+    runner.initialize_segments()
+    end = runner.initialize_main_entrypoint()
+    runner.initialize_vm(hint_locals={"program_input": program_input})
+    runner.run_until_pc(end)
+    runner.end_run()
+
+    resources = runner.get_execution_resources()
+    with open(RESOURCES_PATH, "w") as file:
+        json.dump(
+            {
+                "n_steps": resources.n_steps,
+                "builtin_instance_counter": resources.builtin_instance_counter,
+            },
+            file,
+            indent=2,
+        )
+    print(f"Wrote {RESOURCES_PATH}: {resources}")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,74 @@
+//! Benchmarks leaf lookups in a database holding a million leaves.
+//!
+//! Run with `cargo bench --bench leaf_lookup`. Regressions to full scans of the leaves table are
+//! caught by `test_lookups_use_indexes` instead, as wall-clock times depend on the machine.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pathfinder_crypto::Felt;
+use pathfinder_merkle_tree::storage::Storage;
+use rand::seq::SliceRandom;
+use sn_mpt::db::trie::TrieDB;
+use sn_mpt::db::{self, ConnectionManager};
+use sn_mpt::models::batch::NewBatch;
+use sn_mpt::trie_cache::item::CachedItem;
+
+const BATCHES: u64 = 1_000;
+const LEAVES_PER_BATCH: u64 = 1_000;
+const LOOKUPS: usize = 10_000;
+
+fn leaf_lookup(c: &mut Criterion) {
+    let file = std::env::temp_dir().join(format!("{}_bench.db", rand::random::<u32>()));
+    let manager = ConnectionManager::new(&file.to_string_lossy());
+    manager.migrate().unwrap();
+    let conn = manager.get_connection().unwrap();
+    let storage = TrieDB::new(&conn);
+
+    let mut keys = Vec::with_capacity((BATCHES * LEAVES_PER_BATCH) as usize);
+    let tx = conn.unchecked_transaction().unwrap();
+    for _ in 0..BATCHES {
+        // Batches without a trie, for the leaves to reference
+        let batch_id = db::batch::create_batch(
+            &conn,
+            &NewBatch {
+                parent_id: None,
+                root_idx: 1,
+                root_hash: Felt::ZERO,
+                pre_root_idx: 1,
+                pre_root: Felt::ZERO,
+                item_count: 0,
+                nodes_added: 0,
+                group_id: None,
+            },
+        )
+        .unwrap();
+        let items: Vec<_> = (0..LEAVES_PER_BATCH)
+            .map(|idx| CachedItem::new((batch_id * LEAVES_PER_BATCH + idx).to_be_bytes().to_vec()))
+            .collect();
+        storage.persist_leaves(&items, batch_id).unwrap();
+        keys.extend(
+            items
+                .into_iter()
+                .map(|item| item.key.view_bits().to_bitvec()),
+        );
+    }
+    tx.commit().unwrap();
+
+    let paths: Vec<_> = keys
+        .choose_multiple(&mut rand::thread_rng(), LOOKUPS)
+        .collect();
+    for (name, storage) in [
+        ("latest", TrieDB::new(&conn)),
+        ("at batch", TrieDB::at_batch(&conn, BATCHES / 2)),
+    ] {
+        let mut next = paths.iter().cycle();
+        c.bench_function(&format!("leaf lookup ({})", name), |b| {
+            b.iter(|| storage.leaf(next.next().unwrap()).unwrap())
+        });
+    }
+
+    drop(conn);
+    let _ = std::fs::remove_file(&file);
+}
+
+criterion_group!(benches, leaf_lookup);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+-- A database written before the schema version was tracked, at version 0.
+CREATE TABLE trie_nodes (
+    idx INTEGER PRIMARY KEY,
+    hash BLOB NOT NULL,
+    data BLOB,
+    trie_idx INTEGER UNIQUE NOT NULL
+);
+CREATE TABLE leaves (
+    idx INTEGER PRIMARY KEY,
+    key BLOB NOT NULL,
+    commitment BLOB NOT NULL,
+    value BLOB,
+    batch_id INTEGER NOT NULL
+);
+CREATE TABLE batches (
+    id INTEGER PRIMARY KEY,
+    parent_id INTEGER,
+    status TEXT NOT NULL,
+    root_idx INTEGER NOT NULL,
+    FOREIGN KEY (parent_id) REFERENCES batches(id)
+);
+
+INSERT INTO trie_nodes (idx, hash, data, trie_idx) VALUES
+    (1, X'0000000000000000000000000000000000000000000000000000000000000001', NULL, 1),
+    (2, X'0000000000000000000000000000000000000000000000000000000000000002', NULL, 2),
+    (3, X'0000000000000000000000000000000000000000000000000000000000000003', NULL, 3);
+
+-- The genesis leaf, two leaves of batch 1, one of them written twice, a leaf of batch 2 and a
+-- leaf of batch 3, which was never created
+INSERT INTO leaves (key, commitment, value, batch_id) VALUES
+    (X'000000000000000000000000000000000000000000000000000000000000000a', X'000000000000000000000000000000000000000000000000000000000000000a', X'00', 0),
+    (X'000000000000000000000000000000000000000000000000000000000000000b', X'000000000000000000000000000000000000000000000000000000000000000b', X'0b', 1),
+    (X'000000000000000000000000000000000000000000000000000000000000000c', X'000000000000000000000000000000000000000000000000000000000000000c', X'0c', 1),
+    (X'000000000000000000000000000000000000000000000000000000000000000c', X'000000000000000000000000000000000000000000000000000000000000000c', X'0c', 1),
+    (X'000000000000000000000000000000000000000000000000000000000000000d', X'000000000000000000000000000000000000000000000000000000000000000d', X'0d', 2),
+    (X'000000000000000000000000000000000000000000000000000000000000000e', X'000000000000000000000000000000000000000000000000000000000000000e', X'0e', 3);
+
+INSERT INTO batches (id, parent_id, status, root_idx) VALUES
+    (1, NULL, 'finalized', 2),
+    (2, 1, 'created', 3);
@@ -1,31 +1,81 @@
 use r2d2::PooledConnection;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, OptionalExtension};
+use rusqlite::{params, OptionalExtension, Transaction};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::errors::TrieCacheError;
-use crate::models::batch::{Batch, BatchStatus};
+use crate::models::batch::{
+    batch_commitment, Batch, BatchQuery, BatchStatus, NewBatch, StatusChange,
+};
+use crate::models::page::{Page, SortOrder};
+use pathfinder_crypto::Felt;
 
-/// Retrieves all batches from the database.
+/// The columns read by `Batch::try_from`, in order.
+macro_rules! batch_columns {
+    () => {
+        "id, parent_id, status, root_idx, root_hash, pre_root_idx, pre_root, item_count, \
+        nodes_added, created_at, finalized_at, reverted_at, group_id, batch_commitment, \
+        pruned_at"
+    };
+}
+
+/// Retrieves a page of batches from the database.
 ///
 /// # Arguments
 ///
 /// * `conn` - A pooled connection to the SQLite database.
+/// * `query` - The cursor, page size, filters and ordering to apply.
 ///
 /// # Returns
 ///
-/// A `Result` containing a vector of `Batch` objects or a `TrieCacheError` if an error occurs.
+/// A `Result` containing a `Page` of `Batch` objects or a `TrieCacheError` if an error occurs.
 pub fn get_batches(
     conn: &PooledConnection<SqliteConnectionManager>,
-) -> Result<Vec<Batch>, TrieCacheError> {
+    query: &BatchQuery,
+) -> Result<Page<Batch>, TrieCacheError> {
+    // Unset filters are bound as NULL and match every batch
+    const ASC_QUERY: &str = concat!(
+        "SELECT ",
+        batch_columns!(),
+        " FROM batches
+        WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR id >= ?2)
+            AND (?3 IS NULL OR id <= ?3) AND (?4 IS NULL OR id > ?4)
+            AND (?6 IS NULL OR group_id = ?6)
+        ORDER BY id ASC LIMIT ?5"
+    );
+    const DESC_QUERY: &str = concat!(
+        "SELECT ",
+        batch_columns!(),
+        " FROM batches
+        WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR id >= ?2)
+            AND (?3 IS NULL OR id <= ?3) AND (?4 IS NULL OR id < ?4)
+            AND (?6 IS NULL OR group_id = ?6)
+        ORDER BY id DESC LIMIT ?5"
+    );
+
     // Prepare the SQL statement
-    let mut stmt = conn.prepare_cached("SELECT id, parent_id, status, root_idx FROM batches")?;
+    let mut stmt = conn.prepare_cached(match query.order {
+        SortOrder::Asc => ASC_QUERY,
+        SortOrder::Desc => DESC_QUERY,
+    })?;
 
-    // Execute the query and map the result rows to Batch objects
+    // Fetch one extra row to find out whether another page follows
+    let limit = query.limit();
     let batches: Vec<Batch> = stmt
-        .query_map([], |row| Batch::try_from(row))?
+        .query_map(
+            params![
+                query.status.as_ref().map(BatchStatus::to_string),
+                query.from_id,
+                query.to_id,
+                query.cursor,
+                limit + 1,
+                query.group_id
+            ],
+            |row| Batch::try_from(row),
+        )?
         .collect::<Result<_, _>>()?;
 
-    Ok(batches)
+    Ok(Page::new(batches, limit, |batch| batch.id))
 }
 
 /// Retrieves a single batch from the database by its ID.
@@ -43,69 +93,233 @@ pub fn get_batch(
     id: u64,
 ) -> Result<Batch, TrieCacheError> {
     // Prepare the SQL statement
-    let mut stmt =
-        conn.prepare_cached("SELECT id, parent_id, status, root_idx FROM batches WHERE id = ?")?;
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT ",
+        batch_columns!(),
+        " FROM batches WHERE id = ?"
+    ))?;
 
     // Execute the query and retrieve the result row
-    stmt
-        .query_row(params![id], |row| Batch::try_from(row))
-        .map_err(|_| TrieCacheError::BatchNotFound)
+    stmt.query_row(params![id], |row| Batch::try_from(row))
+        .optional()?
+        .ok_or(TrieCacheError::BatchNotFound)
 }
 
-/// Creates a new batch in the database.
+/// Creates a new batch in the database and records its initial status.
+///
+/// The batch commitment is computed from the commitment of the parent, which must already be stored.
 ///
 /// # Arguments
 ///
 /// * `conn` - A pooled connection to the SQLite database.
-/// * `parent_id` - The ID of the parent batch, or `None` if it has no parent.
-/// * `root_idx` - The root index of the batch.
+/// * `batch` - The parent, roots and counters of the new batch.
 ///
 /// # Returns
 ///
 /// A `Result` containing the ID of the newly created batch or a `TrieCacheError` if an error occurs.
 pub fn create_batch(
     conn: &PooledConnection<SqliteConnectionManager>,
-    parent_id: Option<u64>,
-    root_idx: u64,
+    batch: &NewBatch,
 ) -> Result<u64, TrieCacheError> {
-    const INSERT_QUERY: &str = "INSERT INTO batches (parent_id, status, root_idx) VALUES (?, ?, ?)";
+    const INSERT_QUERY: &str = "INSERT INTO batches (
+            parent_id, status, root_idx, root_hash, pre_root_idx, pre_root, item_count,
+            nodes_added, created_at, group_id
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
 
-    // Execute the INSERT query
+    // Execute the INSERT query, together with the status history and commitment of the batch
+    let tx = write_transaction(conn)?;
+    let created_at = unix_timestamp();
     conn.execute(
         INSERT_QUERY,
-        params![parent_id, BatchStatus::Created.to_string(), root_idx],
+        params![
+            batch.parent_id,
+            BatchStatus::Created.to_string(),
+            batch.root_idx,
+            batch.root_hash.to_be_bytes().to_vec(),
+            batch.pre_root_idx,
+            batch.pre_root.to_be_bytes().to_vec(),
+            batch.item_count,
+            batch.nodes_added,
+            created_at,
+            batch.group_id
+        ],
     )
     .map_err(TrieCacheError::from)?;
 
     // Retrieve the ID of the last inserted row
-    Ok(conn.last_insert_rowid() as u64)
+    let id = conn.last_insert_rowid() as u64;
+    insert_status_change(conn, &id, &BatchStatus::Created, created_at)?;
+    store_commitment(
+        conn,
+        id,
+        batch.parent_id,
+        &batch.pre_root,
+        &batch.root_hash,
+        batch.item_count,
+    )?;
+    if let Some(tx) = tx {
+        tx.commit()?;
+    }
+
+    Ok(id)
 }
 
-/// Retrieves the latest batch with a specific status from the database.
+/// Inserts a batch with its ID, status, roots, timestamps and status history as they are given.
+///
+/// Unlike `create_batch`, nothing is derived, so batches can be copied from another database.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `batch` - The batch to insert.
+/// * `history` - The status history of the batch, oldest first.
+///
+/// # Returns
+///
+/// A `Result` indicating success or a `TrieCacheError` if an error occurs.
+pub fn restore_batch(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    batch: &Batch,
+    history: &[StatusChange],
+) -> Result<(), TrieCacheError> {
+    let decode = |value: &str| hex::decode(value).map_err(|_| TrieCacheError::InvalidHexString);
+    conn.execute(
+        "INSERT INTO batches (
+            id, parent_id, status, root_idx, root_hash, pre_root_idx, pre_root, item_count,
+            nodes_added, created_at, finalized_at, reverted_at, group_id, batch_commitment,
+            pruned_at
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            batch.id,
+            batch.parent_id,
+            batch.status.to_string(),
+            batch.root_idx,
+            decode(&batch.root_hash)?,
+            batch.pre_root_idx,
+            decode(&batch.pre_root)?,
+            batch.item_count,
+            batch.nodes_added,
+            batch.created_at,
+            batch.finalized_at,
+            batch.reverted_at,
+            batch.group_id,
+            batch.batch_commitment.as_deref().map(decode).transpose()?,
+            batch.pruned_at,
+        ],
+    )?;
+    for change in history {
+        insert_status_change(conn, &batch.id, &change.status, change.changed_at)?;
+    }
+
+    Ok(())
+}
+
+/// Computes the commitment of a batch from the stored commitment of its parent and stores it.
+fn store_commitment(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    id: u64,
+    parent_id: Option<u64>,
+    pre_root: &Felt,
+    post_root: &Felt,
+    item_count: u64,
+) -> Result<(), TrieCacheError> {
+    let parent_commitment = match parent_id {
+        Some(parent_id) => {
+            let commitment: Option<Vec<u8>> = conn
+                .query_row(
+                    "SELECT batch_commitment FROM batches WHERE id = ?",
+                    params![parent_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .ok_or(TrieCacheError::BatchNotFound)?;
+            let commitment = commitment.ok_or(TrieCacheError::BatchNotFound)?;
+            Felt::from_be_slice(&commitment).map_err(anyhow::Error::from)?
+        }
+        None => Felt::ZERO,
+    };
+
+    let commitment = batch_commitment(parent_commitment, id, *pre_root, *post_root, item_count);
+    conn.execute(
+        "UPDATE batches SET batch_commitment = ? WHERE id = ?",
+        params![commitment.to_be_bytes().to_vec(), id],
+    )?;
+
+    Ok(())
+}
+
+/// Returns the ID of the latest batch, whatever its status.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+///
+/// # Returns
+///
+/// A `Result` containing the latest batch ID, `None` if there are no batches, or a `TrieCacheError` if an error occurs.
+pub fn get_latest_batch_id(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<Option<u64>, TrieCacheError> {
+    Ok(conn.query_row("SELECT MAX(id) FROM batches", [], |row| row.get(0))?)
+}
+
+/// Sets the group of a batch that was split into several proofs.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `id` - The ID of the batch.
+/// * `group_id` - The ID of the first sub-batch of the group.
+///
+/// # Returns
+///
+/// A `Result` indicating success or a `TrieCacheError` if the batch is not found.
+pub fn set_group_id(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    id: u64,
+    group_id: u64,
+) -> Result<(), TrieCacheError> {
+    let updated = conn.execute(
+        "UPDATE batches SET group_id = ? WHERE id = ?",
+        params![group_id, id],
+    )?;
+    match updated {
+        0 => Err(TrieCacheError::BatchNotFound),
+        _ => Ok(()),
+    }
+}
+
+/// Retrieves the latest batch that isn't reverted, the parent of the next created batch.
 ///
 /// # Arguments
 ///
 /// * `conn` - A pooled connection to the SQLite database.
-/// * `status` - The status of the batch to retrieve.
 ///
 /// # Returns
 ///
 /// A `Result` containing an `Option` of the retrieved `Batch` object or a `TrieCacheError` if an error occurs.
-pub fn get_latest_batch_by_status(
+pub fn get_latest_unreverted_batch(
     conn: &PooledConnection<SqliteConnectionManager>,
-    status: BatchStatus,
 ) -> Result<Option<Batch>, TrieCacheError> {
-    // Prepare the SQL statement
-    let mut stmt = conn.prepare_cached(
-    "SELECT id, parent_id, status, root_idx FROM batches WHERE status = ? ORDER BY id DESC LIMIT 1"
-    ).map_err(TrieCacheError::from)?;
+    let mut stmt = conn.prepare_cached(concat!(
+        "SELECT ",
+        batch_columns!(),
+        " FROM batches WHERE status != ? ORDER BY id DESC LIMIT 1"
+    ))?;
 
     Ok(stmt
-        .query_row(params![status.to_string()], |row| Batch::try_from(row))
+        .query_row(params![BatchStatus::Reverted.to_string()], |row| {
+            Batch::try_from(row)
+        })
         .optional()?)
 }
 
-/// Updates the status of a batch in the database.
+/// Updates the status of a batch in the database and records the transition.
+///
+/// The update only applies if the batch is still in the status it was read in, so a
+/// concurrent status change is reported as an invalid transition instead of being overwritten.
+/// The status and its history entry are written in one transaction, or in the caller's if it runs
+/// one. Callers that need several updates to apply atomically should run them in a transaction.
 ///
 /// # Arguments
 ///
@@ -115,29 +329,215 @@ pub fn get_latest_batch_by_status(
 ///
 /// # Returns
 ///
-/// A `Result` indicating success or a `TrieCacheError` if the batch is not found.
+/// A `Result` indicating success, or a `TrieCacheError` if the batch is not found or
+/// its current status does not allow moving to `new_status`.
 pub fn update_batch_status(
     conn: &PooledConnection<SqliteConnectionManager>,
     id: &u64,
     new_status: BatchStatus,
 ) -> Result<(), TrieCacheError> {
-    // Execute the UPDATE query
+    let current_status = get_batch(conn, *id)?.status;
+    if !current_status.can_transition_to(&new_status) {
+        return Err(TrieCacheError::InvalidStatusTransition {
+            from: current_status,
+            to: new_status,
+        });
+    }
+
+    // Execute the UPDATE query, stamping the matching transition time, and record the transition
+    // in the same transaction
+    let tx = write_transaction(conn)?;
+    let changed_at = unix_timestamp();
     let updated_rows = conn.execute(
-        "UPDATE batches SET status = ?1 WHERE id = ?2",
-        params![new_status.to_string(), id],
+        "UPDATE batches SET status = ?1,
+            finalized_at = CASE WHEN ?1 = ?3 THEN ?5 ELSE finalized_at END,
+            reverted_at = CASE WHEN ?1 = ?4 THEN ?5 ELSE reverted_at END
+        WHERE id = ?2 AND status = ?6",
+        params![
+            new_status.to_string(),
+            id,
+            BatchStatus::Finalized.to_string(),
+            BatchStatus::Reverted.to_string(),
+            changed_at,
+            current_status.to_string()
+        ],
     )?;
 
     if updated_rows == 0 {
-        Err(TrieCacheError::BatchNotFound)
+        return Err(TrieCacheError::InvalidStatusTransition {
+            from: get_batch(conn, *id)?.status,
+            to: new_status,
+        });
+    }
+    insert_status_change(conn, id, &new_status, changed_at)?;
+    if let Some(tx) = tx {
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Retrieves the IDs of the finalized batches a retention policy doesn't retain.
+///
+/// A finalized batch is retained if it's one of the last `keep_last` finalized batches, or if it
+/// was finalized less than `newer_than` seconds ago. Batches without a finalization time count as old.
+/// The latest finalized batch is always retained.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `keep_last` - The number of most recent finalized batches to retain, if set.
+/// * `newer_than` - The age in seconds below which finalized batches are retained, if set.
+///
+/// # Returns
+///
+/// A `Result` containing the IDs of the batches to prune in ascending order or a `TrieCacheError` if an error occurs.
+pub fn get_prunable_batch_ids(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    keep_last: Option<u64>,
+    newer_than: Option<u64>,
+) -> Result<Vec<u64>, TrieCacheError> {
+    let cutoff = newer_than.map(|seconds| unix_timestamp().saturating_sub(seconds));
+    let mut stmt = conn.prepare_cached(
+        "SELECT id FROM batches
+        WHERE status = ?1 AND pruned_at IS NULL
+            AND id NOT IN (SELECT id FROM batches WHERE status = ?1 ORDER BY id DESC LIMIT MAX(?2, 1))
+            AND (?3 IS NULL OR IFNULL(finalized_at, 0) < ?3)
+        ORDER BY id ASC",
+    )?;
+
+    let ids: Vec<u64> = stmt
+        .query_map(
+            params![
+                BatchStatus::Finalized.to_string(),
+                keep_last.unwrap_or(0),
+                cutoff
+            ],
+            |row| row.get(0),
+        )?
+        .collect::<Result<_, _>>()?;
+
+    Ok(ids)
+}
+
+/// Marks a batch as pruned, keeping its row as a tombstone.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `id` - The ID of the batch.
+///
+/// # Returns
+///
+/// A `Result` indicating success or a `TrieCacheError` if an error occurs.
+pub fn mark_pruned(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    id: u64,
+) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "UPDATE batches SET pruned_at = ? WHERE id = ? AND pruned_at IS NULL",
+        params![unix_timestamp(), id],
+    )?;
+
+    Ok(())
+}
+
+/// Retrieves the IDs of all batches built on top of a batch, directly or transitively.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `id` - The ID of the batch.
+///
+/// # Returns
+///
+/// A `Result` containing the descendant IDs in ascending order or a `TrieCacheError` if an error occurs.
+pub fn get_descendant_ids(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    id: u64,
+) -> Result<Vec<u64>, TrieCacheError> {
+    let mut stmt = conn.prepare_cached(
+        "WITH RECURSIVE descendants(id) AS (
+            SELECT id FROM batches WHERE parent_id = ?1
+            UNION ALL
+            SELECT batches.id FROM batches JOIN descendants ON batches.parent_id = descendants.id
+        )
+        SELECT id FROM descendants ORDER BY id ASC",
+    )?;
+
+    let ids: Vec<u64> = stmt
+        .query_map(params![id], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+
+    Ok(ids)
+}
+
+/// Retrieves the status transitions of a batch, oldest first.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `id` - The ID of the batch.
+///
+/// # Returns
+///
+/// A `Result` containing a vector of `StatusChange` objects or a `TrieCacheError` if an error occurs.
+pub fn get_status_history(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    id: u64,
+) -> Result<Vec<StatusChange>, TrieCacheError> {
+    // Prepare the SQL statement
+    let mut stmt = conn.prepare_cached(
+        "SELECT status, changed_at FROM batch_status_history WHERE batch_id = ? ORDER BY idx ASC",
+    )?;
+
+    let history: Vec<StatusChange> = stmt
+        .query_map(params![id], |row| StatusChange::try_from(row))?
+        .collect::<Result<_, _>>()?;
+
+    Ok(history)
+}
+
+/// Starts a transaction for writes that must apply together, unless the caller already runs one.
+///
+/// Inside a caller's transaction, the writes commit or roll back with it.
+fn write_transaction(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<Option<Transaction<'_>>, TrieCacheError> {
+    if conn.is_autocommit() {
+        Ok(Some(conn.unchecked_transaction()?))
     } else {
-        Ok(())
+        Ok(None)
     }
 }
 
+/// Appends a status transition to the history of a batch.
+fn insert_status_change(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    id: &u64,
+    status: &BatchStatus,
+    changed_at: u64,
+) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "INSERT INTO batch_status_history (batch_id, status, changed_at) VALUES (?, ?, ?)",
+        params![id, status.to_string(), changed_at],
+    )?;
+
+    Ok(())
+}
+
+/// Returns the current time as seconds since the Unix epoch.
+pub(crate) fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::db;
+    use pathfinder_crypto::Felt;
 
     #[test]
     fn test_db_operations() {
@@ -146,41 +546,158 @@ mod test {
 
         assert!(get_batch(&conn, 1).is_err());
 
-        let batch = Batch {
-            id: 1,
+        let new_batch = NewBatch {
             parent_id: None,
-            status: BatchStatus::Created,
             root_idx: 1,
+            root_hash: Felt::from_u64(1),
+            pre_root_idx: 0,
+            pre_root: Felt::ZERO,
+            item_count: 3,
+            nodes_added: 1,
+            group_id: None,
         };
-        assert!(create_batch(&conn, batch.parent_id, batch.root_idx).is_ok());
+        assert_eq!(create_batch(&conn, &new_batch).unwrap(), 1);
 
-        assert_eq!(get_batch(&conn, batch.id).unwrap(), batch);
+        let batch = get_batch(&conn, 1).unwrap();
+        assert_eq!(batch.parent_id, None);
+        assert_eq!(batch.status, BatchStatus::Created);
+        assert_eq!(batch.root_idx, 1);
+        assert_eq!(
+            batch.root_hash,
+            hex::encode(Felt::from_u64(1).to_be_bytes())
+        );
+        assert_eq!(batch.pre_root, hex::encode(Felt::ZERO.to_be_bytes()));
+        assert_eq!(batch.item_count, 3);
+        assert_eq!(batch.nodes_added, Some(1));
+        assert!(batch.created_at.is_some());
+        assert_eq!(batch.finalized_at, None);
 
-        let batch_1 = Batch {
-            id: 2,
+        let new_batch_1 = NewBatch {
             parent_id: Some(1),
-            status: BatchStatus::Created,
             root_idx: 7,
+            root_hash: Felt::from_u64(7),
+            pre_root_idx: 1,
+            pre_root: Felt::from_u64(1),
+            item_count: 5,
+            nodes_added: 6,
+            group_id: None,
         };
-        assert!(create_batch(&conn, batch_1.parent_id, batch_1.root_idx).is_ok());
+        assert_eq!(create_batch(&conn, &new_batch_1).unwrap(), 2);
+
+        let batch_1 = get_batch(&conn, 2).unwrap();
+        assert_eq!(batch_1.parent_id, Some(1));
+        assert_eq!(batch_1.pre_root, batch.root_hash);
 
-        assert_eq!(get_batch(&conn, batch_1.id).unwrap(), batch_1);
+        assert_eq!(get_latest_unreverted_batch(&conn).unwrap(), Some(batch_1));
 
         assert_eq!(
-            get_latest_batch_by_status(&conn, BatchStatus::Created).unwrap(),
-            Some(batch_1)
+            get_batches(&conn, &BatchQuery::default())
+                .unwrap()
+                .items
+                .len(),
+            2
         );
 
-        assert_eq!(get_batches(&conn).unwrap().len(), 2);
-
         assert!(update_batch_status(&conn, &batch.id, BatchStatus::Finalized).is_ok());
 
-        assert_eq!(
-            get_latest_batch_by_status(&conn, BatchStatus::Finalized)
-                .unwrap()
-                .unwrap()
-                .id,
-            batch.id
-        );
+        let finalized = get_batch(&conn, batch.id).unwrap();
+        assert_eq!(finalized.status, BatchStatus::Finalized);
+        assert!(finalized.finalized_at.is_some());
+
+        let history: Vec<BatchStatus> = get_status_history(&conn, batch.id)
+            .unwrap()
+            .into_iter()
+            .map(|change| change.status)
+            .collect();
+        assert_eq!(history, vec![BatchStatus::Created, BatchStatus::Finalized]);
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        let test_ctx = db::test::TestContext::new();
+        let _ = test_ctx.batch_seeding();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        // Finalized and reverted batches are terminal
+        assert!(matches!(
+            update_batch_status(&conn, &1, BatchStatus::Finalized),
+            Err(TrieCacheError::InvalidStatusTransition {
+                from: BatchStatus::Finalized,
+                to: BatchStatus::Finalized
+            })
+        ));
+        assert!(update_batch_status(&conn, &3, BatchStatus::Reverted).is_ok());
+        assert!(matches!(
+            update_batch_status(&conn, &3, BatchStatus::Finalized),
+            Err(TrieCacheError::InvalidStatusTransition {
+                from: BatchStatus::Reverted,
+                to: BatchStatus::Finalized
+            })
+        ));
+        assert!(matches!(
+            update_batch_status(&conn, &10, BatchStatus::Finalized),
+            Err(TrieCacheError::BatchNotFound)
+        ));
+        assert_eq!(get_descendant_ids(&conn, 1).unwrap(), vec![2, 3]);
+
+        // The status only changes if its transition is recorded
+        conn.execute("DROP TABLE batch_status_history", []).unwrap();
+        assert!(update_batch_status(&conn, &2, BatchStatus::Finalized).is_err());
+        assert_eq!(get_batch(&conn, 2).unwrap().status, BatchStatus::Created);
+        assert_eq!(get_batch(&conn, 2).unwrap().finalized_at, None);
+        assert!(conn.is_autocommit());
+
+        // Unknown persisted statuses are reported as corruption
+        conn.execute("UPDATE batches SET status = 'pending' WHERE id = 2", [])
+            .unwrap();
+        assert!(matches!(
+            get_batch(&conn, 2),
+            Err(TrieCacheError::CorruptBatchStatus(status)) if status == "pending"
+        ));
+    }
+
+    #[test]
+    fn test_get_batches_pagination() {
+        let test_ctx = db::test::TestContext::new();
+        let batches = test_ctx.batch_seeding();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let query = BatchQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = get_batches(&conn, &query).unwrap();
+        assert_eq!(page.items, batches[..2]);
+        assert_eq!(page.next_cursor, Some(2));
+
+        let query = BatchQuery {
+            cursor: page.next_cursor,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = get_batches(&conn, &query).unwrap();
+        assert_eq!(page.items, batches[2..]);
+        assert_eq!(page.next_cursor, None);
+
+        let query = BatchQuery {
+            order: SortOrder::Desc,
+            cursor: Some(3),
+            ..Default::default()
+        };
+        let ids: Vec<u64> = get_batches(&conn, &query)
+            .unwrap()
+            .items
+            .iter()
+            .map(|batch| batch.id)
+            .collect();
+        assert_eq!(ids, vec![2, 1]);
+
+        let query = BatchQuery {
+            status: Some(BatchStatus::Created),
+            from_id: Some(3),
+            to_id: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(get_batches(&conn, &query).unwrap().items, batches[2..]);
     }
 }
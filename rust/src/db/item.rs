@@ -0,0 +1,143 @@
+use pathfinder_crypto::Felt;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, OptionalExtension};
+
+use crate::db::trie::LEAF_QUERY;
+use crate::errors::TrieCacheError;
+use crate::models::batch::BatchStatus;
+use crate::models::item::Item;
+use crate::trie_cache::item::CachedItem;
+
+/// Retrieves an item as it was at a specific batch.
+///
+/// Reads the same leaf version as `TrieDB::leaf` on a `TrieDB` bound to the batch.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `key` - The key of the item to retrieve.
+/// * `batch_id` - The batch at which the item is read, or `None` for the latest version.
+///
+/// # Returns
+///
+/// A `Result` containing an `Option` of the retrieved `Item` or a `TrieCacheError` if an error occurs.
+pub fn get_item(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    key: &Felt,
+    batch_id: Option<u64>,
+) -> Result<Option<Item>, TrieCacheError> {
+    // Prepare the SQL statement
+    let mut stmt = conn.prepare_cached(LEAF_QUERY)?;
+
+    Ok(stmt
+        .query_row(
+            params![
+                key.to_be_bytes().to_vec(),
+                batch_id.map_or(i64::MAX, |id| id as i64),
+                BatchStatus::Reverted.to_string()
+            ],
+            |row| Item::try_from(row),
+        )
+        .optional()?)
+}
+
+/// Retrieves the keys of the leaves written by a batch.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `batch_id` - The ID of the batch.
+///
+/// # Returns
+///
+/// A `Result` containing the keys or a `TrieCacheError` if an error occurs.
+pub fn get_batch_keys(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    batch_id: u64,
+) -> Result<Vec<Felt>, TrieCacheError> {
+    // Prepare the SQL statement
+    let mut stmt = conn.prepare_cached("SELECT key FROM leaves WHERE batch_id = ? ORDER BY idx")?;
+
+    let keys: Vec<Vec<u8>> = stmt
+        .query_map(params![batch_id], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+
+    Ok(keys
+        .iter()
+        .map(|key| Felt::from_be_slice(key))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(anyhow::Error::from)?)
+}
+
+/// Retrieves the leaves written by a batch as the items they were written from.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `batch_id` - The ID of the batch.
+///
+/// # Returns
+///
+/// A `Result` containing the items in the order they were written or a `TrieCacheError` if an error occurs.
+pub fn get_batch_items(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    batch_id: u64,
+) -> Result<Vec<CachedItem>, TrieCacheError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT key, commitment, value FROM leaves WHERE batch_id = ? ORDER BY idx",
+    )?;
+
+    let items = stmt
+        .query_map(params![batch_id], |row| {
+            let key: Vec<u8> = row.get(0)?;
+            let commitment: Vec<u8> = row.get(1)?;
+            let value: Option<Vec<u8>> = row.get(2)?;
+            Ok((key, commitment, value))
+        })?
+        .map(|row| {
+            let (key, commitment, value) = row?;
+            Ok(CachedItem {
+                value: value.unwrap_or_default(),
+                key: Felt::from_be_slice(&key).map_err(anyhow::Error::from)?,
+                commitment: Felt::from_be_slice(&commitment).map_err(anyhow::Error::from)?,
+            })
+        })
+        .collect::<Result<_, TrieCacheError>>()?;
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+    use crate::db::trie::TrieDB;
+    use crate::trie_cache::item::CachedItem;
+
+    #[test]
+    fn test_get_item_at_batch() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let storage = TrieDB::new(&conn);
+        // Leaves reference the batch that wrote them
+        test_ctx.batch_seeding();
+
+        let item = CachedItem::new(vec![1, 2, 3]);
+        storage.persist_leaves(&vec![item.clone()], 2).unwrap();
+
+        assert_eq!(get_item(&conn, &item.key, Some(1)).unwrap(), None);
+
+        let expected = Item {
+            key: hex::encode(item.key.to_be_bytes()),
+            value: Some("010203".to_string()),
+            commitment: hex::encode(item.commitment.to_be_bytes()),
+            batch_id: 2,
+        };
+        assert_eq!(get_item(&conn, &item.key, Some(2)).unwrap(), Some(expected));
+        assert_eq!(
+            get_item(&conn, &item.key, None).unwrap().unwrap().batch_id,
+            2
+        );
+    }
+}
@@ -0,0 +1,737 @@
+use pathfinder_crypto::hash::poseidon_hash_many;
+use pathfinder_crypto::{Felt, MontFelt};
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, OptionalExtension, Transaction, TransactionBehavior};
+use tracing::{info, warn};
+
+use crate::db::batch;
+use crate::errors::TrieCacheError;
+
+/// Flags databases whose new tries start out with the dummy genesis leaf, see `Trie::new`.
+pub const GENESIS_LEAF_FLAG: &str = "genesis_leaf";
+
+/// A change to the schema of the database.
+struct Migration {
+    /// The schema version the database is at once the migration is applied.
+    version: u64,
+    description: &'static str,
+    apply: fn(&PooledConnection<SqliteConnectionManager>) -> Result<(), TrieCacheError>,
+}
+
+/// The migrations of the schema, in the order they're applied.
+///
+/// Databases created before the schema version was tracked are at version 0, but may hold any of
+/// the changes up to `add_pruned_at`, so those only add what's missing. Released migrations must
+/// never change: new ones are appended with the next version.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create the trie_nodes, leaves and batches tables",
+        apply: create_tables,
+    },
+    Migration {
+        version: 2,
+        description: "Version leaves by the batch that wrote them",
+        apply: version_leaves,
+    },
+    Migration {
+        version: 3,
+        description: "Track root hashes on batches",
+        apply: add_root_hash,
+    },
+    Migration {
+        version: 4,
+        description: "Add batch metadata",
+        apply: add_batch_metadata,
+    },
+    Migration {
+        version: 5,
+        description: "Commit to the batch chain",
+        apply: add_batch_commitment,
+    },
+    Migration {
+        version: 6,
+        description: "Record the status history of batches",
+        apply: create_batch_status_history,
+    },
+    Migration {
+        version: 7,
+        description: "Store signed batch receipts",
+        apply: create_batch_receipts,
+    },
+    Migration {
+        version: 8,
+        description: "Mark pruned batches",
+        apply: add_pruned_at,
+    },
+    Migration {
+        version: 9,
+        description: "Index lookups and reference batches from leaves",
+        apply: constrain_leaves,
+    },
+    Migration {
+        version: 10,
+        description: "Start new tries empty",
+        apply: flag_genesis_leaf,
+    },
+    Migration {
+        version: 11,
+        description: "Allocate trie indices from a counter",
+        apply: create_node_allocator,
+    },
+    Migration {
+        version: 12,
+        description: "Require root hashes on batches",
+        apply: require_root_hash,
+    },
+];
+
+/// The schema version of this binary.
+pub const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Brings the schema of a database up to the version of this binary.
+///
+/// Each pending migration is applied in its own immediate transaction, together with the row
+/// recording it in `schema_version`, so a failed migration leaves the database at the version
+/// before it and a concurrent process never applies the same migration twice.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+///
+/// # Returns
+///
+/// The schema version of the database, or a TrieCacheError if an error occurs. Databases written
+/// by a newer binary are refused with `SchemaTooNew`, and left untouched.
+pub fn migrate(conn: &PooledConnection<SqliteConnectionManager>) -> Result<u64, TrieCacheError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    let version = check_schema_version(conn)?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+        // Another process may have migrated the database since its version was read
+        if check_schema_version(conn)? >= migration.version {
+            continue;
+        }
+
+        info!(
+            "Applying migration {}: {}",
+            migration.version, migration.description
+        );
+        (migration.apply)(conn)?;
+        conn.execute(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)",
+            params![
+                migration.version,
+                migration.description,
+                batch::unix_timestamp()
+            ],
+        )?;
+        tx.commit()?;
+    }
+
+    schema_version(conn)
+}
+
+/// Returns the schema version of a database, 0 if no migration was applied to it.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+pub fn schema_version(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<u64, TrieCacheError> {
+    Ok(conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+/// Returns whether a database carries a flag set by a migration.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `flag` - The name of the flag.
+pub fn has_flag(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    flag: &str,
+) -> Result<bool, TrieCacheError> {
+    Ok(conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM schema_flags WHERE name = ?)",
+        params![flag],
+        |row| row.get(0),
+    )?)
+}
+
+/// Sets a flag on a database, e.g. to carry it over into a rebuilt database.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `flag` - The name of the flag.
+pub fn set_flag(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    flag: &str,
+) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "INSERT OR IGNORE INTO schema_flags (name, set_at) VALUES (?, ?)",
+        params![flag, batch::unix_timestamp()],
+    )?;
+
+    Ok(())
+}
+
+/// Returns the schema version of a database, or `SchemaTooNew` if this binary doesn't know it.
+fn check_schema_version(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<u64, TrieCacheError> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(TrieCacheError::SchemaTooNew {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    Ok(version)
+}
+
+fn create_tables(conn: &PooledConnection<SqliteConnectionManager>) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS trie_nodes (
+            idx INTEGER PRIMARY KEY,
+            hash BLOB NOT NULL,
+            data BLOB,
+            trie_idx INTEGER UNIQUE NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS leaves (
+            idx INTEGER PRIMARY KEY,
+            key BLOB NOT NULL,
+            commitment BLOB NOT NULL,
+            value BLOB,
+            batch_id INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS batches (
+            id INTEGER PRIMARY KEY,
+            parent_id INTEGER,
+            status TEXT NOT NULL,
+            root_idx INTEGER NOT NULL,
+            FOREIGN KEY (parent_id) REFERENCES batches(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn version_leaves(conn: &PooledConnection<SqliteConnectionManager>) -> Result<(), TrieCacheError> {
+    // Older databases may contain duplicate (key, batch_id) rows, so only the most recent one is kept
+    conn.execute(
+        "DELETE FROM leaves WHERE idx NOT IN (
+            SELECT MAX(idx) FROM leaves GROUP BY key, batch_id
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS leaves_key_batch_id ON leaves (key, batch_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn add_root_hash(conn: &PooledConnection<SqliteConnectionManager>) -> Result<(), TrieCacheError> {
+    // Roots of existing batches are backfilled from the hash of the node at their root index
+    add_column_if_missing(conn, "batches", "root_hash", "BLOB")?;
+    conn.execute(
+        "UPDATE batches SET root_hash = (
+            SELECT hash FROM trie_nodes WHERE trie_nodes.trie_idx = batches.root_idx
+        ) WHERE root_hash IS NULL",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS batches_root_hash ON batches (root_hash)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn add_batch_metadata(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    // Pre-state roots and item counts of existing batches are derived from their parent and their
+    // leaves; node counts and timestamps are left unknown
+    add_column_if_missing(conn, "batches", "pre_root_idx", "INTEGER")?;
+    add_column_if_missing(conn, "batches", "pre_root", "BLOB")?;
+    add_column_if_missing(conn, "batches", "item_count", "INTEGER")?;
+    add_column_if_missing(conn, "batches", "nodes_added", "INTEGER")?;
+    add_column_if_missing(conn, "batches", "created_at", "INTEGER")?;
+    add_column_if_missing(conn, "batches", "finalized_at", "INTEGER")?;
+    add_column_if_missing(conn, "batches", "reverted_at", "INTEGER")?;
+    add_column_if_missing(conn, "batches", "group_id", "INTEGER")?;
+    conn.execute(
+        "UPDATE batches SET pre_root_idx = COALESCE(
+            (SELECT parent.root_idx FROM batches AS parent WHERE parent.id = batches.parent_id),
+            1
+        ) WHERE pre_root_idx IS NULL",
+        [],
+    )?;
+    conn.execute(
+        "UPDATE batches SET pre_root = COALESCE(
+            (SELECT hash FROM trie_nodes WHERE trie_nodes.trie_idx = batches.pre_root_idx),
+            zeroblob(32)
+        ) WHERE pre_root IS NULL",
+        [],
+    )?;
+    conn.execute(
+        "UPDATE batches SET item_count = (
+            SELECT COUNT(*) FROM leaves WHERE leaves.batch_id = batches.id
+        ) WHERE item_count IS NULL",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS batches_group_id ON batches (group_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn add_batch_commitment(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    // Existing batches are linked from genesis on, in ID order so that the commitment of a parent
+    // is stored before the ones of its children. The commitment is computed here rather than with
+    // `models::batch::batch_commitment`, so that this migration doesn't change along with it.
+    add_column_if_missing(conn, "batches", "batch_commitment", "BLOB")?;
+    let mut stmt = conn.prepare(
+        "SELECT id, parent_id, pre_root, root_hash, item_count FROM batches
+        WHERE batch_commitment IS NULL ORDER BY id ASC",
+    )?;
+    let batches = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, Option<u64>>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+                row.get::<_, u64>(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let felt = |bytes: &[u8]| Felt::from_be_slice(bytes).map_err(anyhow::Error::from);
+    for (id, parent_id, pre_root, root_hash, item_count) in batches {
+        let parent_commitment = match parent_id {
+            Some(parent_id) => {
+                let commitment: Option<Vec<u8>> = conn
+                    .query_row(
+                        "SELECT batch_commitment FROM batches WHERE id = ?",
+                        params![parent_id],
+                        |row| row.get(0),
+                    )
+                    .optional()?
+                    .flatten();
+                felt(&commitment.ok_or(TrieCacheError::BatchNotFound)?)?
+            }
+            None => Felt::ZERO,
+        };
+        let felts = [
+            parent_commitment,
+            Felt::from_u64(id),
+            felt(&pre_root)?,
+            felt(&root_hash)?,
+            Felt::from_u64(item_count),
+        ];
+        let commitment: Felt = poseidon_hash_many(&felts.map(MontFelt::from)).into();
+        conn.execute(
+            "UPDATE batches SET batch_commitment = ? WHERE id = ?",
+            params![commitment.to_be_bytes().to_vec(), id],
+        )?;
+        warn!("Backfilled the missing commitment of batch {}", id);
+    }
+
+    Ok(())
+}
+
+fn create_batch_status_history(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS batch_status_history (
+            idx INTEGER PRIMARY KEY,
+            batch_id INTEGER NOT NULL,
+            status TEXT NOT NULL,
+            changed_at INTEGER NOT NULL,
+            FOREIGN KEY (batch_id) REFERENCES batches(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS batch_status_history_batch_id
+            ON batch_status_history (batch_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn create_batch_receipts(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS batch_receipts (
+            idx INTEGER PRIMARY KEY,
+            batch_id INTEGER NOT NULL,
+            parent_id INTEGER,
+            status TEXT NOT NULL,
+            pre_root BLOB NOT NULL,
+            post_root BLOB NOT NULL,
+            public_key BLOB NOT NULL,
+            signature_r BLOB NOT NULL,
+            signature_s BLOB NOT NULL,
+            FOREIGN KEY (batch_id) REFERENCES batches(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS batch_receipts_batch_id ON batch_receipts (batch_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn add_pruned_at(conn: &PooledConnection<SqliteConnectionManager>) -> Result<(), TrieCacheError> {
+    add_column_if_missing(conn, "batches", "pruned_at", "INTEGER")
+}
+
+fn constrain_leaves(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    // Leaves written by batches that were never created can't be read at any batch and would
+    // violate the foreign key, so they're moved to the orphan_leaves table for inspection
+    conn.execute(
+        "CREATE TABLE orphan_leaves (
+            idx INTEGER PRIMARY KEY,
+            key BLOB NOT NULL,
+            commitment BLOB NOT NULL,
+            value BLOB,
+            batch_id INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    let orphans = conn.execute(
+        "INSERT INTO orphan_leaves (idx, key, commitment, value, batch_id)
+            SELECT idx, key, commitment, value, batch_id FROM leaves
+            WHERE batch_id != 0 AND batch_id NOT IN (SELECT id FROM batches)",
+        [],
+    )?;
+    if orphans > 0 {
+        conn.execute(
+            "DELETE FROM leaves WHERE idx IN (SELECT idx FROM orphan_leaves)",
+            [],
+        )?;
+        warn!(
+            "Moved {} leaves of unknown batches to the orphan_leaves table",
+            orphans
+        );
+    }
+
+    // SQLite can't add a foreign key to an existing table, so the table is rebuilt. Leaves are
+    // written before their batch in the same transaction, so the key is only checked on commit,
+    // and the genesis leaf, which belongs to no batch, moves from batch 0 to NULL.
+    conn.execute(
+        "CREATE TABLE leaves_new (
+            idx INTEGER PRIMARY KEY,
+            key BLOB NOT NULL,
+            commitment BLOB NOT NULL,
+            value BLOB,
+            batch_id INTEGER REFERENCES batches(id) DEFERRABLE INITIALLY DEFERRED
+        )",
+        [],
+    )?;
+    conn.execute(
+        "INSERT INTO leaves_new (idx, key, commitment, value, batch_id)
+            SELECT idx, key, commitment, value, NULLIF(batch_id, 0) FROM leaves",
+        [],
+    )?;
+    conn.execute("DROP TABLE leaves", [])?;
+    conn.execute("ALTER TABLE leaves_new RENAME TO leaves", [])?;
+
+    // Lookups by key are served by the (key, batch_id) index, of which key is the first column
+    conn.execute(
+        "CREATE UNIQUE INDEX leaves_key_batch_id ON leaves (key, batch_id)",
+        [],
+    )?;
+    // NULLs are distinct in unique indexes, so the genesis leaf is kept unique separately
+    conn.execute(
+        "CREATE UNIQUE INDEX leaves_genesis_key ON leaves (key) WHERE batch_id IS NULL",
+        [],
+    )?;
+    conn.execute("CREATE INDEX leaves_batch_id ON leaves (batch_id)", [])?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS batches_status ON batches (status)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS trie_nodes_hash ON trie_nodes (hash)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn flag_genesis_leaf(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "CREATE TABLE schema_flags (
+            name TEXT PRIMARY KEY,
+            set_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Tries of existing databases were started with the genesis leaf, which new tries keep
+    // writing so rebuilt and new genesis tries have the same roots as before
+    let has_nodes: bool =
+        conn.query_row("SELECT EXISTS (SELECT 1 FROM trie_nodes)", [], |row| {
+            row.get(0)
+        })?;
+    if has_nodes {
+        set_flag(conn, GENESIS_LEAF_FLAG)?;
+    }
+
+    Ok(())
+}
+
+fn create_node_allocator(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    conn.execute(
+        "CREATE TABLE trie_node_allocator (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            next_idx INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    // Allocation continues after the highest stored index, where it used to be derived from
+    conn.execute(
+        "INSERT INTO trie_node_allocator (id, next_idx)
+            SELECT 0, IFNULL(MAX(trie_idx), 0) + 1 FROM trie_nodes",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn require_root_hash(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(), TrieCacheError> {
+    // Root hashes were backfilled from the node at the root index, which older databases may lack
+    let mut stmt = conn.prepare("SELECT id FROM batches WHERE root_hash IS NULL ORDER BY id")?;
+    let unknown = stmt
+        .query_map([], |row| row.get::<_, u64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if !unknown.is_empty() {
+        return Err(TrieCacheError::UnknownRootHash(unknown));
+    }
+
+    // SQLite can't add NOT NULL to an existing column, and batches can't be rebuilt while other
+    // tables reference them, so the constraint is enforced by triggers
+    conn.execute_batch(
+        "CREATE TRIGGER batches_root_hash_insert BEFORE INSERT ON batches
+            WHEN NEW.root_hash IS NULL
+            BEGIN SELECT RAISE(ABORT, 'NOT NULL constraint failed: batches.root_hash'); END;
+        CREATE TRIGGER batches_root_hash_update BEFORE UPDATE OF root_hash ON batches
+            WHEN NEW.root_hash IS NULL
+            BEGIN SELECT RAISE(ABORT, 'NOT NULL constraint failed: batches.root_hash'); END;",
+    )?;
+
+    Ok(())
+}
+
+/// Adds a column to an existing table, unless the table already has it.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `table` - The table to alter.
+/// * `column` - The name of the column to add.
+/// * `definition` - The type and constraints of the column.
+fn add_column_if_missing(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), TrieCacheError> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !columns.iter().any(|name| name == column) {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+    use crate::db::trie::LEAF_QUERY;
+    use crate::db::ConnectionManager;
+    use crate::models::batch::BatchStatus;
+    use crate::trie_cache::chain;
+    use rand::random;
+
+    /// A database written by the last version without schema versions, with a duplicate leaf.
+    const V0_FIXTURE: &str = include_str!("../../fixtures/schema_v0.sql");
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for (idx, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, idx as u64 + 1);
+        }
+    }
+
+    #[test]
+    fn test_migrate_from_v0() {
+        let file = format!("{:?}_schema_v0.db", random::<u32>());
+        let manager = ConnectionManager::new(&file);
+        let conn = manager.get_connection().unwrap();
+        conn.execute_batch(V0_FIXTURE).unwrap();
+
+        assert_eq!(migrate(&conn).unwrap(), SCHEMA_VERSION);
+
+        // The duplicate leaf is dropped, the leaf of the unknown batch is quarantined and the
+        // batches are backfilled
+        let leaves: u64 = conn
+            .query_row("SELECT COUNT(*) FROM leaves", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(leaves, 4);
+        let orphan: (u64, Vec<u8>) = conn
+            .query_row("SELECT batch_id, value FROM orphan_leaves", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(orphan, (3, vec![0x0e]));
+        let genesis_leaves: u64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM leaves WHERE batch_id IS NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(genesis_leaves, 1);
+        assert!(has_flag(&conn, GENESIS_LEAF_FLAG).unwrap());
+        let genesis = batch::get_batch(&conn, 1).unwrap();
+        let child = batch::get_batch(&conn, 2).unwrap();
+        assert_eq!(genesis.status, BatchStatus::Finalized);
+        assert_eq!(genesis.item_count, 2);
+        assert_eq!(child.pre_root_idx, genesis.root_idx);
+        assert_eq!(child.pre_root, genesis.root_hash);
+        assert_eq!(child.item_count, 1);
+        assert_eq!(chain::verify_chain(&conn).unwrap().first_break, None);
+
+        // Root hashes can't be cleared, and a database that lacks some is refused
+        assert!(conn
+            .execute("UPDATE batches SET root_hash = NULL WHERE id = 2", [])
+            .is_err());
+        conn.execute_batch(
+            "DROP TRIGGER batches_root_hash_insert;
+            DROP TRIGGER batches_root_hash_update;
+            UPDATE batches SET root_hash = NULL WHERE id = 2;",
+        )
+        .unwrap();
+        assert!(matches!(
+            require_root_hash(&conn),
+            Err(TrieCacheError::UnknownRootHash(ids)) if ids == vec![2]
+        ));
+        conn.execute(
+            "UPDATE batches SET root_hash = ? WHERE id = 2",
+            params![hex::decode(&child.root_hash).unwrap()],
+        )
+        .unwrap();
+        require_root_hash(&conn).unwrap();
+
+        // Migrating again changes nothing
+        assert_eq!(migrate(&conn).unwrap(), SCHEMA_VERSION);
+        let applied: u64 = conn
+            .query_row("SELECT COUNT(*) FROM schema_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(applied, SCHEMA_VERSION);
+
+        // A database migrated by a newer binary is refused
+        conn.execute(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)",
+            params![SCHEMA_VERSION + 1, "From the future", 0],
+        )
+        .unwrap();
+        assert!(matches!(
+            migrate(&conn),
+            Err(TrieCacheError::SchemaTooNew { found, supported })
+                if found == SCHEMA_VERSION + 1 && supported == SCHEMA_VERSION
+        ));
+
+        drop(conn);
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn test_lookups_use_indexes() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let reverted = BatchStatus::Reverted.to_string();
+        for (query, query_params) in [
+            (LEAF_QUERY, params![vec![1u8], 1, reverted]),
+            ("SELECT key FROM leaves WHERE batch_id = 1", params![]),
+            (
+                "SELECT id FROM batches WHERE status = 'created' ORDER BY id DESC LIMIT 1",
+                params![],
+            ),
+            (
+                "SELECT trie_idx FROM trie_nodes WHERE hash = x'01'",
+                params![],
+            ),
+        ] {
+            let mut stmt = conn
+                .prepare(&format!("EXPLAIN QUERY PLAN {}", query))
+                .unwrap();
+            let plan = stmt
+                .query_map(query_params, |row| row.get::<_, String>(3))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert!(
+                plan.iter().all(|step| !step.starts_with("SCAN")),
+                "{} is planned as {:?}",
+                query,
+                plan
+            );
+        }
+    }
+}
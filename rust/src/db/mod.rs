@@ -1,27 +1,72 @@
 use std::sync::Arc;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 pub mod batch;
+pub mod item;
+pub mod migration;
+pub mod receipt;
+pub mod root;
 pub mod trie;
 
 use crate::errors::TrieCacheError;
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OpenFlags;
 
 #[derive(Debug)]
 pub struct ConnectionManager {
     pool: Arc<Pool<SqliteConnectionManager>>,
+    /// Held shared while a batch is created, and exclusively while batch creation is paused.
+    batch_gate: RwLock<()>,
 }
 
 impl ConnectionManager {
     /// Creates a new ConnectionManager with a connection pool to the specified database file.
+    ///
+    /// Foreign keys are enforced on every connection of the pool.
     pub fn new(file: &str) -> Self {
-        let manager = SqliteConnectionManager::file(file);
+        let manager = SqliteConnectionManager::file(file)
+            .with_init(|conn| conn.execute_batch("PRAGMA foreign_keys = ON;"));
         let pool = Pool::new(manager).unwrap();
         ConnectionManager {
             pool: Arc::new(pool),
+            batch_gate: RwLock::new(()),
         }
     }
 
+    /// Creates a new ConnectionManager with a connection pool to the specified database file,
+    /// opened read-only.
+    ///
+    /// The file must exist, and nothing can be written to it, so the database isn't migrated either.
+    pub fn read_only(file: &str) -> Self {
+        let manager = SqliteConnectionManager::file(file).with_flags(
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        );
+        let pool = Pool::new(manager).unwrap();
+        ConnectionManager {
+            pool: Arc::new(pool),
+            batch_gate: RwLock::new(()),
+        }
+    }
+
+    /// Allows batches to be created, change status or be pruned until the returned guard is dropped.
+    ///
+    /// Waits while batch writes are paused. Must be awaited before a connection is taken from the
+    /// pool, so paused writes don't hold connections the pause needs.
+    pub async fn batch_write(&self) -> RwLockReadGuard<'_, ()> {
+        self.batch_gate.read().await
+    }
+
+    /// Pauses batch writes until the returned guard is dropped.
+    ///
+    /// Blocks until the batch writes in progress are persisted, so the database is left at a batch boundary.
+    /// Must be called outside of the async runtime, e.g. in `spawn_blocking`.
+    pub fn pause_batch_writes(&self) -> RwLockWriteGuard<'_, ()> {
+        self.batch_gate.blocking_write()
+    }
+
     /// Gets a connection from the pool.
     pub fn get_connection(
         &self,
@@ -29,40 +74,13 @@ impl ConnectionManager {
         Ok(self.pool.get()?)
     }
 
-    pub fn create_table(&self) -> Result<(), TrieCacheError> {
-        self.get_connection()?.execute(
-            "CREATE TABLE IF NOT EXISTS trie_nodes (
-                idx INTEGER PRIMARY KEY,
-                hash BLOB NOT NULL,
-                data BLOB,
-                trie_idx INTEGER UNIQUE NOT NULL
-            )",
-            [],
-        )?;
-
-        self.get_connection()?.execute(
-            "CREATE TABLE IF NOT EXISTS leaves (
-                idx INTEGER PRIMARY KEY,
-                key BLOB NOT NULL,
-                commitment BLOB NOT NULL,
-                value BLOB,
-                batch_id INTEGER NOT NULL
-            )",
-            [],
-        )?;
-
-        self.get_connection()?.execute(
-            "CREATE TABLE IF NOT EXISTS batches (
-                id INTEGER PRIMARY KEY,
-                parent_id INTEGER,
-                status TEXT NOT NULL,
-                root_idx INTEGER NOT NULL,
-                FOREIGN KEY (parent_id) REFERENCES batches(id)
-            )",
-            [],
-        )?;
-
-        Ok(())
+    /// Brings the schema of the database up to the version of this binary, see `migration::migrate`.
+    ///
+    /// # Returns
+    ///
+    /// The schema version of the database, or a TrieCacheError if an error occurs.
+    pub fn migrate(&self) -> Result<u64, TrieCacheError> {
+        migration::migrate(&self.get_connection()?)
     }
 }
 
@@ -70,12 +88,13 @@ impl ConnectionManager {
 pub(crate) mod test {
     use crate::{
         db::ConnectionManager,
-        models::batch::{Batch, BatchStatus},
+        models::batch::{Batch, BatchStatus, NewBatch},
     };
+    use pathfinder_crypto::Felt;
     use rand::random;
     use std::{path::Path, sync::Arc};
 
-    use super::batch::{create_batch, update_batch_status};
+    use super::batch::{create_batch, get_batch, update_batch_status};
 
     pub struct TestContext {
         pub(crate) manager: Arc<ConnectionManager>,
@@ -87,7 +106,7 @@ pub(crate) mod test {
             let rand = random::<u32>();
             let file = format!("{:?}_{}", rand, "test.db");
             let manager = Arc::new(ConnectionManager::new(file.as_str()));
-            manager.create_table().unwrap();
+            manager.migrate().unwrap();
 
             TestContext {
                 manager,
@@ -97,31 +116,23 @@ pub(crate) mod test {
 
         pub fn batch_seeding(&self) -> Vec<Batch> {
             let conn = self.manager.get_connection().unwrap();
-            create_batch(&conn, None, 1).unwrap();
-            create_batch(&conn, Some(1), 7).unwrap();
-            create_batch(&conn, Some(2), 16).unwrap();
+            let roots = [(None, 1, 0), (Some(1), 7, 1), (Some(2), 16, 7)];
+            for (parent_id, root_idx, pre_root_idx) in roots {
+                let new_batch = NewBatch {
+                    parent_id,
+                    root_idx,
+                    root_hash: Felt::from_u64(root_idx),
+                    pre_root_idx,
+                    pre_root: Felt::from_u64(pre_root_idx),
+                    item_count: 1,
+                    nodes_added: 1,
+                    group_id: None,
+                };
+                create_batch(&conn, &new_batch).unwrap();
+            }
             update_batch_status(&conn, &1u64, BatchStatus::Finalized).unwrap();
 
-            vec![
-                Batch {
-                    id: 1,
-                    parent_id: None,
-                    status: BatchStatus::Finalized,
-                    root_idx: 1,
-                },
-                Batch {
-                    id: 2,
-                    parent_id: Some(1),
-                    status: BatchStatus::Created,
-                    root_idx: 7,
-                },
-                Batch {
-                    id: 3,
-                    parent_id: Some(2),
-                    status: BatchStatus::Created,
-                    root_idx: 16,
-                },
-            ]
+            (1..=3).map(|id| get_batch(&conn, id).unwrap()).collect()
         }
     }
 
@@ -0,0 +1,66 @@
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::params;
+
+use crate::errors::TrieCacheError;
+use crate::models::receipt::Receipt;
+
+/// Stores a signed receipt of a batch.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `receipt` - The receipt to store.
+///
+/// # Returns
+///
+/// A `Result` indicating success or a `TrieCacheError` if an error occurs.
+pub fn insert_receipt(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    receipt: &Receipt,
+) -> Result<(), TrieCacheError> {
+    let decode = |value: &str| hex::decode(value).map_err(|_| TrieCacheError::InvalidHexString);
+    conn.execute(
+        "INSERT INTO batch_receipts (
+            batch_id, parent_id, status, pre_root, post_root, public_key, signature_r, signature_s
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            receipt.batch_id,
+            receipt.parent_id,
+            receipt.status.to_string(),
+            decode(&receipt.pre_root)?,
+            decode(&receipt.post_root)?,
+            decode(&receipt.public_key)?,
+            decode(&receipt.signature_r)?,
+            decode(&receipt.signature_s)?,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Retrieves the receipts of a batch, in the order they were signed.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `batch_id` - The ID of the batch.
+///
+/// # Returns
+///
+/// A `Result` containing a vector of `Receipt` objects or a `TrieCacheError` if an error occurs.
+pub fn get_receipts(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    batch_id: u64,
+) -> Result<Vec<Receipt>, TrieCacheError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT batch_id, parent_id, status, pre_root, post_root, public_key, signature_r, signature_s
+        FROM batch_receipts WHERE batch_id = ? ORDER BY idx ASC",
+    )?;
+
+    let receipts: Vec<Receipt> = stmt
+        .query_map(params![batch_id], |row| Receipt::try_from(row))?
+        .collect::<Result<_, _>>()?;
+
+    Ok(receipts)
+}
@@ -0,0 +1,130 @@
+use pathfinder_crypto::Felt;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, OptionalExtension};
+
+use crate::errors::TrieCacheError;
+use crate::models::page::Page;
+use crate::models::root::Root;
+
+/// Retrieves a page of roots, ordered by the batch that produced them.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `cursor` - Only roots of batches with an ID greater than the cursor are returned.
+/// * `limit` - The maximum number of roots to return.
+///
+/// # Returns
+///
+/// A `Result` containing a `Page` of `Root` objects or a `TrieCacheError` if an error occurs.
+pub fn get_roots(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    cursor: Option<u64>,
+    limit: u64,
+) -> Result<Page<Root>, TrieCacheError> {
+    // Prepare the SQL statement
+    let mut stmt = conn.prepare_cached(
+        "SELECT root_hash, id, root_idx, status, pruned_at FROM batches
+        WHERE id > ? ORDER BY id ASC LIMIT ?",
+    )?;
+
+    // Fetch one extra row to find out whether another page follows
+    let roots: Vec<Root> = stmt
+        .query_map(params![cursor.unwrap_or(0), limit + 1], |row| {
+            Root::try_from(row)
+        })?
+        .collect::<Result<_, _>>()?;
+
+    Ok(Page::new(roots, limit, |root| root.batch_id))
+}
+
+/// Retrieves a root by its hash.
+///
+/// If several batches share the same root, the earliest one that isn't pruned is returned, or the
+/// earliest one if they all are.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `root_hash` - The hash of the root to retrieve.
+///
+/// # Returns
+///
+/// A `Result` containing an `Option` of the retrieved `Root` or a `TrieCacheError` if an error occurs.
+pub fn get_root(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    root_hash: &Felt,
+) -> Result<Option<Root>, TrieCacheError> {
+    // Prepare the SQL statement
+    let mut stmt = conn.prepare_cached(
+        "SELECT root_hash, id, root_idx, status, pruned_at FROM batches
+        WHERE root_hash = ? ORDER BY pruned_at IS NOT NULL, id ASC LIMIT 1",
+    )?;
+
+    Ok(stmt
+        .query_row(params![root_hash.to_be_bytes().to_vec()], |row| {
+            Root::try_from(row)
+        })
+        .optional()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+    use crate::models::batch::BatchStatus;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+
+    #[test]
+    fn test_root_lookups() {
+        let test_ctx = db::test::TestContext::new();
+        let batches = test_ctx.batch_seeding();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let page = get_roots(&conn, None, 2).unwrap();
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.items[0].root_hash, batches[0].root_hash);
+        assert_eq!(page.items[0].status, BatchStatus::Finalized);
+        assert_eq!(page.next_cursor, Some(2));
+
+        let page = get_roots(&conn, page.next_cursor, 2).unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].batch_id, 3);
+        assert_eq!(page.next_cursor, None);
+
+        let root = get_root(&conn, &Felt::from_u64(7)).unwrap().unwrap();
+        assert_eq!(root.batch_id, 2);
+        assert_eq!(root.root_idx, 7);
+
+        assert_eq!(get_root(&conn, &Felt::from_u64(8)).unwrap(), None);
+
+        // Pruned roots are listed with their pruning time
+        db::batch::mark_pruned(&conn, 1).unwrap();
+        let page = get_roots(&conn, None, 3).unwrap();
+        assert!(page.items[0].pruned_at.is_some());
+        assert!(page.items[1..].iter().all(|root| root.pruned_at.is_none()));
+        let root = get_root(&conn, &Felt::from_hex_str(&batches[0].root_hash).unwrap()).unwrap();
+        assert!(root.unwrap().pruned_at.is_some());
+    }
+
+    #[test]
+    fn test_root_lookup_prefers_unpruned_batches() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let item = CachedItem::new(vec![0, 1]);
+        TrieCache::create_batch(&conn, vec![item.clone()], None, None).unwrap();
+        // Writing the same value again leaves the root unchanged
+        TrieCache::create_batch(&conn, vec![item], None, None).unwrap();
+        let root_hash = db::batch::get_batch(&conn, 1).unwrap().root_hash;
+        assert_eq!(db::batch::get_batch(&conn, 2).unwrap().root_hash, root_hash);
+
+        db::batch::mark_pruned(&conn, 1).unwrap();
+        let root = get_root(&conn, &Felt::from_hex_str(&root_hash).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(root.batch_id, 2);
+        assert_eq!(root.pruned_at, None);
+    }
+}
@@ -7,14 +7,29 @@ use pathfinder_storage::StoredNode;
 use r2d2::PooledConnection;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{params, OptionalExtension};
+use std::collections::HashSet;
 
 use crate::errors::TrieCacheError;
+use crate::models::batch::BatchStatus;
 use crate::trie_cache::item::CachedItem;
 
+/// Selects the version of a leaf visible at a batch: the most recent one written by a batch that is
+/// not reverted, up to the batch ID bound to `?2`. Genesis leaves, stored without a batch ID, are
+/// always visible.
+///
+/// Binds the key as `?1` and the reverted status as `?3`, and returns the key, value, commitment
+/// and batch ID (0 for genesis leaves) of the leaf.
+pub const LEAF_QUERY: &str = "SELECT key, value, commitment, IFNULL(batch_id, 0) FROM leaves
+    WHERE key = ?1 AND (batch_id IS NULL OR (batch_id <= ?2
+        AND batch_id NOT IN (SELECT id FROM batches WHERE status = ?3)))
+    ORDER BY batch_id DESC LIMIT 1";
+
 /// Represents a Trie database.
 #[derive(Debug, Clone, Copy)]
 pub struct TrieDB<'a> {
     conn: &'a PooledConnection<SqliteConnectionManager>,
+    /// The batch at which leaves are read, or `None` for the latest versions.
+    batch_id: Option<u64>,
 }
 
 impl<'a> TrieDB<'a> {
@@ -24,11 +39,31 @@ impl<'a> TrieDB<'a> {
     ///
     /// * `conn` - A reference to a pooled SQLite connection.
     pub fn new(conn: &'a PooledConnection<SqliteConnectionManager>) -> Self {
-        Self { conn }
+        Self {
+            conn,
+            batch_id: None,
+        }
+    }
+
+    /// Creates a new instance of `TrieDB` that reads leaves as they were at a specific batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - A reference to a pooled SQLite connection.
+    /// * `batch_id` - The batch at which leaves are read. Leaves written by later batches are ignored.
+    pub fn at_batch(conn: &'a PooledConnection<SqliteConnectionManager>, batch_id: u64) -> Self {
+        Self {
+            conn,
+            batch_id: Some(batch_id),
+        }
     }
 
     /// Persists the leaves in the database.
     ///
+    /// A leaf written twice within the same batch keeps its last value. Leaves of batch 0, the
+    /// genesis leaf written when a trie is initialized, belong to no batch and are stored with a
+    /// NULL batch ID.
+    ///
     /// # Arguments
     ///
     /// * `leaves` - A vector of `CachedItem` representing the leaves to be persisted.
@@ -43,7 +78,7 @@ impl<'a> TrieDB<'a> {
         batch_id: u64,
     ) -> Result<(), TrieCacheError> {
         const INSERT_QUERY: &str =
-            "INSERT INTO leaves (key, commitment, value, batch_id) VALUES (?1, ?2, ?3, ?4)";
+            "INSERT OR REPLACE INTO leaves (key, commitment, value, batch_id) VALUES (?1, ?2, ?3, ?4)";
 
         for item in leaves {
             self.conn
@@ -53,7 +88,7 @@ impl<'a> TrieDB<'a> {
                         item.key.to_be_bytes().to_vec(),
                         item.commitment.to_be_bytes().to_vec(),
                         item.value,
-                        &batch_id
+                        (batch_id != 0).then_some(batch_id)
                     ],
                 )
                 .map_err(TrieCacheError::from)?;
@@ -111,6 +146,33 @@ impl<'a> TrieDB<'a> {
 
         Ok(trie_idx.map_or(0, |idx| idx))
     }
+
+    /// Allocates a contiguous range of trie indices for new nodes.
+    ///
+    /// Indices are handed out by the counter in `trie_node_allocator` rather than derived from
+    /// `MAX(trie_idx)`, so they are never reused, even after the latest nodes were pruned, and
+    /// don't depend on which root a batch was built on. The counter is bumped before it's read,
+    /// so the allocation holds the write lock of the enclosing transaction and is rolled back
+    /// with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The number of indices to allocate.
+    ///
+    /// # Returns
+    ///
+    /// The first allocated index, or a `TrieCacheError` if the counter couldn't be updated.
+    pub fn allocate_node_indices(&self, count: u64) -> Result<u64, TrieCacheError> {
+        self.conn
+            .prepare_cached("UPDATE trie_node_allocator SET next_idx = next_idx + ?1")?
+            .execute(params![count])?;
+        let next_idx: u64 = self
+            .conn
+            .prepare_cached("SELECT next_idx FROM trie_node_allocator")?
+            .query_row([], |row| row.get(0))?;
+
+        Ok(next_idx - count)
+    }
 }
 
 impl Storage for TrieDB<'_> {
@@ -169,6 +231,9 @@ impl Storage for TrieDB<'_> {
 
     /// Retrieves the leaf value associated with the specified path from the trie database.
     ///
+    /// The most recent version of the leaf is returned, up to the batch of the `TrieDB` if one is set.
+    /// Leaves written by reverted batches are ignored.
+    ///
     /// # Arguments
     ///
     /// * `path` - The path of the leaf to retrieve.
@@ -180,13 +245,17 @@ impl Storage for TrieDB<'_> {
     fn leaf(&self, path: &BitSlice<u8, Msb0>) -> anyhow::Result<Option<Felt>> {
         let mut stmt = self
             .conn
-            .prepare_cached("SELECT commitment FROM leaves WHERE key = ?")
+            .prepare_cached(LEAF_QUERY)
             .context("Creating get statement")?;
 
         let Some(data): Option<Vec<u8>> = stmt
             .query_row(
-                params![Felt::from_bits(path)?.to_be_bytes().to_vec()],
-                |row| row.get(0),
+                params![
+                    Felt::from_bits(path)?.to_be_bytes().to_vec(),
+                    self.batch_id.map_or(i64::MAX, |id| id as i64),
+                    BatchStatus::Reverted.to_string()
+                ],
+                |row| row.get(2),
             )
             .optional()?
         else {
@@ -196,3 +265,279 @@ impl Storage for TrieDB<'_> {
         Ok(Some(Felt::from_be_slice(&data)?))
     }
 }
+
+/// Retrieves the root indices the live trie is read from: the pre- and post-state roots of every
+/// batch that is neither reverted nor pruned.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+///
+/// # Returns
+///
+/// A `Result` containing the root indices or a `TrieCacheError` if an error occurs.
+pub fn get_live_root_idxs(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<Vec<u64>, TrieCacheError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT root_idx FROM batches WHERE status != ?1 AND pruned_at IS NULL
+        UNION SELECT pre_root_idx FROM batches WHERE status != ?1 AND pruned_at IS NULL",
+    )?;
+
+    let root_idxs: Vec<u64> = stmt
+        .query_map(params![BatchStatus::Reverted.to_string()], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+
+    Ok(root_idxs)
+}
+
+/// Deletes every trie node whose index isn't marked as reachable.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+/// * `marked` - The indices of the nodes to keep.
+///
+/// # Returns
+///
+/// A `Result` containing the number of deleted nodes and their size in bytes, or a `TrieCacheError` if an error occurs.
+pub fn delete_unmarked_nodes(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    marked: &HashSet<u64>,
+) -> Result<(u64, u64), TrieCacheError> {
+    // The marked set can be large, so it's joined against through a temporary table
+    conn.execute(
+        "CREATE TEMP TABLE IF NOT EXISTS gc_marked (trie_idx INTEGER PRIMARY KEY)",
+        [],
+    )?;
+    conn.execute("DELETE FROM gc_marked", [])?;
+    {
+        let mut stmt = conn.prepare_cached("INSERT INTO gc_marked (trie_idx) VALUES (?)")?;
+        for trie_idx in marked {
+            stmt.execute(params![trie_idx])?;
+        }
+    }
+
+    let (count, bytes): (u64, Option<u64>) = conn.query_row(
+        "SELECT COUNT(*), SUM(LENGTH(hash) + IFNULL(LENGTH(data), 0)) FROM trie_nodes
+        WHERE trie_idx NOT IN (SELECT trie_idx FROM gc_marked)",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    conn.execute(
+        "DELETE FROM trie_nodes WHERE trie_idx NOT IN (SELECT trie_idx FROM gc_marked)",
+        [],
+    )?;
+    conn.execute("DROP TABLE gc_marked", [])?;
+
+    Ok((count, bytes.unwrap_or(0)))
+}
+
+/// Deletes the leaves written by reverted batches.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+///
+/// # Returns
+///
+/// A `Result` containing the number of deleted leaves and their size in bytes, or a `TrieCacheError` if an error occurs.
+pub fn delete_reverted_leaves(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(u64, u64), TrieCacheError> {
+    const REVERTED: &str = "batch_id IN (SELECT id FROM batches WHERE status = ?)";
+    let status = BatchStatus::Reverted.to_string();
+
+    let (count, bytes): (u64, Option<u64>) = conn.query_row(
+        &format!(
+            "SELECT COUNT(*), SUM(LENGTH(key) + LENGTH(commitment) + IFNULL(LENGTH(value), 0))
+            FROM leaves WHERE {}",
+            REVERTED
+        ),
+        params![status],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    conn.execute(
+        &format!("DELETE FROM leaves WHERE {}", REVERTED),
+        params![status],
+    )?;
+
+    Ok((count, bytes.unwrap_or(0)))
+}
+
+/// Deletes the leaf versions written by pruned batches that no retained batch can read anymore.
+///
+/// Leaves are read at a batch as the most recent version written up to it. Every retained batch
+/// reads at its own ID or, for its pre-state, at its parent's ID, so a version is unreadable once a
+/// newer version of the same key was written at or below the lowest of these read points.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+///
+/// # Returns
+///
+/// A `Result` containing the number of deleted leaves and their size in bytes, or a `TrieCacheError` if an error occurs.
+pub fn delete_superseded_leaves(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<(u64, u64), TrieCacheError> {
+    const SUPERSEDED: &str = "batch_id IN (SELECT id FROM batches WHERE pruned_at IS NOT NULL)
+        AND EXISTS (
+            SELECT 1 FROM leaves AS newer
+            WHERE newer.key = leaves.key AND newer.batch_id > leaves.batch_id
+                AND newer.batch_id <= ?1
+                AND newer.batch_id NOT IN (SELECT id FROM batches WHERE status = ?2)
+        )";
+    let status = BatchStatus::Reverted.to_string();
+
+    // Without retained batches, only the latest versions are kept
+    let floor: u64 = conn.query_row(
+        "SELECT COALESCE(
+            (SELECT MIN(IFNULL(parent_id, 0)) FROM batches WHERE status != ?1 AND pruned_at IS NULL),
+            (SELECT MAX(id) FROM batches),
+            0
+        )",
+        params![status],
+        |row| row.get(0),
+    )?;
+
+    let (count, bytes): (u64, Option<u64>) = conn.query_row(
+        &format!(
+            "SELECT COUNT(*), SUM(LENGTH(key) + LENGTH(commitment) + IFNULL(LENGTH(value), 0))
+            FROM leaves WHERE {}",
+            SUPERSEDED
+        ),
+        params![floor, status],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    conn.execute(
+        &format!("DELETE FROM leaves WHERE {}", SUPERSEDED),
+        params![floor, status],
+    )?;
+
+    Ok((count, bytes.unwrap_or(0)))
+}
+
+/// Retrieves every stored leaf version that a batch which is neither reverted nor pruned wrote.
+///
+/// The genesis leaf is returned as written by batch 0.
+///
+/// # Arguments
+///
+/// * `conn` - A pooled connection to the SQLite database.
+///
+/// # Returns
+///
+/// A `Result` containing the key, commitment and batch ID of every leaf version, ordered by batch,
+/// or a `TrieCacheError` if an error occurs.
+pub fn get_live_leaves(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<Vec<(Felt, Felt, u64)>, TrieCacheError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT key, commitment, IFNULL(batch_id, 0) FROM leaves
+        WHERE batch_id IS NULL OR batch_id NOT IN (
+            SELECT id FROM batches WHERE status = ? OR pruned_at IS NOT NULL
+        )
+        ORDER BY batch_id, key",
+    )?;
+
+    let leaves = stmt
+        .query_map(params![BatchStatus::Reverted.to_string()], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get(2)?,
+            ))
+        })?
+        .map(|row| {
+            let (key, commitment, batch_id) = row?;
+            Ok((
+                Felt::from_be_slice(&key).map_err(anyhow::Error::from)?,
+                Felt::from_be_slice(&commitment).map_err(anyhow::Error::from)?,
+                batch_id,
+            ))
+        })
+        .collect::<Result<_, TrieCacheError>>()?;
+
+    Ok(leaves)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+    use crate::models::batch::NewBatch;
+
+    /// Creates a batch without a trie for leaves to reference.
+    fn create_batch(conn: &PooledConnection<SqliteConnectionManager>) -> u64 {
+        db::batch::create_batch(
+            conn,
+            &NewBatch {
+                parent_id: None,
+                root_idx: 1,
+                root_hash: Felt::ZERO,
+                pre_root_idx: 1,
+                pre_root: Felt::ZERO,
+                item_count: 0,
+                nodes_added: 0,
+                group_id: None,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_leaves_reference_batches() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let storage = TrieDB::new(&conn);
+        let item = CachedItem::new(vec![1, 2, 3]);
+
+        // A leaf of a batch that doesn't exist is rejected
+        assert!(storage.persist_leaves(&vec![item.clone()], 1).is_err());
+
+        // Leaves may be written before their batch within a transaction
+        let tx = conn.unchecked_transaction().unwrap();
+        storage.persist_leaves(&vec![item.clone()], 1).unwrap();
+        assert_eq!(create_batch(&conn), 1);
+        tx.commit().unwrap();
+
+        // The genesis leaf belongs to no batch and is only stored once
+        let genesis = CachedItem::new(vec![0; 32]);
+        storage.persist_leaves(&vec![genesis.clone()], 0).unwrap();
+        storage.persist_leaves(&vec![genesis.clone()], 0).unwrap();
+        let genesis_leaves: u64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM leaves WHERE batch_id IS NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(genesis_leaves, 1);
+        let path = genesis.key.view_bits().to_bitvec();
+        assert_eq!(
+            TrieDB::at_batch(&conn, 1).leaf(&path).unwrap(),
+            Some(genesis.commitment)
+        );
+    }
+
+    #[test]
+    fn test_allocate_node_indices() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let storage = TrieDB::new(&conn);
+
+        assert_eq!(storage.allocate_node_indices(3).unwrap(), 1);
+        assert_eq!(storage.allocate_node_indices(2).unwrap(), 4);
+
+        // Indices allocated by a transaction that's rolled back are handed out again
+        let tx = conn.unchecked_transaction().unwrap();
+        assert_eq!(storage.allocate_node_indices(5).unwrap(), 6);
+        tx.rollback().unwrap();
+        assert_eq!(storage.allocate_node_indices(1).unwrap(), 6);
+
+        // Allocation doesn't depend on the nodes that are stored
+        assert_eq!(storage.get_node_idx().unwrap(), 0);
+        assert_eq!(storage.allocate_node_indices(0).unwrap(), 7);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::models::batch::{BatchStatus, UnknownBatchStatus};
 use r2d2::Error as R2d2Error;
 use rusqlite::Error as RusqliteError;
 use serde_derive::{Deserialize, Serialize};
@@ -15,6 +16,9 @@ pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible>
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
         message = "NOT_FOUND";
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        code = StatusCode::BAD_REQUEST;
+        message = "BAD_REQUEST_INPUTS";
     } else if let Some(TrieCacheError::InvalidHexString) = err.find() {
         code = StatusCode::BAD_REQUEST;
         message = "BAD_REQUEST_INPUTS";
@@ -24,6 +28,66 @@ pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible>
     } else if let Some(TrieCacheError::BatchParentNotFinalized) = err.find() {
         code = StatusCode::BAD_REQUEST;
         message = "PARENT_BATCH_NOT_FINALIZED";
+    } else if let Some(TrieCacheError::BatchAncestorReverted) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "ANCESTOR_BATCH_REVERTED";
+    } else if let Some(TrieCacheError::InvalidStatusTransition { .. }) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "INVALID_STATUS_TRANSITION";
+    } else if let Some(TrieCacheError::CorruptBatchStatus(_)) = err.find() {
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        message = "CORRUPT_BATCH_STATUS";
+    } else if let Some(TrieCacheError::InvalidBatchRange) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_BATCH_RANGE";
+    } else if let Some(TrieCacheError::InvalidProofEncoding) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_PROOF_ENCODING";
+    } else if let Some(TrieCacheError::SigningFailed) = err.find() {
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        message = "SIGNING_FAILED";
+    } else if let Some(TrieCacheError::InvalidReceiptSignature) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_RECEIPT_SIGNATURE";
+    } else if let Some(TrieCacheError::RootPruned) = err.find() {
+        code = StatusCode::GONE;
+        message = "ROOT_PRUNED";
+    } else if let Some(TrieCacheError::InvalidRetentionPolicy) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_RETENTION_POLICY";
+    } else if let Some(TrieCacheError::ItemNotFound) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "ITEM_NOT_FOUND";
+    } else if let Some(TrieCacheError::RootNotFound) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "ROOT_NOT_FOUND";
+    } else if let Some(TrieCacheError::NodeNotFound) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "NODE_NOT_FOUND";
+    } else if let Some(TrieCacheError::BatchReverted) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "BATCH_REVERTED";
+    } else if let Some(TrieCacheError::TargetNotEmpty) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "TARGET_NOT_EMPTY";
+    } else if let Some(TrieCacheError::InvalidSnapshot) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_SNAPSHOT";
+    } else if let Some(TrieCacheError::InvalidBackup) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_BACKUP";
+    } else if let Some(TrieCacheError::InvalidSigningKey) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "INVALID_SIGNING_KEY";
+    } else if let Some(TrieCacheError::UnknownRootHash(_)) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "UNKNOWN_ROOT_HASH";
+    } else if let Some(TrieCacheError::SchemaTooNew { .. }) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "SCHEMA_TOO_NEW";
+    } else if let Some(TrieCacheError::SchemaOutdated { .. }) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "SCHEMA_OUTDATED";
     } else {
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "INTERNAL_SERVER_ERROR";
@@ -55,7 +119,50 @@ pub enum TrieCacheError {
     NodeNotFound,
     ArbitraryError(anyhow::Error),
     BatchParentNotFinalized,
+    BatchAncestorReverted,
+    /// The batches of a range don't form a chain of batches that aren't reverted.
+    InvalidBatchRange,
+    /// A binary encoded proof is malformed.
+    InvalidProofEncoding,
     InvalidHexString,
+    ItemNotFound,
+    RootNotFound,
+    InvalidStatusTransition {
+        from: BatchStatus,
+        to: BatchStatus,
+    },
+    /// A batch status stored in the database could not be parsed.
+    CorruptBatchStatus(String),
+    /// The configured signing key can't be read or isn't a valid Stark private key.
+    InvalidSigningKey,
+    /// Signing a batch receipt with the configured key failed.
+    SigningFailed,
+    /// The signature of a receipt doesn't match its contents and public key.
+    InvalidReceiptSignature,
+    /// The trie at a batch was removed by history pruning.
+    RootPruned,
+    /// A retention policy retains nothing, as neither `keep_last` nor `newer_than` is set.
+    InvalidRetentionPolicy,
+    /// The database a trie is rebuilt, imported or restored into already holds batches or nodes.
+    TargetNotEmpty,
+    /// The batch was reverted, so its trie can no longer be read.
+    BatchReverted,
+    /// A trie snapshot is malformed or doesn't hash to the root in its header.
+    InvalidSnapshot,
+    /// A backup doesn't match the size, checksum or latest batch of its manifest.
+    InvalidBackup,
+    /// Batches of a database being migrated have no root hash, as the node at their root index is missing.
+    UnknownRootHash(Vec<u64>),
+    /// The database was migrated by a newer version, whose schema this one doesn't know.
+    SchemaTooNew {
+        found: u64,
+        supported: u64,
+    },
+    /// The database a trie is rebuilt from isn't at the schema version of this binary, and isn't migrated as it's only read.
+    SchemaOutdated {
+        found: u64,
+        required: u64,
+    },
 }
 
 impl warp::reject::Reject for TrieCacheError {}
@@ -67,6 +174,11 @@ impl From<R2d2Error> for TrieCacheError {
 
 impl From<RusqliteError> for TrieCacheError {
     fn from(err: RusqliteError) -> Self {
+        if let RusqliteError::FromSqlConversionFailure(_, _, ref source) = err {
+            if let Some(UnknownBatchStatus(status)) = source.downcast_ref::<UnknownBatchStatus>() {
+                return TrieCacheError::CorruptBatchStatus(status.clone());
+            }
+        }
         TrieCacheError::DatabaseOperationError(err)
     }
 }
@@ -76,3 +188,56 @@ impl From<anyhow::Error> for TrieCacheError {
         TrieCacheError::ArbitraryError(err)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_codes() {
+        let cases = [
+            (TrieCacheError::NodeNotFound, 404, "NODE_NOT_FOUND"),
+            (TrieCacheError::BatchReverted, 409, "BATCH_REVERTED"),
+            (TrieCacheError::TargetNotEmpty, 409, "TARGET_NOT_EMPTY"),
+            (TrieCacheError::InvalidSnapshot, 400, "INVALID_SNAPSHOT"),
+            (TrieCacheError::InvalidBackup, 400, "INVALID_BACKUP"),
+            (
+                TrieCacheError::InvalidSigningKey,
+                400,
+                "INVALID_SIGNING_KEY",
+            ),
+            (
+                TrieCacheError::UnknownRootHash(vec![1]),
+                409,
+                "UNKNOWN_ROOT_HASH",
+            ),
+            (
+                TrieCacheError::SchemaTooNew {
+                    found: 2,
+                    supported: 1,
+                },
+                409,
+                "SCHEMA_TOO_NEW",
+            ),
+            (
+                TrieCacheError::SchemaOutdated {
+                    found: 1,
+                    required: 2,
+                },
+                409,
+                "SCHEMA_OUTDATED",
+            ),
+        ];
+
+        for (err, code, message) in cases {
+            let resp = handle_rejection(warp::reject::custom(err))
+                .await
+                .unwrap()
+                .into_response();
+            assert_eq!(resp.status().as_u16(), code);
+            let body = warp::hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg: Message = serde_json::from_slice(&body).unwrap();
+            assert_eq!(msg.message, message);
+        }
+    }
+}
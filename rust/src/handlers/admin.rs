@@ -0,0 +1,65 @@
+use crate::db::ConnectionManager;
+use crate::errors::TrieCacheError;
+use crate::models::admin::RetentionPolicy;
+use crate::trie_cache::trie::TrieHash;
+use crate::trie_cache::{backup, gc, integrity};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use warp::Reply;
+
+/// Handler for garbage-collecting the trie.
+///
+/// This function retrieves a connection from the connection manager and deletes the trie nodes and leaves
+/// that are unreachable from any batch that isn't reverted. It returns a JSON response containing the GC report.
+pub async fn collect_garbage(
+    manager: Arc<ConnectionManager>,
+) -> Result<impl Reply, warp::Rejection> {
+    let _writing = manager.batch_write().await;
+    let conn = manager.get_connection()?;
+    let report = gc::collect_garbage(&conn)?;
+
+    Ok(warp::reply::json(&report))
+}
+
+/// Handler for pruning the history of the trie.
+///
+/// This function retrieves a connection from the connection manager, prunes the finalized batches the retention
+/// policy doesn't retain and collects the data only they read. It returns a JSON response containing the prune report.
+pub async fn prune_history(
+    policy: RetentionPolicy,
+    manager: Arc<ConnectionManager>,
+) -> Result<impl Reply, warp::Rejection> {
+    let _writing = manager.batch_write().await;
+    let conn = manager.get_connection()?;
+    let report = gc::prune_history(&conn, &policy)?;
+
+    Ok(warp::reply::json(&report))
+}
+
+/// Handler for checking the integrity of the trie.
+///
+/// This function retrieves a connection from the connection manager, recomputes the hashes of every node reachable
+/// from a live batch and looks up every live leaf from its batch's root. It returns a JSON response containing the integrity report.
+pub async fn verify_db(manager: Arc<ConnectionManager>) -> Result<impl Reply, warp::Rejection> {
+    let conn = manager.get_connection()?;
+    let report = integrity::verify_db::<TrieHash>(&conn)?;
+
+    Ok(warp::reply::json(&report))
+}
+
+/// Handler for backing up the database.
+///
+/// This function pauses batch writes, copies the database into the backup directory with SQLite's online backup API
+/// and resumes batch writes, on a blocking thread. It returns a JSON response containing the manifest of the backup.
+pub async fn create_backup(
+    manager: Arc<ConnectionManager>,
+    backup_dir: PathBuf,
+) -> Result<impl Reply, warp::Rejection> {
+    let manifest =
+        tokio::task::spawn_blocking(move || backup::create_backup(&manager, &backup_dir))
+            .await
+            .map_err(|err| TrieCacheError::from(anyhow::Error::from(err)))??;
+
+    Ok(warp::reply::json(&manifest))
+}
@@ -1,22 +1,33 @@
 use crate::db::ConnectionManager;
-use crate::models::batch::BatchStatus;
+use crate::models::batch::{
+    BatchDetails, BatchQuery, BatchStatus, CreateBatchQuery, FinalizeQuery, RangeProofQuery,
+};
+use crate::models::receipt::Receipt;
+use crate::trie_cache::chain;
+use crate::trie_cache::codec;
+use crate::trie_cache::cost::ProofCost;
 use crate::trie_cache::item::CachedItem;
+use crate::trie_cache::signer::{verify_receipt, Signer};
 use crate::trie_cache::TrieCache;
 use crate::{db, errors::TrieCacheError};
 use std::sync::Arc;
 use tracing::info;
 
-use warp::{http::StatusCode, Reply};
+use warp::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    Reply,
+};
 
 /// Handler for listing batches.
 ///
-/// This function retrieves a connection from the connection manager and fetches all batches from the database.
-/// It returns a JSON response containing the list of batches.
+/// This function retrieves a connection from the connection manager and fetches a page of batches from the database.
+/// It returns a JSON response containing the batches and the cursor of the next page.
 pub(crate) async fn list_batches(
+    query: BatchQuery,
     manager: Arc<ConnectionManager>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let conn = manager.get_connection()?;
-    let batches = db::batch::get_batches(&conn)?;
+    let batches = db::batch::get_batches(&conn, &query)?;
 
     Ok(warp::reply::json(&batches))
 }
@@ -24,39 +35,72 @@ pub(crate) async fn list_batches(
 /// Handler for fetching a specific batch.
 ///
 /// This function retrieves a connection from the connection manager and fetches the batch with the given ID from the database.
-/// It returns a JSON response containing the batch data.
+/// It returns a JSON response containing the batch data and its status history.
 pub async fn fetch_batch(
     batch_id: u64,
     manager: Arc<ConnectionManager>,
 ) -> Result<impl Reply, warp::Rejection> {
     let conn = manager.get_connection()?;
     let batch = db::batch::get_batch(&conn, batch_id)?;
-    Ok(warp::reply::json(&batch))
+    let status_history = db::batch::get_status_history(&conn, batch_id)?;
+    let receipts = db::receipt::get_receipts(&conn, batch_id)?;
+    Ok(warp::reply::json(&BatchDetails {
+        batch,
+        status_history,
+        receipts,
+    }))
 }
 
 /// Handler for creating a new batch.
 ///
 /// This function takes a vector of hexadecimal values and converts them into `CachedItem` objects.
-/// It then creates a new batch in the database using the `TrieCache` struct and returns the resulting proofs as a JSON response.
+/// It then creates a new batch in the database using the `TrieCache` struct and returns the resulting proof as a JSON response.
+/// If `max_items_per_proof` is set, the response is the list of proofs of the sub-batches instead.
+/// Proofs are returned in the format requested by the `format` query parameter.
 pub async fn create_batch(
+    query: CreateBatchQuery,
+    accept: Option<String>,
     hex_values: Vec<String>,
     manager: Arc<ConnectionManager>,
-) -> Result<impl warp::Reply, warp::Rejection> {
+    signer: Option<Arc<Signer>>,
+) -> Result<warp::reply::Response, warp::Rejection> {
     info!("Received new Batch!");
     let items: Vec<CachedItem> = hex_values
         .into_iter()
         .map(|hex| {
             hex::decode(hex)
-                    .map(CachedItem::new)
-                    .map_err(|_| TrieCacheError::InvalidHexString)
+                .map(CachedItem::new)
+                .map_err(|_| TrieCacheError::InvalidHexString)
         })
         .collect::<Result<Vec<_>, _>>()?;
 
-    let conn = manager.get_connection()?;
+    let mut proofs = {
+        let _writing = manager.batch_write().await;
+        let conn = manager.get_connection()?;
+        TrieCache::create_batch(&conn, items, query.max_items_per_proof, signer.as_deref())?
+    };
+    for proof in proofs.iter_mut() {
+        proof.order_leaf_updates(&query.leaf_order)?;
+    }
+
+    if query.format.is_none() && accepts_binary(&accept) {
+        let bytes = match query.max_items_per_proof {
+            Some(_) => codec::encode_all(&proofs)?,
+            None => codec::encode(&proofs[0])?,
+        };
+        return Ok(binary_reply(bytes));
+    }
 
-    let proofs = TrieCache::create_batch(&conn, items)?;
+    let format = query.format.unwrap_or_default();
+    let proofs = proofs
+        .iter()
+        .map(|proof| proof.format(&format))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(warp::reply::json(&proofs))
+    match query.max_items_per_proof {
+        Some(_) => Ok(warp::reply::json(&proofs).into_response()),
+        None => Ok(warp::reply::json(&proofs[0]).into_response()),
+    }
 }
 
 /// Handler for updating the status of a batch.
@@ -67,9 +111,11 @@ pub async fn update_batch_status(
     batch_id: u64,
     new_status: BatchStatus,
     manager: Arc<ConnectionManager>,
+    signer: Option<Arc<Signer>>,
 ) -> Result<impl Reply, warp::Rejection> {
+    let _writing = manager.batch_write().await;
     let conn = manager.get_connection()?;
-    TrieCache::update_batch_status(&conn, batch_id, new_status)?;
+    TrieCache::update_batch_status(&conn, batch_id, new_status, signer.as_deref())?;
 
     Ok(warp::reply::with_status(
         "Batch status updated",
@@ -77,8 +123,100 @@ pub async fn update_batch_status(
     ))
 }
 
+/// Handler for finalizing a batch.
+///
+/// This function retrieves a connection from the connection manager and finalizes the batch with the given ID,
+/// along with its created ancestors if `cascade` is set. It returns a JSON response containing the IDs of the finalized batches.
+pub async fn finalize_batch(
+    batch_id: u64,
+    query: FinalizeQuery,
+    manager: Arc<ConnectionManager>,
+    signer: Option<Arc<Signer>>,
+) -> Result<impl Reply, warp::Rejection> {
+    let _writing = manager.batch_write().await;
+    let conn = manager.get_connection()?;
+    let finalized = TrieCache::finalize_batch(&conn, batch_id, query.cascade, signer.as_deref())?;
+
+    Ok(warp::reply::json(&finalized))
+}
+
+/// Handler for fetching a proof over a range of batches.
+///
+/// This function retrieves a connection from the connection manager and generates a single proof for the transition
+/// from the pre-state of the first batch to the post-state of the last batch. It returns the proof as a JSON response,
+/// in the format requested by the `format` query parameter.
+pub async fn fetch_range_proof(
+    query: RangeProofQuery,
+    accept: Option<String>,
+    manager: Arc<ConnectionManager>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let conn = manager.get_connection()?;
+    let mut proof = TrieCache::get_range_proof(&conn, query.from, query.to)?;
+    proof.order_leaf_updates(&query.leaf_order)?;
+
+    if query.format.is_none() && accepts_binary(&accept) {
+        return Ok(binary_reply(codec::encode(&proof)?));
+    }
+
+    Ok(warp::reply::json(&proof.format(&query.format.unwrap_or_default())?).into_response())
+}
+
+/// Handler for estimating the cost of proving a batch.
+///
+/// This function retrieves a connection from the connection manager and regenerates the proof of the batch with the given ID.
+/// It returns a JSON response containing the node counts of the proof and the estimated Cairo steps and builtin usage.
+pub async fn fetch_batch_cost(
+    batch_id: u64,
+    manager: Arc<ConnectionManager>,
+) -> Result<impl Reply, warp::Rejection> {
+    let conn = manager.get_connection()?;
+    let proof = TrieCache::get_range_proof(&conn, batch_id, batch_id)?;
+
+    Ok(warp::reply::json(&ProofCost::estimate(&proof)))
+}
+
+/// Handler for validating the batch chain.
+///
+/// This function retrieves a connection from the connection manager and recomputes the commitments of all batches from genesis on.
+/// It returns a JSON response containing the number of verified batches and the first broken link, if any.
+pub async fn verify_batch_chain(
+    manager: Arc<ConnectionManager>,
+) -> Result<impl Reply, warp::Rejection> {
+    let conn = manager.get_connection()?;
+    let verification = chain::verify_chain(&conn)?;
+
+    Ok(warp::reply::json(&verification))
+}
+
+/// Handler for verifying a batch receipt.
+///
+/// This function checks the signature of the receipt against its contents and public key.
+/// It returns a response indicating that the signature is valid, or rejects the request if it isn't.
+pub async fn verify_batch_receipt(receipt: Receipt) -> Result<impl Reply, warp::Rejection> {
+    verify_receipt(&receipt)?;
+
+    Ok(warp::reply::with_status(
+        "Receipt signature valid",
+        StatusCode::OK,
+    ))
+}
+
 // pub async fn query_batch_proof(batch_id: u64) -> Result<impl Reply, warp::Rejection> {
 //     // Logic to retrieve proof linked to the batch
 //     let proof = "Example proof data"; // Replace with real data retrieval
 //     Ok(warp::reply::json(&proof))
 // }
+
+/// Returns whether the `Accept` header asks for binary encoded proofs.
+fn accepts_binary(accept: &Option<String>) -> bool {
+    accept.as_deref().is_some_and(|accept| {
+        accept.split(',').any(|media_type| {
+            media_type.split(';').next().unwrap_or("").trim() == codec::MEDIA_TYPE
+        })
+    })
+}
+
+/// Wraps binary encoded proofs in a response with the matching content type.
+fn binary_reply(bytes: Vec<u8>) -> warp::reply::Response {
+    warp::reply::with_header(bytes, CONTENT_TYPE, codec::MEDIA_TYPE).into_response()
+}
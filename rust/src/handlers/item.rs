@@ -0,0 +1,34 @@
+use crate::db::ConnectionManager;
+use crate::models::item::ItemQuery;
+use crate::{db, errors::TrieCacheError};
+use pathfinder_crypto::Felt;
+use std::sync::Arc;
+
+use warp::Reply;
+
+/// Handler for fetching an item.
+///
+/// This function decodes the hexadecimal key and looks up the item as it was at the requested batch.
+/// If no batch is given, the latest version of the item is returned.
+/// It returns a JSON response containing the item's value and commitment.
+pub async fn fetch_item(
+    hex_key: String,
+    query: ItemQuery,
+    manager: Arc<ConnectionManager>,
+) -> Result<impl Reply, warp::Rejection> {
+    let key = hex::decode(hex_key)
+        .ok()
+        .and_then(|bytes| Felt::from_be_slice(&bytes).ok())
+        .ok_or(TrieCacheError::InvalidHexString)?;
+
+    let conn = manager.get_connection()?;
+    if let Some(batch_id) = query.at_batch {
+        if db::batch::get_batch(&conn, batch_id)?.pruned_at.is_some() {
+            return Err(TrieCacheError::RootPruned.into());
+        }
+    }
+
+    let item =
+        db::item::get_item(&conn, &key, query.at_batch)?.ok_or(TrieCacheError::ItemNotFound)?;
+    Ok(warp::reply::json(&item))
+}
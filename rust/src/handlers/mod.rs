@@ -1 +1,4 @@
+pub mod admin;
 pub mod batch;
+pub mod item;
+pub mod root;
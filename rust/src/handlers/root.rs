@@ -0,0 +1,44 @@
+use crate::db::ConnectionManager;
+use crate::models::page::PageQuery;
+use crate::{db, errors::TrieCacheError};
+use pathfinder_crypto::Felt;
+use std::sync::Arc;
+
+use warp::Reply;
+
+/// Handler for listing roots.
+///
+/// This function retrieves a connection from the connection manager and fetches a page of roots from the database.
+/// It returns a JSON response containing the roots and the cursor of the next page.
+pub async fn list_roots(
+    query: PageQuery,
+    manager: Arc<ConnectionManager>,
+) -> Result<impl Reply, warp::Rejection> {
+    let conn = manager.get_connection()?;
+    let roots = db::root::get_roots(&conn, query.cursor, query.limit())?;
+
+    Ok(warp::reply::json(&roots))
+}
+
+/// Handler for fetching a root by its hash.
+///
+/// This function decodes the hexadecimal root hash and looks up the batch that produced it.
+/// It returns a JSON response containing the batch ID, trie index and status of the root, or
+/// `RootPruned` if every batch that produced it was pruned.
+pub async fn fetch_root(
+    hex_hash: String,
+    manager: Arc<ConnectionManager>,
+) -> Result<impl Reply, warp::Rejection> {
+    let root_hash = hex::decode(hex_hash)
+        .ok()
+        .and_then(|bytes| Felt::from_be_slice(&bytes).ok())
+        .ok_or(TrieCacheError::InvalidHexString)?;
+
+    let conn = manager.get_connection()?;
+    let root = db::root::get_root(&conn, &root_hash)?.ok_or(TrieCacheError::RootNotFound)?;
+    if root.pruned_at.is_some() {
+        return Err(TrieCacheError::RootPruned.into());
+    }
+
+    Ok(warp::reply::json(&root))
+}
@@ -1,26 +1,133 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
 use std::sync::Arc;
 use tracing_subscriber::EnvFilter;
 use warp::{Filter};
 
-mod db;
-pub mod errors;
-mod handlers;
-mod models;
-mod routes;
-mod trie_cache;
-use crate::db::ConnectionManager;
-use crate::errors::handle_rejection;
+use sn_mpt::db::ConnectionManager;
+use sn_mpt::errors::handle_rejection;
+use sn_mpt::trie_cache::signer::{Signer, SIGNING_KEY_FILE_ENV};
+use sn_mpt::{routes, trie_cache};
+use tracing::info;
 
 #[tokio::main]
 async fn main() {
-    let manager = Arc::new(ConnectionManager::new("database.db"));
-    manager.create_table().unwrap();
-
-    let routes = routes::routes(manager.clone()).recover(handle_rejection);
-
     // Setup logging
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => serve().await,
+        ["rebuild", source, target] => rebuild(source, target),
+        ["export", database, batch_id, file] => export_snapshot(database, batch_id, file),
+        ["import", file, database] => import_snapshot(file, database),
+        ["restore", manifest, database] => restore_backup(manifest, database),
+        ["decode", file] => decode_proofs(file, false),
+        ["decode-all", file] => decode_proofs(file, true),
+        _ => {
+            eprintln!(
+                "Usage: sn_mpt [rebuild <source.db> <target.db> | export <database.db> <batch_id> <snapshot> | import <snapshot> <database.db> | restore <manifest.json> <database.db> | decode <proof.bin> | decode-all <proofs.bin>]"
+            );
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Serves the API on the default database.
+async fn serve() {
+    let manager = Arc::new(ConnectionManager::new("database.db"));
+    manager.migrate().unwrap();
+
+    let signer = match Signer::from_env() {
+        Ok(signer) => signer.map(Arc::new),
+        Err(err) => {
+            eprintln!(
+                "Can't load the signing key named by {}: {:?}",
+                SIGNING_KEY_FILE_ENV, err
+            );
+            std::process::exit(1);
+        }
+    };
+    match &signer {
+        Some(signer) => info!(
+            "Signing batch receipts with public key {}",
+            signer.public_key()
+        ),
+        None => info!("No signing key configured, batch receipts are not signed"),
+    }
+
+    let backup_dir = trie_cache::backup::backup_dir();
+    info!("Writing backups to {}", backup_dir.display());
+
+    let routes = routes::routes(manager.clone(), signer, backup_dir).recover(handle_rejection);
     warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
 }
+
+/// Rebuilds the trie of the source database into the empty target database and prints the report.
+///
+/// The source is opened read-only and isn't migrated. Exits with a non-zero status if a
+/// recomputed root doesn't match the recorded one.
+fn rebuild(source: &str, target: &str) {
+    let source = ConnectionManager::read_only(source);
+    let target = ConnectionManager::new(target);
+    target.migrate().unwrap();
+
+    let report = trie_cache::rebuild::rebuild(
+        &source.get_connection().unwrap(),
+        &target.get_connection().unwrap(),
+    )
+    .unwrap();
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    if report.first_mismatch.is_some() {
+        std::process::exit(1);
+    }
+}
+
+/// Exports the trie at a batch of the database into a snapshot file and prints its summary.
+fn export_snapshot(database: &str, batch_id: &str, file: &str) {
+    let manager = ConnectionManager::new(database);
+    manager.migrate().unwrap();
+    let batch_id = batch_id.parse().expect("The batch ID must be a number");
+
+    let mut out = BufWriter::new(File::create(file).unwrap());
+    let summary = trie_cache::snapshot::export_snapshot(
+        &manager.get_connection().unwrap(),
+        batch_id,
+        &mut out,
+    )
+    .unwrap();
+    println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+}
+
+/// Imports a snapshot file into the empty database as its genesis batch and prints its summary.
+fn import_snapshot(file: &str, database: &str) {
+    let manager = ConnectionManager::new(database);
+    manager.migrate().unwrap();
+
+    let mut input = BufReader::new(File::open(file).unwrap());
+    let summary =
+        trie_cache::snapshot::import_snapshot(&manager.get_connection().unwrap(), &mut input)
+            .unwrap();
+    println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+}
+
+/// Restores the backup described by a manifest into a new database and prints the manifest.
+fn restore_backup(manifest: &str, database: &str) {
+    let manifest =
+        trie_cache::backup::restore_backup(Path::new(manifest), Path::new(database)).unwrap();
+    println!("{}", serde_json::to_string_pretty(&manifest).unwrap());
+}
+
+/// Decodes a binary encoded proof file, or a list of proofs with `all`, and prints it as JSON.
+fn decode_proofs(file: &str, all: bool) {
+    let bytes = std::fs::read(file).unwrap();
+    let json = match all {
+        true => serde_json::to_string_pretty(&trie_cache::codec::decode_all(&bytes).unwrap()),
+        false => serde_json::to_string_pretty(&trie_cache::codec::decode(&bytes).unwrap()),
+    };
+    println!("{}", json.unwrap());
+}
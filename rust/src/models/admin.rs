@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+/// Query parameters describing which finalized batches history pruning retains.
+///
+/// A batch is retained if either condition holds, so at least one of them must be set.
+#[derive(Debug, Default, Deserialize)]
+pub struct RetentionPolicy {
+    /// The number of most recent finalized batches to retain.
+    pub keep_last: Option<u64>,
+    /// The age in seconds below which finalized batches are retained.
+    pub newer_than: Option<u64>,
+}
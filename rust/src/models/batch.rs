@@ -1,6 +1,12 @@
 use crate::errors::TrieCacheError;
+use crate::models::page::{page_limit, SortOrder};
+use crate::models::receipt::Receipt;
+use pathfinder_crypto::hash::poseidon_hash_many;
+use pathfinder_crypto::{Felt, MontFelt};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
 use rusqlite::Row;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -9,26 +15,212 @@ pub struct Batch {
     pub parent_id: Option<u64>,
     pub status: BatchStatus,
     pub root_idx: u64,
+    /// The hash of the trie root after the batch was applied. The column is never NULL.
+    pub root_hash: String,
+    pub pre_root_idx: u64,
+    /// The hash of the trie root before the batch was applied.
+    pub pre_root: String,
+    pub item_count: u64,
+    /// The number of trie nodes written by the batch. Unknown for batches created by older versions.
+    pub nodes_added: Option<u64>,
+    /// Unix timestamps of the status transitions. Unknown for batches created by older versions.
+    pub created_at: Option<u64>,
+    pub finalized_at: Option<u64>,
+    pub reverted_at: Option<u64>,
+    /// The ID of the first sub-batch, if the batch was split into several proofs.
+    pub group_id: Option<u64>,
+    /// The hash linking the batch to its parent, see `batch_commitment`.
+    pub batch_commitment: Option<String>,
+    /// Unix timestamp of the pruning of the batch. The trie at a pruned batch can no longer be read.
+    pub pruned_at: Option<u64>,
+}
+
+/// The values required to insert a new batch.
+#[derive(Debug)]
+pub struct NewBatch {
+    pub parent_id: Option<u64>,
+    pub root_idx: u64,
+    pub root_hash: Felt,
+    pub pre_root_idx: u64,
+    pub pre_root: Felt,
+    pub item_count: u64,
+    pub nodes_added: u64,
+    pub group_id: Option<u64>,
+}
+
+/// Computes the commitment linking a batch to its parent.
+///
+/// The commitment is `poseidon(parent_commitment, batch_id, pre_root, post_root, item_count)`,
+/// with a parent commitment of 0 for batches without a parent.
+///
+/// # Arguments
+///
+/// * `parent_commitment` - The commitment of the parent batch.
+/// * `batch_id` - The ID of the batch.
+/// * `pre_root` - The trie root before the batch was applied.
+/// * `post_root` - The trie root after the batch was applied.
+/// * `item_count` - The number of items in the batch.
+///
+/// # Returns
+///
+/// The commitment of the batch.
+pub fn batch_commitment(
+    parent_commitment: Felt,
+    batch_id: u64,
+    pre_root: Felt,
+    post_root: Felt,
+    item_count: u64,
+) -> Felt {
+    let felts = [
+        parent_commitment,
+        Felt::from_u64(batch_id),
+        pre_root,
+        post_root,
+        Felt::from_u64(item_count),
+    ];
+
+    poseidon_hash_many(&felts.map(MontFelt::from)).into()
+}
+
+/// A batch together with its full status history and signed receipts.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct BatchDetails {
+    #[serde(flatten)]
+    pub batch: Batch,
+    pub status_history: Vec<StatusChange>,
+    pub receipts: Vec<Receipt>,
+}
+
+/// A single status transition of a batch.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct StatusChange {
+    pub status: BatchStatus,
+    pub changed_at: u64,
+}
+
+/// Query parameters accepted when listing batches.
+#[derive(Debug, Default, Deserialize)]
+pub struct BatchQuery {
+    /// Only batches after this ID, in the requested order, are returned.
+    pub cursor: Option<u64>,
+    pub limit: Option<u64>,
+    pub status: Option<BatchStatus>,
+    /// The lowest batch ID to include.
+    pub from_id: Option<u64>,
+    /// The highest batch ID to include.
+    pub to_id: Option<u64>,
+    /// Only the sub-batches of this group.
+    pub group_id: Option<u64>,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+/// The format in which batch proofs are returned.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofFormat {
+    /// The hex-string JSON document read by `cairo0/src/mpt.cairo`.
+    #[default]
+    Cairo0,
+    /// The flat felt252 array `Serde` reads into the `BatchUpdate` struct of `cairo1/src/lib.cairo`.
+    Cairo1,
+}
+
+/// The order of the leaf updates in batch proofs.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LeafOrder {
+    /// The order in which the items were submitted.
+    #[default]
+    Insertion,
+    /// Ascending key order.
+    Key,
+}
+
+/// Query parameters accepted when creating a batch.
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateBatchQuery {
+    /// The maximum number of items covered by a single proof. Larger batches are split into sub-batches.
+    pub max_items_per_proof: Option<usize>,
+    /// The requested proof format. If set, it takes precedence over the `Accept` header.
+    pub format: Option<ProofFormat>,
+    #[serde(default)]
+    pub leaf_order: LeafOrder,
+}
+
+/// Query parameters accepted when finalizing a batch.
+#[derive(Debug, Default, Deserialize)]
+pub struct FinalizeQuery {
+    /// Whether created ancestors of the batch are finalized as well.
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+/// Query parameters accepted when requesting a proof over a range of batches.
+#[derive(Debug, Deserialize)]
+pub struct RangeProofQuery {
+    /// The ID of the first batch of the range.
+    pub from: u64,
+    /// The ID of the last batch of the range.
+    pub to: u64,
+    /// The requested proof format. If set, it takes precedence over the `Accept` header.
+    pub format: Option<ProofFormat>,
+    #[serde(default)]
+    pub leaf_order: LeafOrder,
+}
+
+impl BatchQuery {
+    /// Returns the requested page size, clamped to the allowed range.
+    pub fn limit(&self) -> u64 {
+        page_limit(self.limit)
+    }
 }
 
 impl TryFrom<&Row<'_>> for Batch {
     type Error = rusqlite::Error;
 
     fn try_from(row: &Row) -> Result<Self, Self::Error> {
-        let status: String = row.get(2)?;
+        let root_hash: Vec<u8> = row.get(4)?;
+        let pre_root: Vec<u8> = row.get(6)?;
+        let batch_commitment: Option<Vec<u8>> = row.get(13)?;
         Ok(Batch {
             id: row.get(0)?,
             parent_id: row.get(1)?,
-            status: BatchStatus::from_str(status.as_str()).unwrap_or(BatchStatus::Reverted),
+            status: row.get(2)?,
             root_idx: row.get(3)?,
+            root_hash: hex::encode(root_hash),
+            pre_root_idx: row.get(5)?,
+            pre_root: hex::encode(pre_root),
+            item_count: row.get(7)?,
+            nodes_added: row.get(8)?,
+            created_at: row.get(9)?,
+            finalized_at: row.get(10)?,
+            reverted_at: row.get(11)?,
+            group_id: row.get(12)?,
+            batch_commitment: batch_commitment.map(hex::encode),
+            pruned_at: row.get(14)?,
+        })
+    }
+}
+
+impl TryFrom<&Row<'_>> for StatusChange {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(StatusChange {
+            status: row.get(0)?,
+            changed_at: row.get(1)?,
         })
     }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum BatchStatus {
+    #[serde(alias = "created")]
     Created = 1,
+    #[serde(alias = "finalized")]
     Finalized = 2,
+    #[serde(alias = "reverted")]
     Reverted = 3,
 }
 
@@ -45,6 +237,59 @@ impl BatchStatus {
             BatchStatus::Reverted => "reverted".to_string(),
         }
     }
+
+    /// Returns the numeric value of the status, as signed in receipts.
+    ///
+    /// # Returns
+    ///
+    /// - 1 for created, 2 for finalized and 3 for reverted.
+    pub fn code(&self) -> u64 {
+        match self {
+            BatchStatus::Created => 1,
+            BatchStatus::Finalized => 2,
+            BatchStatus::Reverted => 3,
+        }
+    }
+
+    /// Checks whether a batch in this status may move to `next`.
+    ///
+    /// Only created batches can change status, either by being finalized or reverted.
+    /// Finalized and reverted batches are terminal.
+    ///
+    /// # Arguments
+    ///
+    /// * `next` - The requested status.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the transition is allowed, `false` otherwise.
+    pub fn can_transition_to(&self, next: &BatchStatus) -> bool {
+        matches!(
+            (self, next),
+            (BatchStatus::Created, BatchStatus::Finalized)
+                | (BatchStatus::Created, BatchStatus::Reverted)
+        )
+    }
+}
+
+/// A status string read from the database that does not name a `BatchStatus`.
+#[derive(Debug)]
+pub struct UnknownBatchStatus(pub String);
+
+impl fmt::Display for UnknownBatchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown batch status: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownBatchStatus {}
+
+impl FromSql for BatchStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let status = value.as_str()?;
+        BatchStatus::from_str(status)
+            .map_err(|_| FromSqlError::Other(Box::new(UnknownBatchStatus(status.to_string()))))
+    }
 }
 
 impl FromStr for BatchStatus {
@@ -0,0 +1,33 @@
+use rusqlite::Row;
+use serde::{Deserialize, Serialize};
+
+/// A leaf of the trie as it was at a given batch.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Item {
+    pub key: String,
+    pub value: Option<String>,
+    pub commitment: String,
+    pub batch_id: u64,
+}
+
+impl TryFrom<&Row<'_>> for Item {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let key: Vec<u8> = row.get(0)?;
+        let value: Option<Vec<u8>> = row.get(1)?;
+        let commitment: Vec<u8> = row.get(2)?;
+        Ok(Item {
+            key: hex::encode(key),
+            value: value.map(hex::encode),
+            commitment: hex::encode(commitment),
+            batch_id: row.get(3)?,
+        })
+    }
+}
+
+/// Query parameters accepted when fetching an item.
+#[derive(Debug, Deserialize)]
+pub struct ItemQuery {
+    pub at_batch: Option<u64>,
+}
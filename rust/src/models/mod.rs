@@ -1 +1,6 @@
+pub mod admin;
 pub mod batch;
+pub mod item;
+pub mod page;
+pub mod receipt;
+pub mod root;
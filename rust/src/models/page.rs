@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+/// The number of entries returned when a request doesn't specify a limit.
+const DEFAULT_PAGE_LIMIT: u64 = 100;
+/// The maximum number of entries a single page may contain.
+const MAX_PAGE_LIMIT: u64 = 1000;
+
+/// A page of results together with the cursor to fetch the next one.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<u64>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from up to `limit + 1` fetched entries.
+    ///
+    /// The extra entry only signals that another page exists and is dropped from the result.
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The fetched entries.
+    /// * `limit` - The requested page size.
+    /// * `cursor` - Extracts the cursor value of an entry.
+    pub fn new(mut items: Vec<T>, limit: u64, cursor: impl Fn(&T) -> u64) -> Self {
+        let next_cursor = if items.len() as u64 > limit {
+            items.truncate(limit as usize);
+            items.last().map(cursor)
+        } else {
+            None
+        };
+
+        Page { items, next_cursor }
+    }
+}
+
+/// Query parameters accepted by paginated endpoints.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    pub cursor: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+impl PageQuery {
+    /// Returns the requested page size, clamped to the allowed range.
+    pub fn limit(&self) -> u64 {
+        page_limit(self.limit)
+    }
+}
+
+/// The order in which paginated entries are returned.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Resolves a requested page size, applying the default and clamping it to the allowed range.
+pub fn page_limit(limit: Option<u64>) -> u64 {
+    limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT)
+}
@@ -0,0 +1,45 @@
+use crate::models::batch::BatchStatus;
+use rusqlite::Row;
+use serde::{Deserialize, Serialize};
+
+/// A signature of the operator over a batch transition.
+///
+/// The signed message is the Poseidon hash of
+/// `(batch_id, pre_root, post_root, parent_id, status)`, with the status as its numeric value,
+/// see `Signer::message_hash`.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Receipt {
+    pub batch_id: u64,
+    /// The parent of the batch, signed as 0 for the first batch.
+    pub parent_id: Option<u64>,
+    pub pre_root: String,
+    pub post_root: String,
+    /// The status the receipt attests to, either `created` or `finalized`.
+    pub status: BatchStatus,
+    /// The Stark public key of the signer.
+    pub public_key: String,
+    pub signature_r: String,
+    pub signature_s: String,
+}
+
+impl TryFrom<&Row<'_>> for Receipt {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let pre_root: Vec<u8> = row.get(3)?;
+        let post_root: Vec<u8> = row.get(4)?;
+        let public_key: Vec<u8> = row.get(5)?;
+        let signature_r: Vec<u8> = row.get(6)?;
+        let signature_s: Vec<u8> = row.get(7)?;
+        Ok(Receipt {
+            batch_id: row.get(0)?,
+            parent_id: row.get(1)?,
+            status: row.get(2)?,
+            pre_root: hex::encode(pre_root),
+            post_root: hex::encode(post_root),
+            public_key: hex::encode(public_key),
+            signature_r: hex::encode(signature_r),
+            signature_s: hex::encode(signature_s),
+        })
+    }
+}
@@ -0,0 +1,29 @@
+use crate::models::batch::BatchStatus;
+use rusqlite::Row;
+use serde::{Deserialize, Serialize};
+
+/// A trie root together with the batch that produced it.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Root {
+    pub root_hash: String,
+    pub batch_id: u64,
+    pub root_idx: u64,
+    pub status: BatchStatus,
+    /// Unix timestamp of the pruning of the batch. The trie at a pruned root can no longer be read.
+    pub pruned_at: Option<u64>,
+}
+
+impl TryFrom<&Row<'_>> for Root {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        let root_hash: Vec<u8> = row.get(0)?;
+        Ok(Root {
+            root_hash: hex::encode(root_hash),
+            batch_id: row.get(1)?,
+            root_idx: row.get(2)?,
+            status: row.get(3)?,
+            pruned_at: row.get(4)?,
+        })
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::db::ConnectionManager;
+use crate::handlers::admin::{collect_garbage, create_backup, prune_history, verify_db};
+use crate::models::admin::RetentionPolicy;
+use crate::routes::{with_backup_dir, with_manager};
+
+use warp::Filter;
+
+/// Defines the routes for maintenance operations.
+///
+/// This function takes a `ConnectionManager` and the directory backups are written to as input and returns a `Filter` that handles admin requests.
+pub fn admin_routes(
+    manager: Arc<ConnectionManager>,
+    backup_dir: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    collect_garbage_route(manager.clone())
+        .or(prune_history_route(manager.clone()))
+        .or(verify_db_route(manager.clone()))
+        .or(create_backup_route(manager, backup_dir))
+}
+
+/// Defines the route for garbage-collecting the trie.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles POST requests to "/admin/gc".
+fn collect_garbage_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "gc")
+        .and(warp::post())
+        .and(with_manager(manager))
+        .and_then(collect_garbage)
+}
+
+/// Defines the route for pruning the history of the trie.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles POST requests to "/admin/prune".
+/// The retention policy is read from the `keep_last` and `newer_than` query parameters.
+fn prune_history_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "prune")
+        .and(warp::post())
+        .and(warp::query::<RetentionPolicy>())
+        .and(with_manager(manager))
+        .and_then(prune_history)
+}
+
+/// Defines the route for checking the integrity of the trie.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles GET requests to "/admin/verify-db".
+fn verify_db_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "verify-db")
+        .and(warp::get())
+        .and(with_manager(manager))
+        .and_then(verify_db)
+}
+
+/// Defines the route for backing up the database.
+///
+/// This function takes a `ConnectionManager` and the directory backups are written to as input and returns a `Filter` that handles POST requests to "/admin/backup".
+fn create_backup_route(
+    manager: Arc<ConnectionManager>,
+    backup_dir: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "backup")
+        .and(warp::post())
+        .and(with_manager(manager))
+        .and(with_backup_dir(backup_dir))
+        .and_then(create_backup)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::TestContext;
+    use crate::errors::handle_rejection;
+    use crate::trie_cache::backup::BackupManifest;
+    use crate::trie_cache::gc::{GcReport, PruneReport};
+    use crate::trie_cache::integrity::IntegrityReport;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+    use rand::random;
+    use warp::http::StatusCode;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_collect_garbage() {
+        let test_ctx = TestContext::new();
+        let api = admin_routes(test_ctx.manager.clone(), PathBuf::new()).recover(handle_rejection);
+
+        let resp = request().method("POST").path("/admin/gc").reply(&api).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let report: GcReport = serde_json::from_str(&body).unwrap();
+        assert_eq!(report.nodes_deleted, 0);
+        assert_eq!(report.leaves_deleted, 0);
+    }
+
+    #[tokio::test]
+    async fn test_prune_history() {
+        let test_ctx = TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        for value in 0..3 {
+            TrieCache::create_batch(&conn, vec![CachedItem::new(vec![value])], None, None).unwrap();
+        }
+        TrieCache::finalize_batch(&conn, 3, true, None).unwrap();
+        let api = admin_routes(test_ctx.manager.clone(), PathBuf::new()).recover(handle_rejection);
+
+        let resp = request()
+            .method("POST")
+            .path("/admin/prune?keep_last=1")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let report: PruneReport = serde_json::from_str(&body).unwrap();
+        assert_eq!(report.pruned_batches, vec![1, 2]);
+
+        // A policy that retains nothing is rejected
+        let resp = request()
+            .method("POST")
+            .path("/admin/prune")
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_verify_db() {
+        let test_ctx = TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![1])], None, None).unwrap();
+        let api = admin_routes(test_ctx.manager.clone(), PathBuf::new()).recover(handle_rejection);
+
+        let resp = request()
+            .method("GET")
+            .path("/admin/verify-db")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let report: IntegrityReport = serde_json::from_str(&body).unwrap();
+        assert_eq!(report.batches_checked, 1);
+        assert!(report.issues.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_backup() {
+        let test_ctx = TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![1])], None, None).unwrap();
+        let dir = std::env::temp_dir().join(format!("{}_backups", random::<u32>()));
+        let api = admin_routes(test_ctx.manager.clone(), dir.clone()).recover(handle_rejection);
+
+        let resp = request()
+            .method("POST")
+            .path("/admin/backup")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let manifest: BackupManifest = serde_json::from_str(&body).unwrap();
+        assert_eq!(manifest.latest_batch_id, Some(1));
+        assert!(dir.join(&manifest.file).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
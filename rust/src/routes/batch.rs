@@ -1,22 +1,35 @@
 use std::sync::Arc;
 
 use crate::db::ConnectionManager;
-use crate::handlers::batch::{create_batch, fetch_batch, list_batches, update_batch_status};
-use crate::models::batch::BatchStatus;
-
+use crate::handlers::batch::{
+    create_batch, fetch_batch, fetch_batch_cost, fetch_range_proof, finalize_batch, list_batches,
+    update_batch_status, verify_batch_chain, verify_batch_receipt,
+};
+use crate::models::batch::{
+    BatchQuery, BatchStatus, CreateBatchQuery, FinalizeQuery, RangeProofQuery,
+};
+use crate::models::receipt::Receipt;
+use crate::routes::{with_manager, with_signer};
+use crate::trie_cache::signer::Signer;
 
 use warp::Filter;
 
 /// Defines the routes for batch operations.
 ///
-/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles batch-related requests.
+/// This function takes a `ConnectionManager` and the optional receipt `Signer` as input and returns a `Filter` that handles batch-related requests.
 pub fn batch_routes(
     manager: Arc<ConnectionManager>,
+    signer: Option<Arc<Signer>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     list_batches_route(manager.clone())
         .or(fetch_batch_route(manager.clone()))
-        .or(create_batch_route(manager.clone()))
-        .or(update_batch_status_route(manager.clone()))
+        .or(create_batch_route(manager.clone(), signer.clone()))
+        .or(update_batch_status_route(manager.clone(), signer.clone()))
+        .or(finalize_batch_route(manager.clone(), signer))
+        .or(fetch_range_proof_route(manager.clone()))
+        .or(fetch_batch_cost_route(manager.clone()))
+        .or(verify_batch_receipt_route())
+        .or(verify_batch_chain_route(manager.clone()))
 }
 
 /// Defines the route for listing batches.
@@ -27,6 +40,7 @@ fn list_batches_route(
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("batches")
         .and(warp::get())
+        .and(warp::query::<BatchQuery>())
         .and(with_manager(manager))
         .and_then(list_batches)
 }
@@ -48,33 +62,95 @@ fn fetch_batch_route(
 /// This function takes a `ConnectionManager` as input and returns a `Filter` that handles POST requests to "/batches".
 fn create_batch_route(
     manager: Arc<ConnectionManager>,
+    signer: Option<Arc<Signer>>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("batches")
         .and(warp::post())
+        .and(warp::query::<CreateBatchQuery>())
+        .and(warp::header::optional::<String>("accept"))
         .and(warp::body::json::<Vec<String>>())
         .and(with_manager(manager))
+        .and(with_signer(signer))
         .and_then(create_batch)
 }
 
 /// Defines the route for updating the status of a batch.
 ///
 /// This function takes a `ConnectionManager` as input and returns a `Filter` that handles PUT requests to "/batches/{id}/status/{status}".
+/// Reverting a batch also reverts every batch built on top of it, in the same transaction.
 fn update_batch_status_route(
     manager: Arc<ConnectionManager>,
+    signer: Option<Arc<Signer>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("batches" / u64 / "status" / BatchStatus)
         .and(warp::put())
         .and(with_manager(manager))
+        .and(with_signer(signer))
         .and_then(update_batch_status)
 }
 
-/// Helper function to pass `ConnectionManager` as a Warp filter.
+/// Defines the route for finalizing a batch.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles POST requests to "/batches/{id}/finalize".
+fn finalize_batch_route(
+    manager: Arc<ConnectionManager>,
+    signer: Option<Arc<Signer>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("batches" / u64 / "finalize")
+        .and(warp::post())
+        .and(warp::query::<FinalizeQuery>())
+        .and(with_manager(manager))
+        .and(with_signer(signer))
+        .and_then(finalize_batch)
+}
+
+/// Defines the route for fetching a proof over a range of batches.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles GET requests to "/batches/proof".
+fn fetch_range_proof_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("batches" / "proof")
+        .and(warp::get())
+        .and(warp::query::<RangeProofQuery>())
+        .and(warp::header::optional::<String>("accept"))
+        .and(with_manager(manager))
+        .and_then(fetch_range_proof)
+}
+
+/// Defines the route for estimating the cost of proving a batch.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles GET requests to "/batches/{id}/cost".
+fn fetch_batch_cost_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("batches" / u64 / "cost")
+        .and(warp::get())
+        .and(with_manager(manager))
+        .and_then(fetch_batch_cost)
+}
+
+/// Defines the route for validating the batch chain.
 ///
-/// This function takes a `ConnectionManager` as input and returns a `Filter` that extracts the `ConnectionManager` from the request.
-fn with_manager(
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles GET requests to "/batches/chain/verify".
+fn verify_batch_chain_route(
     manager: Arc<ConnectionManager>,
-) -> impl Filter<Extract = (Arc<ConnectionManager>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || manager.clone())
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("batches" / "chain" / "verify")
+        .and(warp::get())
+        .and(with_manager(manager))
+        .and_then(verify_batch_chain)
+}
+
+/// Defines the route for verifying a batch receipt.
+///
+/// This function returns a `Filter` that handles POST requests to "/batches/receipts/verify".
+fn verify_batch_receipt_route(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("batches" / "receipts" / "verify")
+        .and(warp::post())
+        .and(warp::body::json::<Receipt>())
+        .and_then(verify_batch_receipt)
 }
 
 #[cfg(test)]
@@ -82,8 +158,14 @@ mod test {
     use warp::http::StatusCode;
     use super::*;
     use crate::db::test::TestContext;
-    use crate::models::batch::Batch;
-    use crate::{errors::Message, handle_rejection};
+    use crate::models::batch::{Batch, BatchDetails, BatchStatus};
+    use crate::models::page::Page;
+    use crate::trie_cache::batch_proof::BatchProof;
+    use crate::trie_cache::chain::ChainVerification;
+    use crate::trie_cache::codec;
+    use crate::trie_cache::signer::verify_receipt;
+    use crate::errors::{handle_rejection, Message, TrieCacheError};
+    use pathfinder_crypto::Felt;
     use warp::test::request;
 
     #[tokio::test]
@@ -91,30 +173,58 @@ mod test {
         let test_ctx = TestContext::new();
         let batches = test_ctx.batch_seeding();
 
-        let api = batch_routes(test_ctx.manager.clone()).recover(handle_rejection);
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
 
         let resp = request().method("GET").path("/batches").reply(&api).await;
 
         assert_eq!(resp.status(), StatusCode::OK);
         let body = String::from_utf8(resp.body().to_vec()).unwrap();
-        let received: Vec<Batch> = serde_json::from_str(&body).unwrap();
+        let received: Page<Batch> = serde_json::from_str(&body).unwrap();
 
-        assert_eq!(received, batches);
+        assert_eq!(received.items, batches);
+        assert_eq!(received.next_cursor, None);
+
+        let resp = request()
+            .method("GET")
+            .path("/batches?status=created&order=desc&limit=1")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let received: Page<Batch> = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(received.items, batches[2..]);
+        assert_eq!(received.next_cursor, Some(3));
+
+        let resp = request()
+            .method("GET")
+            .path("/batches?order=sideways")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
     async fn test_fetch_batch() {
         let test_ctx = TestContext::new();
         let batches = test_ctx.batch_seeding();
-        let api = batch_routes(test_ctx.manager.clone()).recover(handle_rejection);
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
 
         let resp = request().method("GET").path("/batches/1").reply(&api).await;
 
         assert_eq!(resp.status(), StatusCode::OK);
         let body = String::from_utf8(resp.body().to_vec()).unwrap();
-        let received: Batch = serde_json::from_str(&body).unwrap();
+        let received: BatchDetails = serde_json::from_str(&body).unwrap();
 
-        assert_eq!(received, batches[0]);
+        assert_eq!(received.batch, batches[0]);
+        let history: Vec<BatchStatus> = received
+            .status_history
+            .into_iter()
+            .map(|change| change.status)
+            .collect();
+        assert_eq!(history, vec![BatchStatus::Created, BatchStatus::Finalized]);
 
         let resp = request()
             .method("GET")
@@ -131,7 +241,7 @@ mod test {
     #[tokio::test]
     async fn test_create_batch() {
         let test_ctx = TestContext::new();
-        let api = batch_routes(test_ctx.manager.clone()).recover(handle_rejection);
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
 
         let resp = request()
             .method("POST")
@@ -153,13 +263,59 @@ mod test {
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = request()
+            .method("POST")
+            .path("/batches?leaf_order=key")
+            .json(&vec!["060606", "070707", "080808"])
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let proof: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let keys: Vec<_> = proof["leaf_updates"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|update| update["key"].as_str().unwrap())
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(proof["proof_hash"].as_str().unwrap().len(), 64);
+
+        let resp = request()
+            .method("POST")
+            .path("/batches?max_items_per_proof=1")
+            .json(&vec!["020202", "030303"])
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let proofs: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(proofs.len(), 2);
+        assert_eq!(proofs[0]["post_root"], proofs[1]["pre_root"]);
+
+        let resp = request()
+            .method("POST")
+            .path("/batches?max_items_per_proof=1")
+            .header("accept", codec::MEDIA_TYPE)
+            .json(&vec!["040404", "050505"])
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], codec::MEDIA_TYPE);
+        let proofs = codec::decode_all(resp.body()).unwrap();
+        assert_eq!(proofs.len(), 2);
+        assert_eq!(proofs[0].post_root, proofs[1].pre_root);
     }
 
     #[tokio::test]
     async fn test_update_batch_status() {
         let test_ctx = TestContext::new();
         let _ = test_ctx.batch_seeding();
-        let api = batch_routes(test_ctx.manager.clone()).recover(handle_rejection);
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
 
         let resp = request()
             .method("PUT")
@@ -179,5 +335,323 @@ mod test {
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = request()
+            .method("PUT")
+            .path("/batches/2/status/reverted")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "INVALID_STATUS_TRANSITION");
+    }
+
+    #[tokio::test]
+    async fn test_revert_batch_cascades() {
+        let test_ctx = TestContext::new();
+        let _ = test_ctx.batch_seeding();
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
+
+        let resp = request()
+            .method("PUT")
+            .path("/batches/2/status/reverted")
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Batch 3 is built on batch 2, so it's reverted with it
+        let resp = request().method("GET").path("/batches/3").reply(&api).await;
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let details: BatchDetails = serde_json::from_str(&body).unwrap();
+        assert_eq!(details.batch.status, BatchStatus::Reverted);
+    }
+
+    #[tokio::test]
+    async fn test_finalize_batch() {
+        let test_ctx = TestContext::new();
+        let _ = test_ctx.batch_seeding();
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
+
+        let resp = request()
+            .method("POST")
+            .path("/batches/3/finalize")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "PARENT_BATCH_NOT_FINALIZED");
+
+        let resp = request()
+            .method("POST")
+            .path("/batches/3/finalize?cascade=true")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let finalized: Vec<u64> = serde_json::from_str(&body).unwrap();
+        assert_eq!(finalized, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_range_proof() {
+        let test_ctx = TestContext::new();
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
+
+        for value in ["010101", "020202"] {
+            let resp = request()
+                .method("POST")
+                .path("/batches")
+                .json(&vec![value])
+                .reply(&api)
+                .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let resp = request()
+            .method("GET")
+            .path("/batches/proof?from=1&to=2")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let proof: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(proof["leaf_updates"].as_array().unwrap().len(), 2);
+
+        let resp = request()
+            .method("GET")
+            .path("/batches/proof?from=1&to=2&format=cairo1")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert!(body.get("receipt").is_none());
+        let felts: Vec<String> = serde_json::from_value(body["felts"].clone()).unwrap();
+        assert_eq!(
+            felts[0],
+            format!("0x{}", proof["pre_root"].as_str().unwrap())
+        );
+        assert_eq!(
+            felts[2],
+            "0x".to_string() + &hex::encode(Felt::from_u64(2).to_be_bytes())
+        );
+
+        let resp = request()
+            .method("GET")
+            .path("/batches/proof?from=1&to=2")
+            .header("accept", "application/octet-stream, application/json;q=0.5")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let decoded = codec::decode(resp.body()).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), proof);
+        assert_eq!(codec::encode(&decoded).unwrap(), resp.body().to_vec());
+
+        // An explicit format wins over the Accept header
+        let resp = request()
+            .method("GET")
+            .path("/batches/proof?from=1&to=2&format=cairo0")
+            .header("accept", codec::MEDIA_TYPE)
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_ne!(resp.headers()["content-type"], codec::MEDIA_TYPE);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            proof
+        );
+
+        let resp = request()
+            .method("GET")
+            .path("/batches/proof?from=2&to=1")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "INVALID_BATCH_RANGE");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_batch_cost() {
+        let test_ctx = TestContext::new();
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
+
+        let resp = request()
+            .method("POST")
+            .path("/batches")
+            .json(&vec!["010101", "020202"])
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = request()
+            .method("GET")
+            .path("/batches/1/cost")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let cost: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(cost["leaf_updates"], 2);
+        assert_eq!(
+            cost["preimage_nodes"],
+            cost["binary_nodes"].as_u64().unwrap() + cost["edge_nodes"].as_u64().unwrap()
+        );
+        assert_eq!(cost["pedersen_builtins"], 0);
+
+        let resp = request()
+            .method("GET")
+            .path("/batches/10/cost")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_signed_receipts() {
+        let test_ctx = TestContext::new();
+        let signer = Signer::new(Felt::from_u64(42)).unwrap();
+        let public_key = signer.public_key();
+        let api = batch_routes(test_ctx.manager.clone(), Some(Arc::new(signer)))
+            .recover(handle_rejection);
+
+        let resp = request()
+            .method("POST")
+            .path("/batches")
+            .json(&vec!["010101"])
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let proof: BatchProof = serde_json::from_str(&body).unwrap();
+        let receipt = proof.receipt.unwrap();
+        assert_eq!(receipt.public_key, public_key);
+        assert_eq!(receipt.post_root, proof.post_root);
+        assert!(verify_receipt(&receipt).is_ok());
+
+        let resp = request()
+            .method("POST")
+            .path("/batches/1/finalize")
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = request().method("GET").path("/batches/1").reply(&api).await;
+
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let details: BatchDetails = serde_json::from_str(&body).unwrap();
+        let statuses: Vec<_> = details.receipts.iter().map(|r| &r.status).collect();
+        assert_eq!(statuses, [&BatchStatus::Created, &BatchStatus::Finalized]);
+        assert!(details.receipts.iter().all(|r| verify_receipt(r).is_ok()));
+        assert_ne!(
+            details.receipts[0].signature_r,
+            details.receipts[1].signature_r
+        );
+
+        let resp = request()
+            .method("POST")
+            .path("/batches/receipts/verify")
+            .json(&details.receipts[1])
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // A creation receipt doesn't verify as a finalization receipt
+        let refinalized = Receipt {
+            status: BatchStatus::Finalized,
+            ..details.receipts.into_iter().next().unwrap()
+        };
+        assert!(matches!(
+            verify_receipt(&refinalized),
+            Err(TrieCacheError::InvalidReceiptSignature)
+        ));
+
+        let resp = request()
+            .method("POST")
+            .path("/batches?format=cairo1")
+            .json(&vec!["020202"])
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert!(!body["felts"].as_array().unwrap().is_empty());
+        let cairo1_receipt: Receipt = serde_json::from_value(body["receipt"].clone()).unwrap();
+        assert_eq!(cairo1_receipt.batch_id, 2);
+        assert!(verify_receipt(&cairo1_receipt).is_ok());
+
+        let resp = request()
+            .method("POST")
+            .path("/batches")
+            .header("accept", codec::MEDIA_TYPE)
+            .json(&vec!["030303"])
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let decoded = codec::decode(resp.body()).unwrap();
+        assert_eq!(codec::encode(&decoded).unwrap(), resp.body().to_vec());
+        let binary_receipt = decoded.receipt.unwrap();
+        assert_eq!(binary_receipt.batch_id, 3);
+        assert_eq!(binary_receipt.parent_id, Some(2));
+        assert!(verify_receipt(&binary_receipt).is_ok());
+
+        let forged = Receipt {
+            pre_root: receipt.post_root.clone(),
+            ..receipt
+        };
+        let resp = request()
+            .method("POST")
+            .path("/batches/receipts/verify")
+            .json(&forged)
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "INVALID_RECEIPT_SIGNATURE");
+    }
+
+    #[tokio::test]
+    async fn test_verify_batch_chain() {
+        let test_ctx = TestContext::new();
+        let api = batch_routes(test_ctx.manager.clone(), None).recover(handle_rejection);
+
+        for value in ["010101", "020202"] {
+            let resp = request()
+                .method("POST")
+                .path("/batches")
+                .json(&vec![value])
+                .reply(&api)
+                .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let resp = request()
+            .method("GET")
+            .path("/batches/chain/verify")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let verification: ChainVerification = serde_json::from_str(&body).unwrap();
+        assert_eq!(verification.verified_batches, 2);
+        assert_eq!(verification.first_break, None);
     }
 }
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use crate::db::ConnectionManager;
+use crate::handlers::item::fetch_item;
+use crate::models::item::ItemQuery;
+use crate::routes::with_manager;
+
+use warp::Filter;
+
+/// Defines the routes for item operations.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles item-related requests.
+pub fn item_routes(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    fetch_item_route(manager)
+}
+
+/// Defines the route for fetching an item by key.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles GET requests to "/items/{key}?at_batch={id}".
+fn fetch_item_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("items" / String)
+        .and(warp::get())
+        .and(warp::query::<ItemQuery>())
+        .and(with_manager(manager))
+        .and_then(fetch_item)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::TestContext;
+    use crate::models::item::Item;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+    use crate::errors::{handle_rejection, Message};
+    use warp::http::StatusCode;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_fetch_item() {
+        let test_ctx = TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let item = CachedItem::new(vec![1, 2, 3]);
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![4, 5, 6])], None, None).unwrap();
+        TrieCache::create_batch(&conn, vec![item.clone()], None, None).unwrap();
+
+        let api = item_routes(test_ctx.manager.clone()).recover(handle_rejection);
+        let key = hex::encode(item.key.to_be_bytes());
+
+        let resp = request()
+            .method("GET")
+            .path(&format!("/items/{}?at_batch=2", key))
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let received: Item = serde_json::from_str(&body).unwrap();
+        assert_eq!(received.value, Some("010203".to_string()));
+        assert_eq!(
+            received.commitment,
+            hex::encode(item.commitment.to_be_bytes())
+        );
+        assert_eq!(received.batch_id, 2);
+
+        let resp = request()
+            .method("GET")
+            .path(&format!("/items/{}?at_batch=1", key))
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "ITEM_NOT_FOUND");
+
+        let resp = request()
+            .method("GET")
+            .path(&format!("/items/{}?at_batch=10", key))
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "BATCH_NOT_FOUND");
+
+        let resp = request()
+            .method("GET")
+            .path("/items/invalid")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,13 +1,49 @@
+mod admin;
 mod batch;
+mod item;
+mod root;
 
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::db::ConnectionManager;
+use crate::trie_cache::signer::Signer;
+use admin::admin_routes;
 use batch::batch_routes;
+use item::item_routes;
+use root::root_routes;
 use warp::Filter;
 
 pub fn routes(
     manager: Arc<ConnectionManager>,
+    signer: Option<Arc<Signer>>,
+    backup_dir: PathBuf,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    batch_routes(manager)
+    batch_routes(manager.clone(), signer)
+        .or(item_routes(manager.clone()))
+        .or(root_routes(manager.clone()))
+        .or(admin_routes(manager, backup_dir))
+}
+
+/// Helper function to pass `ConnectionManager` as a Warp filter.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that extracts the `ConnectionManager` from the request.
+fn with_manager(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (Arc<ConnectionManager>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || manager.clone())
+}
+
+/// Helper function to pass the optional receipt `Signer` as a Warp filter.
+fn with_signer(
+    signer: Option<Arc<Signer>>,
+) -> impl Filter<Extract = (Option<Arc<Signer>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || signer.clone())
+}
+
+/// Helper function to pass the directory backups are written to as a Warp filter.
+fn with_backup_dir(
+    backup_dir: PathBuf,
+) -> impl Filter<Extract = (PathBuf,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || backup_dir.clone())
 }
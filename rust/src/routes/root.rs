@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use crate::db::ConnectionManager;
+use crate::handlers::root::{fetch_root, list_roots};
+use crate::models::page::PageQuery;
+use crate::routes::with_manager;
+
+use warp::Filter;
+
+/// Defines the routes for root operations.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles root-related requests.
+pub fn root_routes(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    list_roots_route(manager.clone()).or(fetch_root_route(manager))
+}
+
+/// Defines the route for listing roots.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles GET requests to "/roots".
+fn list_roots_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("roots")
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(with_manager(manager))
+        .and_then(list_roots)
+}
+
+/// Defines the route for fetching a root by its hash.
+///
+/// This function takes a `ConnectionManager` as input and returns a `Filter` that handles GET requests to "/roots/{hash}".
+fn fetch_root_route(
+    manager: Arc<ConnectionManager>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("roots" / String)
+        .and(warp::get())
+        .and(with_manager(manager))
+        .and_then(fetch_root)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::TestContext;
+    use crate::models::page::Page;
+    use crate::models::root::Root;
+    use crate::errors::{handle_rejection, Message};
+    use warp::http::StatusCode;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_list_roots() {
+        let test_ctx = TestContext::new();
+        let batches = test_ctx.batch_seeding();
+        let api = root_routes(test_ctx.manager.clone()).recover(handle_rejection);
+
+        let resp = request()
+            .method("GET")
+            .path("/roots?limit=2")
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let received: Page<Root> = serde_json::from_str(&body).unwrap();
+        assert_eq!(received.items.len(), 2);
+        assert_eq!(received.items[1].root_hash, batches[1].root_hash);
+        assert_eq!(received.next_cursor, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_root() {
+        let test_ctx = TestContext::new();
+        let batches = test_ctx.batch_seeding();
+        let api = root_routes(test_ctx.manager.clone()).recover(handle_rejection);
+
+        let resp = request()
+            .method("GET")
+            .path(&format!("/roots/{}", batches[2].root_hash))
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let received: Root = serde_json::from_str(&body).unwrap();
+        assert_eq!(received.batch_id, 3);
+        assert_eq!(received.root_idx, 16);
+
+        let resp = request()
+            .method("GET")
+            .path(&format!("/roots/{}", "00".repeat(32)))
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "ROOT_NOT_FOUND");
+
+        // Pruned roots are still listed, but can't be looked up
+        let conn = test_ctx.manager.get_connection().unwrap();
+        crate::db::batch::mark_pruned(&conn, 1).unwrap();
+        let resp = request()
+            .method("GET")
+            .path(&format!("/roots/{}", batches[0].root_hash))
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::GONE);
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let msg: Message = serde_json::from_str(&body).unwrap();
+        assert_eq!(msg.message, "ROOT_PRUNED");
+
+        let resp = request().method("GET").path("/roots").reply(&api).await;
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let received: Page<Root> = serde_json::from_str(&body).unwrap();
+        assert!(received.items[0].pruned_at.is_some());
+    }
+}
@@ -0,0 +1,325 @@
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+use crate::db;
+use crate::db::ConnectionManager;
+use crate::errors::TrieCacheError;
+use crate::models::batch::BatchQuery;
+use crate::models::page::SortOrder;
+
+/// The environment variable naming the directory backups are written to.
+pub const BACKUP_DIR_ENV: &str = "SN_MPT_BACKUP_DIR";
+/// The directory backups are written to if `SN_MPT_BACKUP_DIR` isn't set.
+const DEFAULT_BACKUP_DIR: &str = "backups";
+
+/// Describes a backup, so it can be validated before it's restored.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BackupManifest {
+    /// The file name of the backup, in the directory of the manifest.
+    pub file: String,
+    pub created_at: u64,
+    /// The latest batch in the backup, `None` if it holds no batches.
+    pub latest_batch_id: Option<u64>,
+    pub latest_root: Option<String>,
+    /// The size of the backup in bytes.
+    pub size: u64,
+    /// The hex-encoded SHA3-256 hash of the backup.
+    pub checksum: String,
+}
+
+/// Returns the directory named by `SN_MPT_BACKUP_DIR`, or `backups` if the variable isn't set.
+pub fn backup_dir() -> PathBuf {
+    std::env::var_os(BACKUP_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_BACKUP_DIR))
+}
+
+/// Writes a consistent copy of the database and its manifest into a directory.
+///
+/// Batch creation, status changes and pruning are paused while the database is copied, so the
+/// copy ends at a batch boundary, and resume before the copy is hashed. The copy is taken with
+/// SQLite's online backup API in a single step. The copy is written as `backup-{created_at}.db`
+/// and its manifest as `backup-{created_at}.json`, with a `-{n}` suffix after the timestamp if a
+/// backup of the same second exists. Existing backups are never overwritten.
+///
+/// # Arguments
+///
+/// * `manager` - The connection manager of the database to back up.
+/// * `dir` - The directory to write the backup into. It's created if it doesn't exist.
+///
+/// # Returns
+///
+/// The manifest of the backup, or a TrieCacheError if an error occurs.
+pub fn create_backup(
+    manager: &ConnectionManager,
+    dir: &Path,
+) -> Result<BackupManifest, TrieCacheError> {
+    std::fs::create_dir_all(dir).map_err(anyhow::Error::from)?;
+    let created_at = db::batch::unix_timestamp();
+    let (name, file) = reserve_backup_file(dir, created_at)?;
+
+    {
+        // The connection is taken first, as the batch writes waiting for the pause to end don't
+        // hold connections until it does
+        let conn = manager.get_connection()?;
+        let _paused = manager.pause_batch_writes();
+        let mut copy = Connection::open(&file)?;
+        let backup = Backup::new(&conn, &mut copy)?;
+        // Copying every page in one step reads the source in a single transaction
+        while backup.step(-1)? != StepResult::Done {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    let (latest_batch_id, latest_root) = latest_batch(&file)?;
+    let (size, checksum) = checksum(&file)?;
+    let manifest = BackupManifest {
+        file: format!("{}.db", name),
+        created_at,
+        latest_batch_id,
+        latest_root,
+        size,
+        checksum,
+    };
+
+    let out = File::options()
+        .write(true)
+        .create_new(true)
+        .open(dir.join(format!("{}.json", name)))
+        .map_err(anyhow::Error::from)?;
+    serde_json::to_writer_pretty(out, &manifest).map_err(anyhow::Error::from)?;
+    info!("Backup written: {:?}", manifest);
+    Ok(manifest)
+}
+
+/// Restores a backup into a new database file.
+///
+/// The backup is read from the directory of its manifest and copied to `target`. The copy must
+/// match the size and checksum in the manifest and hold its latest batch and root, and is removed
+/// if it doesn't.
+///
+/// # Arguments
+///
+/// * `manifest` - The path of the manifest of the backup.
+/// * `target` - The path of the database to restore into, which must not exist yet.
+///
+/// # Returns
+///
+/// The manifest of the restored backup, or a TrieCacheError if an error occurs.
+pub fn restore_backup(manifest: &Path, target: &Path) -> Result<BackupManifest, TrieCacheError> {
+    if target.exists() {
+        return Err(TrieCacheError::TargetNotEmpty);
+    }
+
+    let file = File::open(manifest).map_err(anyhow::Error::from)?;
+    let manifest_dir = manifest.parent().unwrap_or(Path::new(""));
+    let manifest: BackupManifest =
+        serde_json::from_reader(file).map_err(|_| TrieCacheError::InvalidBackup)?;
+
+    std::fs::copy(manifest_dir.join(&manifest.file), target).map_err(anyhow::Error::from)?;
+    if let Err(err) = verify_backup(&manifest, target) {
+        let _ = std::fs::remove_file(target);
+        return Err(err);
+    }
+
+    info!("Backup restored: {:?}", manifest);
+    Ok(manifest)
+}
+
+/// Checks that a database file matches the manifest of a backup.
+fn verify_backup(manifest: &BackupManifest, file: &Path) -> Result<(), TrieCacheError> {
+    let (size, checksum) = checksum(file)?;
+    if size != manifest.size || checksum != manifest.checksum {
+        return Err(TrieCacheError::InvalidBackup);
+    }
+
+    let (latest_batch_id, latest_root) = latest_batch(file)?;
+    if latest_batch_id != manifest.latest_batch_id || latest_root != manifest.latest_root {
+        return Err(TrieCacheError::InvalidBackup);
+    }
+    Ok(())
+}
+
+/// Creates an empty database file for a backup under the first unused name for its timestamp.
+///
+/// The file is created exclusively, so concurrent backups never share or overwrite a file.
+fn reserve_backup_file(dir: &Path, created_at: u64) -> Result<(String, PathBuf), TrieCacheError> {
+    for n in 0.. {
+        let name = match n {
+            0 => format!("backup-{}", created_at),
+            n => format!("backup-{}-{}", created_at, n),
+        };
+        let file = dir.join(format!("{}.db", name));
+        match File::options().write(true).create_new(true).open(&file) {
+            Ok(_) => return Ok((name, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(anyhow::Error::from(err).into()),
+        }
+    }
+    unreachable!("the backup names of a timestamp are unbounded")
+}
+
+/// Returns the ID and root hash of the latest batch of a database file.
+fn latest_batch(file: &Path) -> Result<(Option<u64>, Option<String>), TrieCacheError> {
+    let manager = ConnectionManager::new(&file.to_string_lossy());
+    let query = BatchQuery {
+        limit: Some(1),
+        order: SortOrder::Desc,
+        ..Default::default()
+    };
+    let page = db::batch::get_batches(&manager.get_connection()?, &query)?;
+
+    Ok(match page.items.into_iter().next() {
+        Some(batch) => (Some(batch.id), Some(batch.root_hash)),
+        None => (None, None),
+    })
+}
+
+/// Returns the size and the hex-encoded SHA3-256 hash of a file, reading it in chunks.
+fn checksum(file: &Path) -> Result<(u64, String), TrieCacheError> {
+    let mut file = File::open(file).map_err(anyhow::Error::from)?;
+    let mut hasher = Sha3_256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buf).map_err(anyhow::Error::from)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+    use rand::random;
+
+    #[test]
+    fn test_backup_and_restore() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let items: Vec<_> = (1..4)
+            .map(|value| CachedItem::new(vec![0, value]))
+            .collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 4])], None, None).unwrap();
+        let latest = db::batch::get_batch(&conn, 2).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("{}_backups", random::<u32>()));
+        let manifest = create_backup(&test_ctx.manager, &dir).unwrap();
+        assert_eq!(manifest.latest_batch_id, Some(2));
+        assert_eq!(manifest.latest_root, Some(latest.root_hash.clone()));
+
+        // Batches created after the backup aren't in it
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 5])], None, None).unwrap();
+
+        let manifest_file = dir.join(manifest.file.replace(".db", ".json"));
+        let target = dir.join("restored.db");
+        assert_eq!(restore_backup(&manifest_file, &target).unwrap(), manifest);
+        let restored = ConnectionManager::new(&target.to_string_lossy());
+        let restored = restored.get_connection().unwrap();
+        assert_eq!(db::batch::get_latest_batch_id(&restored).unwrap(), Some(2));
+        assert_eq!(db::batch::get_batch(&restored, 2).unwrap(), latest);
+        TrieCache::get_range_proof(&restored, 1, 2).unwrap();
+
+        // An existing database is never overwritten
+        assert!(matches!(
+            restore_backup(&manifest_file, &target),
+            Err(TrieCacheError::TargetNotEmpty)
+        ));
+
+        // A manifest that doesn't match the backup is rejected, and nothing is left behind
+        let tampered = BackupManifest {
+            latest_batch_id: Some(3),
+            ..manifest
+        };
+        std::fs::write(&manifest_file, serde_json::to_vec(&tampered).unwrap()).unwrap();
+        let target = dir.join("tampered.db");
+        assert!(matches!(
+            restore_backup(&manifest_file, &target),
+            Err(TrieCacheError::InvalidBackup)
+        ));
+        assert!(!target.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_backup_during_batch_writes() {
+        let test_ctx = db::test::TestContext::new();
+        let dir = std::env::temp_dir().join(format!("{}_backups", random::<u32>()));
+
+        // More writers than the pool has connections wait for the backup without holding one
+        let paused = {
+            let manager = test_ctx.manager.clone();
+            tokio::task::spawn_blocking(move || {
+                let conn = manager.get_connection().unwrap();
+                let _paused = manager.pause_batch_writes();
+                std::thread::sleep(Duration::from_millis(200));
+                db::batch::get_latest_batch_id(&conn).unwrap()
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let writers: Vec<_> = (0..20)
+            .map(|_| {
+                let manager = test_ctx.manager.clone();
+                tokio::spawn(async move {
+                    let _writing = manager.batch_write().await;
+                    let conn = manager.get_connection().unwrap();
+                    db::batch::get_latest_batch_id(&conn).unwrap()
+                })
+            })
+            .collect();
+        let manager = test_ctx.manager.clone();
+        let backup_dir = dir.clone();
+        let manifest = tokio::task::spawn_blocking(move || create_backup(&manager, &backup_dir))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(manifest.latest_batch_id, None);
+
+        assert_eq!(paused.await.unwrap(), None);
+        for writer in writers {
+            assert_eq!(writer.await.unwrap(), None);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backups_of_the_same_second() {
+        let test_ctx = db::test::TestContext::new();
+        let dir = std::env::temp_dir().join(format!("{}_backups", random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A backup of the same second gets the next free suffix instead of replacing the file
+        let (first, _) = reserve_backup_file(&dir, 42).unwrap();
+        let (second, _) = reserve_backup_file(&dir, 42).unwrap();
+        assert_eq!(first, "backup-42");
+        assert_eq!(second, "backup-42-1");
+
+        let manifests: Vec<_> = (0..3)
+            .map(|_| create_backup(&test_ctx.manager, &dir).unwrap())
+            .collect();
+        for (idx, manifest) in manifests.iter().enumerate() {
+            assert!(manifests[idx + 1..].iter().all(|m| m.file != manifest.file));
+            let manifest_file = dir.join(manifest.file.replace(".db", ".json"));
+            let target = dir.join(format!("restored-{}.db", idx));
+            assert_eq!(&restore_backup(&manifest_file, &target).unwrap(), manifest);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
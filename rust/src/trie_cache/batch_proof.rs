@@ -1,13 +1,18 @@
 use pathfinder_common::hash::FeltHash;
 use pathfinder_common::trie::TrieNode;
 use pathfinder_crypto::Felt;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::errors::TrieCacheError;
+use crate::models::batch::{LeafOrder, ProofFormat};
+use crate::models::receipt::Receipt;
+use crate::trie_cache::codec;
 use crate::trie_cache::item::CachedItem;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use tracing::info;
 
 /// Represents a leaf update in the batch proof.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct LeafUpdate {
     pub key: String,
     pub pre_value: String,
@@ -25,14 +30,46 @@ impl From<&CachedItem> for LeafUpdate {
     }
 }
 
+/// The pre- and post-update proofs of a single key.
+#[derive(Debug)]
+pub struct LeafProof {
+    pub key: Felt,
+    pub pre: Vec<TrieNode>,
+    pub post: Vec<TrieNode>,
+}
+
 /// Represents a batch proof.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct BatchProof {
     pub id: u64,
     pub pre_root: String,
     pub post_root: String,
-    pub preimage: HashMap<String, Vec<String>>,
+    /// The preimage nodes, keyed by their hash. A `BTreeMap` keeps the serialized entries sorted.
+    pub preimage: BTreeMap<String, Vec<String>>,
     pub leaf_updates: Vec<LeafUpdate>,
+    /// The Poseidon hash of the canonical binary encoding of the proof, see `codec::hash`.
+    #[serde(default)]
+    pub proof_hash: String,
+    /// The signed creation receipt of the batch, if the server has a signing key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<Receipt>,
+    /// The full pre- and post-update proofs, used by the cairo1 format.
+    #[serde(skip)]
+    pub proofs: Vec<LeafProof>,
+}
+
+/// A batch proof in the format requested by the client.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum FormattedProof<'a> {
+    /// The hex-string document read by the cairo0 hints.
+    Cairo0(&'a BatchProof),
+    /// The felt252 array, as `0x`-prefixed hex strings, and the receipt of the proof.
+    Cairo1 {
+        felts: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        receipt: Option<&'a Receipt>,
+    },
 }
 
 impl BatchProof {
@@ -43,54 +80,465 @@ impl BatchProof {
     /// * `pre_root` - The pre-update root hash.
     /// * `post_root` - The post-update root hash.
     /// * `leaf_updates` - The list of leaf updates.
-    /// * `proofs` - The list of proofs.
+    /// * `proofs` - The pre- and post-update proofs of every updated key.
     /// * `batch_id` - The batch ID.
     ///
     /// # Returns
     ///
-    /// A new `BatchProof` instance.
+    /// A new `BatchProof` instance, or a `TrieCacheError` if the proof can't be encoded.
     pub fn new<H: FeltHash>(
         pre_root: Felt,
         post_root: Felt,
         leaf_updates: Vec<LeafUpdate>,
-        proofs: Vec<Vec<TrieNode>>,
+        proofs: Vec<LeafProof>,
         batch_id: &u64,
-    ) -> Self {
+    ) -> Result<Self, TrieCacheError> {
         let mut batch_proof = BatchProof {
             id: *batch_id,
             pre_root: hex::encode(pre_root.to_be_bytes()),
             post_root: hex::encode(post_root.to_be_bytes()),
-            preimage: HashMap::new(),
+            preimage: BTreeMap::new(),
             leaf_updates,
+            proof_hash: String::new(),
+            receipt: None,
+            proofs,
+        };
+        batch_proof.generate_preimage_and_updates::<H>()?;
+        batch_proof.proof_hash = batch_proof.canonical_hash()?;
+        Ok(batch_proof)
+    }
+
+    /// Orders the leaf updates and the proofs of the updated keys, and updates the proof hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The requested order.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) once ordered, or a `TrieCacheError` if the proof holds malformed hex values.
+    pub fn order_leaf_updates(&mut self, order: &LeafOrder) -> Result<(), TrieCacheError> {
+        match order {
+            LeafOrder::Insertion => {}
+            LeafOrder::Key => {
+                // Keys are fixed-width hex strings, so they sort like the felts they encode
+                self.leaf_updates.sort_by(|a, b| a.key.cmp(&b.key));
+                self.proofs.sort_by_key(|proof| proof.key);
+                self.proof_hash = self.canonical_hash()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Computes the hash committing to the canonical binary encoding of the proof.
+    ///
+    /// # Returns
+    ///
+    /// The hex-encoded hash, or a `TrieCacheError` if the proof holds malformed hex values.
+    pub fn canonical_hash(&self) -> Result<String, TrieCacheError> {
+        let hash = codec::hash(&codec::encode_unsigned(self)?);
+        Ok(hex::encode(hash.to_be_bytes()))
+    }
+
+    /// Formats the proof for the requested verifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The requested format.
+    ///
+    /// # Returns
+    ///
+    /// The formatted proof, or a `TrieCacheError` if the roots of the proof can't be parsed.
+    pub fn format(&self, format: &ProofFormat) -> Result<FormattedProof<'_>, TrieCacheError> {
+        match format {
+            ProofFormat::Cairo0 => Ok(FormattedProof::Cairo0(self)),
+            ProofFormat::Cairo1 => Ok(FormattedProof::Cairo1 {
+                felts: self
+                    .to_cairo1_felts()?
+                    .iter()
+                    .map(|felt| format!("0x{}", hex::encode(felt.to_be_bytes())))
+                    .collect(),
+                receipt: self.receipt.as_ref(),
+            }),
+        }
+    }
+
+    /// Serializes the proof into the felt252 layout Cairo's `Serde` expects for the `BatchUpdate`
+    /// struct of `cairo1/src/lib.cairo`.
+    ///
+    /// Structs are laid out field by field and arrays are prefixed with their length:
+    /// `[pre_root, post_root, n_updates, updates...]`, where each update is
+    /// `[key, n_pre, proof_pre..., n_post, proof_post...]`. `TrieNode` variants are prefixed with
+    /// their index: binary nodes are `[0, left, right]` and edge nodes `[1, path, child, length]`,
+    /// in the argument order of `BinaryNodeImpl::new` and `EdgeNodeImpl::new`, with the path as
+    /// the felt of its bits and the length as a number.
+    ///
+    /// The `main` of `cairo1/src/lib.cairo` still verifies a hard-coded `BatchUpdate` rather than
+    /// reading its arguments, so the layout is tested against that literal and the struct
+    /// declarations instead of a run of the program.
+    ///
+    /// # Returns
+    ///
+    /// The felts of the proof, or a `TrieCacheError` if the roots of the proof can't be parsed.
+    pub fn to_cairo1_felts(&self) -> Result<Vec<Felt>, TrieCacheError> {
+        let mut felts = vec![
+            Felt::from_hex_str(&self.pre_root).map_err(anyhow::Error::from)?,
+            Felt::from_hex_str(&self.post_root).map_err(anyhow::Error::from)?,
+            Felt::from_u64(self.proofs.len() as u64),
+        ];
+
+        for proof in &self.proofs {
+            felts.push(proof.key);
+            for nodes in [&proof.pre, &proof.post] {
+                felts.push(Felt::from_u64(nodes.len() as u64));
+                for node in nodes {
+                    match node {
+                        TrieNode::Binary { left, right } => {
+                            felts.extend([Felt::ZERO, *left, *right]);
+                        }
+                        TrieNode::Edge { child, path } => felts.extend([
+                            Felt::from_u64(1),
+                            Felt::from_bits(path).map_err(anyhow::Error::from)?,
+                            *child,
+                            Felt::from_u64(path.len() as u64),
+                        ]),
+                    }
+                }
+            }
+        }
+
+        Ok(felts)
+    }
+
+    /// Fills the preimage with every node on the pre- and post-update paths. Nodes shared by
+    /// several paths are only stored once.
+    ///
+    /// This is the smallest witness `patricia_update` accepts: its descent hints decompose every
+    /// node they reach under both the previous and the new root through the preimage, including
+    /// post-update nodes of subtrees that were empty before the update, so no path node can be
+    /// left out. The number of path nodes and of preimage nodes is logged.
+    fn generate_preimage_and_updates<H: FeltHash>(&mut self) -> Result<(), TrieCacheError> {
+        let mut preimage = BTreeMap::new();
+        let mut path_nodes = 0;
+        for node in self
+            .proofs
+            .iter()
+            .flat_map(|proof| proof.pre.iter().chain(proof.post.iter()))
+        {
+            insert_node::<H>(&mut preimage, node)?;
+            path_nodes += 1;
+        }
+        self.preimage = preimage;
+
+        info!(
+            "Batch #{} witness: {} preimage nodes for {} path nodes",
+            self.id,
+            self.preimage.len(),
+            path_nodes
+        );
+        Ok(())
+    }
+}
+
+/// Adds a node to a preimage, keyed by its hash.
+fn insert_node<H: FeltHash>(
+    preimage: &mut BTreeMap<String, Vec<String>>,
+    node: &TrieNode,
+) -> Result<(), TrieCacheError> {
+    let hash = node.hash::<H>();
+    match node {
+        TrieNode::Binary { left, right } => {
+            preimage.insert(
+                hex::encode(hash.to_be_bytes()),
+                vec![
+                    hex::encode(left.to_be_bytes()),
+                    hex::encode(right.to_be_bytes()),
+                ],
+            );
+        }
+        TrieNode::Edge { child, path } => {
+            preimage.insert(
+                hex::encode(hash.to_be_bytes()),
+                vec![
+                    hex::encode(path.len().to_be_bytes()),
+                    hex::encode(
+                        Felt::from_bits(path)
+                            .map_err(anyhow::Error::from)?
+                            .to_be_bytes(),
+                    ),
+                    hex::encode(child.to_be_bytes()),
+                ],
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+    use crate::trie_cache::TrieCache;
+    use bitvec::prelude::*;
+    use pathfinder_common::hash::PoseidonHash;
+    use std::collections::BTreeSet;
+
+    fn edge(path: &BitSlice<u8, Msb0>) -> TrieNode {
+        TrieNode::Edge {
+            child: Felt::from_u64(1),
+            path: path.to_bitvec(),
+        }
+    }
+
+    fn binary() -> TrieNode {
+        TrieNode::Binary {
+            left: Felt::from_u64(2),
+            right: Felt::from_u64(3),
+        }
+    }
+
+    #[test]
+    fn test_preimage_holds_every_proof_node() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let items: Vec<_> = (0..4).map(|value| CachedItem::new(vec![value])).collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        let items: Vec<_> = (2..8)
+            .map(|value| CachedItem::new(vec![value, 1]))
+            .collect();
+        let proof = TrieCache::create_batch(&conn, items, None, None)
+            .unwrap()
+            .remove(0);
+
+        // The verifier walks both roots, including subtrees that were empty before the batch
+        assert!(proof.preimage.contains_key(&proof.pre_root));
+        assert!(proof.preimage.contains_key(&proof.post_root));
+        let path_nodes: Vec<_> = proof
+            .proofs
+            .iter()
+            .flat_map(|proof| proof.pre.iter().chain(proof.post.iter()))
+            .map(|node| hex::encode(node.hash::<PoseidonHash>().to_be_bytes()))
+            .collect();
+        for hash in &path_nodes {
+            assert!(proof.preimage.contains_key(hash));
+        }
+
+        // Nothing but the path nodes, each of them once
+        let distinct: BTreeSet<_> = path_nodes.iter().cloned().collect();
+        assert_eq!(
+            proof.preimage.keys().cloned().collect::<BTreeSet<_>>(),
+            distinct
+        );
+        assert!(proof.preimage.len() < path_nodes.len());
+    }
+
+    #[test]
+    fn test_cairo1_felts() {
+        let key = Felt::from_u64(5);
+        let bits = key.view_bits();
+        let proof = BatchProof {
+            id: 1,
+            pre_root: hex::encode(Felt::from_u64(10).to_be_bytes()),
+            post_root: hex::encode(Felt::from_u64(11).to_be_bytes()),
+            preimage: BTreeMap::new(),
+            leaf_updates: vec![],
+            proof_hash: String::new(),
+            receipt: None,
+            proofs: vec![LeafProof {
+                key,
+                pre: vec![binary()],
+                post: vec![binary(), edge(&bits[1..])],
+            }],
         };
-        batch_proof.generate_preimage_and_updates::<H>(proofs);
-        batch_proof
-    }
-
-    fn generate_preimage_and_updates<H: FeltHash>(&mut self, proofs: Vec<Vec<TrieNode>>) {
-        proofs.iter().flat_map(|r| r.iter()).for_each(|node| {
-            let hash = node.hash::<H>();
-            match node {
-                TrieNode::Binary { left, right } => {
-                    let _ = &self.preimage.insert(
-                        hex::encode(hash.to_be_bytes()),
-                        vec![
-                            hex::encode(left.to_be_bytes()),
-                            hex::encode(right.to_be_bytes()),
-                        ],
-                    );
+
+        let binary_felts = [Felt::ZERO, Felt::from_u64(2), Felt::from_u64(3)];
+        let mut expected = vec![
+            Felt::from_u64(10),
+            Felt::from_u64(11),
+            Felt::from_u64(1),
+            key,
+        ];
+        expected.push(Felt::from_u64(1));
+        expected.extend(binary_felts);
+        expected.push(Felt::from_u64(2));
+        expected.extend(binary_felts);
+        expected.extend([
+            Felt::from_u64(1),
+            Felt::from_bits(&bits[1..]).unwrap(),
+            Felt::from_u64(1),
+            Felt::from_u64(250),
+        ]);
+        assert_eq!(proof.to_cairo1_felts().unwrap(), expected);
+
+        match proof.format(&ProofFormat::Cairo1).unwrap() {
+            FormattedProof::Cairo1 { felts, receipt } => {
+                assert!(receipt.is_none());
+                assert_eq!(felts.len(), expected.len());
+                assert_eq!(
+                    felts[0],
+                    format!("0x{}", hex::encode(Felt::from_u64(10).to_be_bytes()))
+                );
+            }
+            FormattedProof::Cairo0(_) => panic!("expected the cairo1 format"),
+        }
+    }
+
+    /// Returns the field names of a struct declared in a Cairo source, in declaration order.
+    fn cairo_fields<'a>(source: &'a str, name: &str) -> Vec<&'a str> {
+        let start = source.find(&format!("pub struct {} {{", name)).unwrap();
+        let body = &source[start..];
+        let body = &body[body.find('{').unwrap() + 1..body.find('}').unwrap()];
+        body.split(',')
+            .filter_map(|field| field.split(':').next())
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .collect()
+    }
+
+    /// Reads the proof nodes of a Cairo `array![...]` literal into their felt252 layout.
+    fn cairo_nodes(literal: &str) -> Vec<Vec<Felt>> {
+        literal
+            .split("TrieNode::")
+            .skip(1)
+            .map(|node| {
+                let args = &node[node.find('(').unwrap() + 1..];
+                let args: Vec<&str> = args[args.find('(').unwrap() + 1..args.find(')').unwrap()]
+                    .split(',')
+                    .map(str::trim)
+                    .collect();
+                match args.as_slice() {
+                    [left, right] if node.starts_with("Binary") => vec![
+                        Felt::ZERO,
+                        Felt::from_hex_str(left).unwrap(),
+                        Felt::from_hex_str(right).unwrap(),
+                    ],
+                    [path, child, length] if node.starts_with("Edge") => vec![
+                        Felt::from_u64(1),
+                        Felt::from_hex_str(path).unwrap(),
+                        Felt::from_hex_str(child).unwrap(),
+                        Felt::from_u64(length.parse().unwrap()),
+                    ],
+                    _ => panic!("unexpected node {}", node),
                 }
-                TrieNode::Edge { child, path } => {
-                    let _ = &self.preimage.insert(
-                        hex::encode(hash.to_be_bytes()),
-                        vec![
-                            hex::encode(path.len().to_be_bytes()),
-                            hex::encode(Felt::from_bits(path).unwrap().to_be_bytes()),
-                            hex::encode(child.to_be_bytes()),
-                        ],
-                    );
+            })
+            .collect()
+    }
+
+    /// Converts a node from its felt252 layout back into a trie node.
+    fn trie_node(felts: &[Felt]) -> TrieNode {
+        match felts {
+            [tag, left, right] if *tag == Felt::ZERO => TrieNode::Binary {
+                left: *left,
+                right: *right,
+            },
+            [_, path, child, length] => {
+                let length = u64::from_be_bytes(length.to_be_bytes()[24..].try_into().unwrap());
+                TrieNode::Edge {
+                    child: *child,
+                    path: path.view_bits()[path.view_bits().len() - length as usize..].to_bitvec(),
                 }
             }
-        });
+            _ => panic!("unexpected node layout"),
+        }
+    }
+
+    #[test]
+    fn test_cairo1_felts_match_lib_cairo() {
+        let source = include_str!("../../../cairo1/src/lib.cairo");
+
+        // Serde lays structs out in declaration order
+        assert_eq!(
+            cairo_fields(source, "BatchUpdate"),
+            vec!["pre_root", "post_root", "leaf_updates"]
+        );
+        assert_eq!(
+            cairo_fields(source, "LeafUpdate"),
+            vec!["key", "proof_pre", "proof_post"]
+        );
+
+        // The batch update that `main` verifies, read into the documented layout
+        let main = &source[source.find("fn main()").unwrap()..source.find("#[cfg(test)]").unwrap()];
+        let mut updates = main.split("LeafUpdate {");
+        let header = updates.next().unwrap();
+        let field = |text: &str, name: &str| {
+            let value = &text[text.find(&format!("{}:", name)).unwrap() + name.len() + 1..];
+            Felt::from_hex_str(value[..value.find(',').unwrap()].trim()).unwrap()
+        };
+        let mut expected = vec![field(header, "pre_root"), field(header, "post_root")];
+        let mut proofs = vec![];
+        let mut update_felts = vec![];
+        for update in updates {
+            let (pre, post) = update.split_at(update.find("proof_post:").unwrap());
+            let (pre, post) = (cairo_nodes(pre), cairo_nodes(post));
+            let key = field(update, "key");
+
+            update_felts.push(key);
+            for nodes in [&pre, &post] {
+                update_felts.push(Felt::from_u64(nodes.len() as u64));
+                update_felts.extend(nodes.iter().flatten());
+            }
+            proofs.push(LeafProof {
+                key,
+                pre: pre.iter().map(|node| trie_node(node)).collect(),
+                post: post.iter().map(|node| trie_node(node)).collect(),
+            });
+        }
+        assert!(!proofs.is_empty());
+        expected.push(Felt::from_u64(proofs.len() as u64));
+        expected.extend(update_felts);
+
+        let proof = BatchProof {
+            id: 1,
+            pre_root: hex::encode(expected[0].to_be_bytes()),
+            post_root: hex::encode(expected[1].to_be_bytes()),
+            preimage: BTreeMap::new(),
+            leaf_updates: vec![],
+            proof_hash: String::new(),
+            receipt: None,
+            proofs,
+        };
+        assert_eq!(proof.to_cairo1_felts().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let items: Vec<_> = (0..8).map(|value| CachedItem::new(vec![value])).collect();
+        let mut proof = TrieCache::create_batch(&conn, items, None, None)
+            .unwrap()
+            .remove(0);
+
+        let hashes: Vec<_> = proof.preimage.keys().cloned().collect();
+        assert!(hashes.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(proof.proof_hash, proof.canonical_hash().unwrap());
+
+        let insertion_hash = proof.proof_hash.clone();
+        proof.order_leaf_updates(&LeafOrder::Key).unwrap();
+        let keys: Vec<_> = proof
+            .leaf_updates
+            .iter()
+            .map(|update| &update.key)
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(proof
+            .proofs
+            .windows(2)
+            .all(|pair| pair[0].key < pair[1].key));
+        assert_ne!(proof.proof_hash, insertion_hash);
+        assert_eq!(proof.proof_hash, proof.canonical_hash().unwrap());
+
+        // Ordering is idempotent, and equal proofs serialize to equal documents
+        let json = serde_json::to_string(&proof).unwrap();
+        proof.order_leaf_updates(&LeafOrder::Key).unwrap();
+        assert_eq!(serde_json::to_string(&proof).unwrap(), json);
+
+        // A malformed proof is reported instead of panicking
+        proof.pre_root = "not hex".to_string();
+        assert!(proof.order_leaf_updates(&LeafOrder::Key).is_err());
     }
 }
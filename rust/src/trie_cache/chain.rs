@@ -0,0 +1,287 @@
+use pathfinder_crypto::Felt;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::db;
+use crate::errors::TrieCacheError;
+use crate::models::batch::{batch_commitment, BatchQuery, BatchStatus};
+use crate::models::page::SortOrder;
+use crate::trie_cache::parse_felt;
+
+/// The result of validating the batch chain from genesis.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChainVerification {
+    /// The number of batches whose commitment was checked before the first break, if any.
+    pub verified_batches: u64,
+    /// The first batch whose link is broken, in ID order.
+    pub first_break: Option<ChainBreak>,
+}
+
+/// A batch whose link to its parent doesn't hold.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChainBreak {
+    pub batch_id: u64,
+    pub reason: ChainBreakReason,
+    /// The commitment recomputed from the parent, if it could be computed.
+    pub expected_commitment: Option<String>,
+    pub stored_commitment: Option<String>,
+}
+
+/// Why the link of a batch doesn't hold.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainBreakReason {
+    /// The parent of the batch doesn't exist.
+    MissingParent,
+    /// The batch has no parent, although an earlier batch that wasn't reverted exists.
+    UnexpectedGenesis,
+    /// The pre-state root of the batch isn't the post-state root of its parent.
+    RootMismatch,
+    /// The batch has no stored commitment.
+    MissingCommitment,
+    /// The stored commitment doesn't match the one recomputed from the parent.
+    CommitmentMismatch,
+}
+
+/// Validates the commitments of all batches, from genesis on, and reports the first break.
+///
+/// Batches are checked in ID order. Every batch must link to an earlier batch, start from the
+/// root its parent ended at, and store the commitment recomputed from its parent's commitment.
+/// Only a batch created while every earlier batch was reverted may have no parent.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a pooled SQLite connection.
+///
+/// # Returns
+///
+/// The number of verified batches and the first break, or a TrieCacheError if an error occurs.
+pub fn verify_chain(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<ChainVerification, TrieCacheError> {
+    // The commitment and post-state root of every verified batch
+    let mut links: HashMap<u64, (String, String)> = HashMap::new();
+    // Whether a verified batch that wasn't reverted exists, so a new batch has to link to it
+    let mut has_head = false;
+    let mut query = BatchQuery {
+        limit: Some(1000),
+        order: SortOrder::Asc,
+        ..Default::default()
+    };
+
+    loop {
+        let page = db::batch::get_batches(conn, &query)?;
+        for batch in page.items {
+            let parent_commitment = match batch.parent_id {
+                Some(parent_id) => match links.get(&parent_id) {
+                    Some((_, parent_root)) if *parent_root != batch.pre_root => {
+                        return Ok(broken(
+                            &links,
+                            batch.id,
+                            ChainBreakReason::RootMismatch,
+                            None,
+                            batch.batch_commitment,
+                        ));
+                    }
+                    Some((commitment, _)) => parse_felt(commitment)?,
+                    None => {
+                        return Ok(broken(
+                            &links,
+                            batch.id,
+                            ChainBreakReason::MissingParent,
+                            None,
+                            batch.batch_commitment,
+                        ));
+                    }
+                },
+                None if has_head => {
+                    return Ok(broken(
+                        &links,
+                        batch.id,
+                        ChainBreakReason::UnexpectedGenesis,
+                        None,
+                        batch.batch_commitment,
+                    ));
+                }
+                None => Felt::ZERO,
+            };
+
+            let expected = hex::encode(
+                batch_commitment(
+                    parent_commitment,
+                    batch.id,
+                    parse_felt(&batch.pre_root)?,
+                    parse_felt(&batch.root_hash)?,
+                    batch.item_count,
+                )
+                .to_be_bytes(),
+            );
+            match batch.batch_commitment {
+                Some(stored) if stored == expected => {
+                    has_head |= batch.status != BatchStatus::Reverted;
+                    links.insert(batch.id, (stored, batch.root_hash));
+                }
+                Some(stored) => {
+                    return Ok(broken(
+                        &links,
+                        batch.id,
+                        ChainBreakReason::CommitmentMismatch,
+                        Some(expected),
+                        Some(stored),
+                    ));
+                }
+                None => {
+                    return Ok(broken(
+                        &links,
+                        batch.id,
+                        ChainBreakReason::MissingCommitment,
+                        Some(expected),
+                        None,
+                    ));
+                }
+            }
+        }
+
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+
+    Ok(ChainVerification {
+        verified_batches: links.len() as u64,
+        first_break: None,
+    })
+}
+
+fn broken(
+    links: &HashMap<u64, (String, String)>,
+    batch_id: u64,
+    reason: ChainBreakReason,
+    expected_commitment: Option<String>,
+    stored_commitment: Option<String>,
+) -> ChainVerification {
+    ChainVerification {
+        verified_batches: links.len() as u64,
+        first_break: Some(ChainBreak {
+            batch_id,
+            reason,
+            expected_commitment,
+            stored_commitment,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::batch::NewBatch;
+    use rusqlite::params;
+
+    #[test]
+    fn test_verify_chain() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        assert_eq!(
+            verify_chain(&conn).unwrap(),
+            ChainVerification {
+                verified_batches: 0,
+                first_break: None,
+            }
+        );
+
+        let batches = test_ctx.batch_seeding();
+        let genesis = batch_commitment(Felt::ZERO, 1, Felt::ZERO, Felt::from_u64(1), 1);
+        assert_eq!(
+            batches[0].batch_commitment,
+            Some(hex::encode(genesis.to_be_bytes()))
+        );
+        assert_eq!(verify_chain(&conn).unwrap().verified_batches, 3);
+
+        // Tampering with a batch breaks its link
+        conn.execute("UPDATE batches SET item_count = 2 WHERE id = 2", params![])
+            .unwrap();
+        let verification = verify_chain(&conn).unwrap();
+        assert_eq!(verification.verified_batches, 1);
+        let first_break = verification.first_break.unwrap();
+        assert_eq!(first_break.batch_id, 2);
+        assert_eq!(first_break.reason, ChainBreakReason::CommitmentMismatch);
+        assert_eq!(first_break.stored_commitment, batches[1].batch_commitment);
+
+        conn.execute("UPDATE batches SET item_count = 1 WHERE id = 2", params![])
+            .unwrap();
+        conn.execute(
+            "UPDATE batches SET batch_commitment = NULL WHERE id = 3",
+            params![],
+        )
+        .unwrap();
+        let first_break = verify_chain(&conn).unwrap().first_break.unwrap();
+        assert_eq!(first_break.batch_id, 3);
+        assert_eq!(first_break.reason, ChainBreakReason::MissingCommitment);
+        assert_eq!(first_break.expected_commitment, batches[2].batch_commitment);
+        conn.execute(
+            "UPDATE batches SET batch_commitment = ? WHERE id = 3",
+            params![hex::decode(batches[2].batch_commitment.as_ref().unwrap()).unwrap()],
+        )
+        .unwrap();
+        assert_eq!(verify_chain(&conn).unwrap().verified_batches, 3);
+
+        // A parentless batch after the head forks the chain, even with a consistent commitment
+        let orphan = NewBatch {
+            parent_id: None,
+            root_idx: 0,
+            root_hash: Felt::from_u64(7),
+            pre_root_idx: 0,
+            pre_root: Felt::ZERO,
+            item_count: 1,
+            nodes_added: 0,
+            group_id: None,
+        };
+        let orphan_id = db::batch::create_batch(&conn, &orphan).unwrap();
+        let verification = verify_chain(&conn).unwrap();
+        assert_eq!(verification.verified_batches, 3);
+        let first_break = verification.first_break.unwrap();
+        assert_eq!(first_break.batch_id, orphan_id);
+        assert_eq!(first_break.reason, ChainBreakReason::UnexpectedGenesis);
+
+        conn.execute(
+            "UPDATE batches SET pre_root = zeroblob(32) WHERE id = 3",
+            params![],
+        )
+        .unwrap();
+        assert_eq!(
+            verify_chain(&conn).unwrap().first_break.unwrap().reason,
+            ChainBreakReason::RootMismatch
+        );
+    }
+
+    #[test]
+    fn test_verify_chain_after_revert() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let genesis = NewBatch {
+            parent_id: None,
+            root_idx: 0,
+            root_hash: Felt::from_u64(1),
+            pre_root_idx: 0,
+            pre_root: Felt::ZERO,
+            item_count: 1,
+            nodes_added: 0,
+            group_id: None,
+        };
+
+        // Once every batch is reverted, the next batch starts a new chain
+        let reverted = db::batch::create_batch(&conn, &genesis).unwrap();
+        db::batch::update_batch_status(&conn, &reverted, BatchStatus::Reverted).unwrap();
+        db::batch::create_batch(&conn, &genesis).unwrap();
+        assert_eq!(
+            verify_chain(&conn).unwrap(),
+            ChainVerification {
+                verified_batches: 2,
+                first_break: None,
+            }
+        );
+    }
+}
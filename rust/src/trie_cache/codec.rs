@@ -0,0 +1,391 @@
+use pathfinder_crypto::hash::poseidon_hash_many;
+use pathfinder_crypto::{Felt, MontFelt};
+use std::collections::BTreeMap;
+
+use crate::errors::TrieCacheError;
+use crate::models::batch::BatchStatus;
+use crate::models::receipt::Receipt;
+use crate::trie_cache::batch_proof::{BatchProof, LeafUpdate};
+
+/// The media type clients send in the `Accept` header to receive binary encoded proofs.
+pub const MEDIA_TYPE: &str = "application/octet-stream";
+
+/// The bytes every binary encoded proof starts with.
+const MAGIC: &[u8; 4] = b"SMPT";
+/// The version of the encoding, bumped on every incompatible change.
+const VERSION: u8 = 2;
+
+const BINARY_NODE_TAG: u8 = 0;
+const EDGE_NODE_TAG: u8 = 1;
+
+const NO_RECEIPT_TAG: u8 = 0;
+const RECEIPT_TAG: u8 = 1;
+
+/// Encodes a `BatchProof` into the compact binary format.
+///
+/// The encoding starts with the `SMPT` magic and a version byte, followed by the varint ID, the
+/// pre- and post-root, the varint-prefixed leaf updates as `key, pre_value, post_value`, and the
+/// varint-prefixed preimage. Each preimage entry is its hash, followed by a node tag and either
+/// `left, right` for binary nodes or a varint path length, the path and the child for edge nodes.
+/// Felts are written as raw 32-byte big-endian values and varints as unsigned LEB128. Preimage
+/// entries are written in ascending hash order, so equal proofs have equal encodings.
+///
+/// The proof ends with its receipt, tagged `0` if the proof has none and `1` otherwise, followed
+/// by the varint batch ID, the varint parent ID (0 for none), the status byte, the pre- and
+/// post-root, the public key and the `r` and `s` values of the signature. The proof hash isn't
+/// encoded, as it commits to the encoding without the receipt, see `encode_unsigned`.
+///
+/// # Arguments
+///
+/// * `proof` - The proof to encode.
+///
+/// # Returns
+///
+/// The encoded proof, or a `TrieCacheError` if the proof holds malformed hex values.
+pub fn encode(proof: &BatchProof) -> Result<Vec<u8>, TrieCacheError> {
+    let mut out = encode_unsigned(proof)?;
+    match &proof.receipt {
+        None => out.push(NO_RECEIPT_TAG),
+        Some(receipt) => {
+            out.push(RECEIPT_TAG);
+            write_varint(&mut out, receipt.batch_id);
+            write_varint(&mut out, receipt.parent_id.unwrap_or(0));
+            out.push(receipt.status.code() as u8);
+            write_felt(&mut out, &receipt.pre_root)?;
+            write_felt(&mut out, &receipt.post_root)?;
+            write_felt(&mut out, &receipt.public_key)?;
+            write_felt(&mut out, &receipt.signature_r)?;
+            write_felt(&mut out, &receipt.signature_s)?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Encodes a `BatchProof` without its receipt, as committed to by the proof hash.
+///
+/// The receipt is signed after the proof is built, so it can't be part of what the proof hash
+/// commits to.
+///
+/// # Arguments
+///
+/// * `proof` - The proof to encode.
+///
+/// # Returns
+///
+/// The encoded proof, or a `TrieCacheError` if the proof holds malformed hex values.
+pub fn encode_unsigned(proof: &BatchProof) -> Result<Vec<u8>, TrieCacheError> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    write_varint(&mut out, proof.id);
+    write_felt(&mut out, &proof.pre_root)?;
+    write_felt(&mut out, &proof.post_root)?;
+
+    write_varint(&mut out, proof.leaf_updates.len() as u64);
+    for update in &proof.leaf_updates {
+        write_felt(&mut out, &update.key)?;
+        write_felt(&mut out, &update.pre_value)?;
+        write_felt(&mut out, &update.post_value)?;
+    }
+
+    write_varint(&mut out, proof.preimage.len() as u64);
+    for (hash, node) in &proof.preimage {
+        write_felt(&mut out, hash)?;
+        match node.as_slice() {
+            [left, right] => {
+                out.push(BINARY_NODE_TAG);
+                write_felt(&mut out, left)?;
+                write_felt(&mut out, right)?;
+            }
+            [length, path, child] => {
+                let length = u64::from_str_radix(length, 16)
+                    .map_err(|_| TrieCacheError::InvalidProofEncoding)?;
+                out.push(EDGE_NODE_TAG);
+                write_varint(&mut out, length);
+                write_felt(&mut out, path)?;
+                write_felt(&mut out, child)?;
+            }
+            _ => return Err(TrieCacheError::InvalidProofEncoding),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Encodes several proofs by concatenating their encodings.
+///
+/// # Arguments
+///
+/// * `proofs` - The proofs to encode.
+///
+/// # Returns
+///
+/// The encoded proofs, or a `TrieCacheError` if a proof holds malformed hex values.
+pub fn encode_all(proofs: &[BatchProof]) -> Result<Vec<u8>, TrieCacheError> {
+    let mut out = vec![];
+    for proof in proofs {
+        out.extend(encode(proof)?);
+    }
+
+    Ok(out)
+}
+
+/// Hashes an encoding with Poseidon.
+///
+/// The hashed felts are the length of the encoding in bytes, followed by the encoding split into
+/// 31-byte big-endian chunks, so every chunk fits into a felt.
+///
+/// # Arguments
+///
+/// * `bytes` - The encoding to hash.
+///
+/// # Returns
+///
+/// The hash of the encoding.
+pub fn hash(bytes: &[u8]) -> Felt {
+    let mut felts = vec![MontFelt::from(Felt::from_u64(bytes.len() as u64))];
+    for chunk in bytes.chunks(31) {
+        let felt = Felt::from_be_slice(chunk).expect("31 bytes always fit into a felt");
+        felts.push(felt.into());
+    }
+
+    poseidon_hash_many(&felts).into()
+}
+
+/// Decodes a `BatchProof` from the compact binary format, recomputing its proof hash.
+///
+/// # Arguments
+///
+/// * `bytes` - The encoded proof.
+///
+/// # Returns
+///
+/// The decoded proof, or a `TrieCacheError` if the bytes aren't a single valid encoding.
+pub fn decode(bytes: &[u8]) -> Result<BatchProof, TrieCacheError> {
+    let mut reader = Reader { bytes };
+    let proof = reader.read_proof()?;
+    if !reader.bytes.is_empty() {
+        return Err(TrieCacheError::InvalidProofEncoding);
+    }
+
+    Ok(proof)
+}
+
+/// Decodes a sequence of concatenated proofs, as written by `encode_all`.
+///
+/// # Arguments
+///
+/// * `bytes` - The encoded proofs.
+///
+/// # Returns
+///
+/// The decoded proofs, or a `TrieCacheError` if the bytes aren't a sequence of valid encodings.
+pub fn decode_all(bytes: &[u8]) -> Result<Vec<BatchProof>, TrieCacheError> {
+    let mut reader = Reader { bytes };
+    let mut proofs = vec![];
+    while !reader.bytes.is_empty() {
+        proofs.push(reader.read_proof()?);
+    }
+
+    Ok(proofs)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes a hex-encoded felt as 32 raw bytes.
+fn write_felt(out: &mut Vec<u8>, hex_value: &str) -> Result<(), TrieCacheError> {
+    let bytes = hex::decode(hex_value).map_err(|_| TrieCacheError::InvalidProofEncoding)?;
+    if bytes.len() > 32 {
+        return Err(TrieCacheError::InvalidProofEncoding);
+    }
+
+    // Left-pad shorter values, so every felt takes exactly 32 bytes
+    out.resize(out.len() + 32 - bytes.len(), 0);
+    out.extend(bytes);
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn read_proof(&mut self) -> Result<BatchProof, TrieCacheError> {
+        if self.take(MAGIC.len())? != MAGIC || self.take(1)?[0] != VERSION {
+            return Err(TrieCacheError::InvalidProofEncoding);
+        }
+
+        let id = self.read_varint()?;
+        let pre_root = self.read_felt()?;
+        let post_root = self.read_felt()?;
+
+        let mut leaf_updates = vec![];
+        for _ in 0..self.read_varint()? {
+            leaf_updates.push(LeafUpdate {
+                key: self.read_felt()?,
+                pre_value: self.read_felt()?,
+                post_value: self.read_felt()?,
+            });
+        }
+
+        let mut preimage = BTreeMap::new();
+        for _ in 0..self.read_varint()? {
+            let hash = self.read_felt()?;
+            let node = match self.take(1)?[0] {
+                BINARY_NODE_TAG => vec![self.read_felt()?, self.read_felt()?],
+                EDGE_NODE_TAG => {
+                    // Edge lengths are `usize` big-endian bytes in the JSON format
+                    let length = self.read_varint()? as usize;
+                    vec![
+                        hex::encode(length.to_be_bytes()),
+                        self.read_felt()?,
+                        self.read_felt()?,
+                    ]
+                }
+                _ => return Err(TrieCacheError::InvalidProofEncoding),
+            };
+            preimage.insert(hash, node);
+        }
+
+        let receipt = match self.take(1)?[0] {
+            NO_RECEIPT_TAG => None,
+            RECEIPT_TAG => Some(self.read_receipt()?),
+            _ => return Err(TrieCacheError::InvalidProofEncoding),
+        };
+
+        let mut proof = BatchProof {
+            id,
+            pre_root,
+            post_root,
+            preimage,
+            leaf_updates,
+            proof_hash: String::new(),
+            receipt,
+            proofs: vec![],
+        };
+        proof.proof_hash = proof.canonical_hash()?;
+        Ok(proof)
+    }
+
+    fn read_receipt(&mut self) -> Result<Receipt, TrieCacheError> {
+        let batch_id = self.read_varint()?;
+        let parent_id = Some(self.read_varint()?).filter(|&id| id != 0);
+        let status = match self.take(1)?[0] {
+            1 => BatchStatus::Created,
+            2 => BatchStatus::Finalized,
+            _ => return Err(TrieCacheError::InvalidProofEncoding),
+        };
+
+        Ok(Receipt {
+            batch_id,
+            parent_id,
+            status,
+            pre_root: self.read_felt()?,
+            post_root: self.read_felt()?,
+            public_key: self.read_felt()?,
+            signature_r: self.read_felt()?,
+            signature_s: self.read_felt()?,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], TrieCacheError> {
+        if self.bytes.len() < len {
+            return Err(TrieCacheError::InvalidProofEncoding);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, TrieCacheError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(TrieCacheError::InvalidProofEncoding)
+    }
+
+    fn read_felt(&mut self) -> Result<String, TrieCacheError> {
+        Ok(hex::encode(self.take(32)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+
+    #[test]
+    fn test_round_trip() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let items: Vec<_> = (0..5).map(|value| CachedItem::new(vec![value])).collect();
+        let proof = TrieCache::create_batch(&conn, items, None, None)
+            .unwrap()
+            .remove(0);
+
+        let encoded = encode(&proof).unwrap();
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&proof).unwrap()
+        );
+
+        // Raw felts take half the space of the hex strings
+        assert!(encoded.len() * 2 < serde_json::to_vec(&proof).unwrap().len());
+
+        let all = decode_all(&encode_all(&[proof, decoded]).unwrap()).unwrap();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[test]
+    fn test_round_trip_fixture() {
+        let mut proof: BatchProof =
+            serde_json::from_str(include_str!("../../../cairo0/src/mpt_input.json")).unwrap();
+        proof.proof_hash = proof.canonical_hash().unwrap();
+
+        let decoded = decode(&encode(&proof).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&proof).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_input() {
+        assert!(matches!(
+            decode(b"JSON"),
+            Err(TrieCacheError::InvalidProofEncoding)
+        ));
+
+        let mut encoded = MAGIC.to_vec();
+        encoded.push(VERSION + 1);
+        assert!(decode(&encoded).is_err());
+
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let proof = TrieCache::create_batch(&conn, vec![CachedItem::new(vec![1])], None, None)
+            .unwrap()
+            .remove(0);
+        let encoded = encode(&proof).unwrap();
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::errors::TrieCacheError;
+use crate::trie_cache::batch_proof::BatchProof;
+
+/// The environment variable naming the compiled verifier program, as written by `cairo-compile`.
+pub const VERIFIER_PROGRAM_ENV: &str = "SN_MPT_VERIFIER_PROGRAM";
+/// Where `make run` writes the compiled verifier if `SN_MPT_VERIFIER_PROGRAM` isn't set.
+const DEFAULT_VERIFIER_PROGRAM: &str = "../cairo0/build/compiled_cairo_files/mpt.json";
+
+/// Fixed cost of `main`: allocating and squashing the update dict and setting up the Patricia constants.
+const BASE_STEPS: u64 = 150;
+/// Cost of writing one update to the dict and squashing it.
+const STEPS_PER_UPDATE: u64 = 90;
+/// Cost of verifying one binary node in `patricia_update`.
+const STEPS_PER_BINARY_NODE: u64 = 40;
+/// Cost of verifying one edge node in `patricia_update`, including the checks on its path.
+const STEPS_PER_EDGE_NODE: u64 = 70;
+/// Range checks used by `dict_squash` for one update.
+const RANGE_CHECKS_PER_UPDATE: u64 = 6;
+/// Range checks used to bound the path of one edge node.
+const RANGE_CHECKS_PER_EDGE_NODE: u64 = 2;
+
+/// An estimate of the resources `cairo0/src/mpt.cairo` needs to verify a `BatchProof`.
+///
+/// The node counts are exact. The step and range check coefficients approximate the per-iteration
+/// cost of the `patricia_update` path of cairo-lang 0.13.1 and haven't been measured yet: they
+/// must be checked against `cairo0/scripts/measure_mpt.py` on `cairo0/src/mpt_input.json`, see
+/// `test_estimate_matches_cairo_run`. `main` only calls the Poseidon verifier, so no Pedersen
+/// builtin instances are used, although the builtin is declared.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ProofCost {
+    pub leaf_updates: u64,
+    pub preimage_nodes: u64,
+    pub binary_nodes: u64,
+    pub edge_nodes: u64,
+    pub estimated_steps: u64,
+    pub poseidon_builtins: u64,
+    /// Pedersen builtin instances, which only the Pedersen verifier that `main` doesn't call uses.
+    pub pedersen_builtins: u64,
+    pub range_check_builtins: u64,
+    /// The builtins of the compiled verifier, each of which takes a segment in the layout of the
+    /// run whether it is used or not. `None` if the compiled verifier can't be read.
+    pub declared_builtins: Option<Vec<String>>,
+}
+
+impl ProofCost {
+    /// Estimates the cost of verifying a `BatchProof`.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof` - The proof to estimate.
+    ///
+    /// # Returns
+    ///
+    /// The node counts of the proof and the estimated steps and builtin usage.
+    pub fn estimate(proof: &BatchProof) -> Self {
+        let leaf_updates = proof.leaf_updates.len() as u64;
+        // Binary nodes are stored as `[left, right]`, edge nodes as `[length, path, child]`
+        let binary_nodes = proof
+            .preimage
+            .values()
+            .filter(|node| node.len() == 2)
+            .count() as u64;
+        let edge_nodes = proof.preimage.len() as u64 - binary_nodes;
+        // The verifier hashes every preimage node once, and the new leaf node of every update
+        let hashes = binary_nodes + edge_nodes + leaf_updates;
+
+        ProofCost {
+            leaf_updates,
+            preimage_nodes: binary_nodes + edge_nodes,
+            binary_nodes,
+            edge_nodes,
+            estimated_steps: BASE_STEPS
+                + leaf_updates * STEPS_PER_UPDATE
+                + binary_nodes * STEPS_PER_BINARY_NODE
+                + edge_nodes * STEPS_PER_EDGE_NODE,
+            poseidon_builtins: hashes,
+            pedersen_builtins: 0,
+            range_check_builtins: leaf_updates * RANGE_CHECKS_PER_UPDATE
+                + edge_nodes * RANGE_CHECKS_PER_EDGE_NODE,
+            declared_builtins: CompiledProgram::from_file(&verifier_program())
+                .ok()
+                .map(|program| program.builtins),
+        }
+    }
+}
+
+/// The part of a program compiled by `cairo-compile` the estimate reads.
+#[derive(Deserialize, Debug)]
+pub struct CompiledProgram {
+    /// The builtins of the program, in the order of its `%builtins` directive.
+    pub builtins: Vec<String>,
+}
+
+impl CompiledProgram {
+    /// Reads a compiled program from its JSON file.
+    pub fn from_file(path: &std::path::Path) -> Result<Self, TrieCacheError> {
+        let file = std::fs::File::open(path).map_err(anyhow::Error::from)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file)).map_err(anyhow::Error::from)?)
+    }
+}
+
+/// Returns the path named by `SN_MPT_VERIFIER_PROGRAM`, or where `make run` compiles the verifier.
+fn verifier_program() -> PathBuf {
+    std::env::var_os(VERIFIER_PROGRAM_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_VERIFIER_PROGRAM))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+    use pathfinder_common::hash::PoseidonHash;
+    use pathfinder_common::trie::TrieNode;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_estimate_mpt_input_fixture() {
+        let proof: BatchProof =
+            serde_json::from_str(include_str!("../../../cairo0/src/mpt_input.json")).unwrap();
+        let cost = ProofCost::estimate(&proof);
+
+        // Counted by hand from the fixture
+        assert_eq!(cost.leaf_updates, 2);
+        assert_eq!(cost.preimage_nodes, 11);
+        assert_eq!(cost.binary_nodes, 6);
+        assert_eq!(cost.edge_nodes, 5);
+
+        // `main` only calls the Poseidon verifier
+        assert!(cost.poseidon_builtins > 0);
+        assert_eq!(cost.pedersen_builtins, 0);
+    }
+
+    #[test]
+    fn test_compiled_program_builtins() {
+        let file = std::env::temp_dir().join(format!("{}_mpt.json", rand::random::<u32>()));
+        std::fs::write(
+            &file,
+            r#"{"builtins": ["pedersen", "range_check", "poseidon"], "data": [], "prime": "0x1"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            CompiledProgram::from_file(&file).unwrap().builtins,
+            vec!["pedersen", "range_check", "poseidon"]
+        );
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(CompiledProgram::from_file(&file).is_err());
+    }
+
+    /// The relative error of the estimated steps and builtin instances allowed against a measured run.
+    const TOLERANCE_PERCENT: u64 = 10;
+
+    /// The resources of a run of the verifier, as written by `cairo0/scripts/measure_mpt.py`.
+    #[derive(Deserialize)]
+    struct ExecutionResources {
+        n_steps: u64,
+        builtin_instance_counter: HashMap<String, u64>,
+    }
+
+    fn assert_within_tolerance(name: &str, estimated: u64, measured: u64) {
+        let error = estimated.abs_diff(measured) * 100;
+        assert!(
+            error <= measured * TOLERANCE_PERCENT,
+            "estimated {} {} against {} measured",
+            estimated,
+            name,
+            measured
+        );
+    }
+
+    #[test]
+    #[ignore = "needs `python cairo0/scripts/measure_mpt.py` to have run with cairo-lang 0.13.1"]
+    fn test_estimate_matches_cairo_run() {
+        let proof: BatchProof =
+            serde_json::from_str(include_str!("../../../cairo0/src/mpt_input.json")).unwrap();
+        let cost = ProofCost::estimate(&proof);
+        let file = std::fs::File::open("../cairo0/build/mpt_input_resources.json").unwrap();
+        let measured: ExecutionResources = serde_json::from_reader(file).unwrap();
+        let builtin = |name: &str| {
+            measured
+                .builtin_instance_counter
+                .get(name)
+                .copied()
+                .unwrap_or(0)
+        };
+
+        assert_within_tolerance("steps", cost.estimated_steps, measured.n_steps);
+        assert_within_tolerance(
+            "poseidon builtins",
+            cost.poseidon_builtins,
+            builtin("poseidon_builtin"),
+        );
+        assert_within_tolerance(
+            "range check builtins",
+            cost.range_check_builtins,
+            builtin("range_check_builtin"),
+        );
+        assert_eq!(cost.pedersen_builtins, builtin("pedersen_builtin"));
+    }
+
+    #[test]
+    fn test_estimate_counts_proof_paths() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        let items: Vec<_> = (0..8)
+            .map(|value| CachedItem::new(vec![0, value]))
+            .collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        let items: Vec<_> = (8..12)
+            .map(|value| CachedItem::new(vec![0, value]))
+            .collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        let proof = TrieCache::get_range_proof(&conn, 2, 2).unwrap();
+        let cost = ProofCost::estimate(&proof);
+
+        // The nodes on the paths of the updated keys, before and after the batch, by hash
+        let nodes: HashMap<_, _> = proof
+            .proofs
+            .iter()
+            .flat_map(|proof| proof.pre.iter().chain(proof.post.iter()))
+            .map(|node| {
+                (
+                    node.hash::<PoseidonHash>(),
+                    matches!(node, TrieNode::Binary { .. }),
+                )
+            })
+            .collect();
+        let binary_nodes = nodes.values().filter(|binary| **binary).count() as u64;
+        assert_eq!(cost.leaf_updates, 4);
+        assert_eq!(cost.binary_nodes, binary_nodes);
+        assert_eq!(cost.edge_nodes, nodes.len() as u64 - binary_nodes);
+    }
+}
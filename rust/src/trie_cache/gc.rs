@@ -0,0 +1,229 @@
+use pathfinder_merkle_tree::storage::Storage;
+use pathfinder_storage::StoredNode;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::info;
+
+use crate::db;
+use crate::db::trie::TrieDB;
+use crate::errors::TrieCacheError;
+use crate::models::admin::RetentionPolicy;
+use crate::trie_cache::trie::EMPTY_ROOT_IDX;
+
+/// The outcome of a garbage collection run.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct GcReport {
+    /// The number of distinct roots the live trie is read from.
+    pub live_roots: u64,
+    /// The number of nodes reachable from the live roots.
+    pub nodes_kept: u64,
+    pub nodes_deleted: u64,
+    pub leaves_deleted: u64,
+    /// The size of the deleted hashes, node encodings and leaf values, in bytes. The database file
+    /// only shrinks once SQLite reuses or vacuums the freed pages.
+    pub bytes_reclaimed: u64,
+}
+
+/// The outcome of a history pruning run.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PruneReport {
+    /// The IDs of the batches pruned by this run.
+    pub pruned_batches: Vec<u64>,
+    /// The garbage collection of the data only the pruned batches read.
+    pub gc: GcReport,
+}
+
+/// Deletes the trie nodes and leaves that are unreachable from any batch that is neither reverted nor pruned.
+///
+/// Nodes are marked by walking the tries from the pre- and post-state roots of every live batch,
+/// so nodes shared between reverted and live batches are always kept. Every unmarked node is then
+/// deleted, together with the leaves written by reverted batches and the leaf versions of pruned
+/// batches that were overwritten before any live batch. The run holds the write lock of the
+/// database, so no batch can be created between marking and sweeping.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a pooled SQLite connection.
+///
+/// # Returns
+///
+/// A `GcReport` describing the deleted data, or a TrieCacheError if an error occurs. Nothing is
+/// deleted if a live root references a missing node.
+pub fn collect_garbage(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<GcReport, TrieCacheError> {
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let report = sweep(conn)?;
+    tx.commit()?;
+
+    info!("Garbage collection finished: {:?}", report);
+    Ok(report)
+}
+
+/// Prunes the finalized batches a retention policy doesn't retain and collects their data.
+///
+/// Pruned batches keep their row, marked with `pruned_at`, so the batch chain can still be
+/// verified, but their trie can no longer be read. Proofs of retained batches stay available,
+/// including the pre-state of a retained batch whose parent was pruned.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a pooled SQLite connection.
+/// * `policy` - The retention policy. At least one of its conditions must be set.
+///
+/// # Returns
+///
+/// A `PruneReport` listing the pruned batches and the collected data, or a TrieCacheError if an error occurs.
+pub fn prune_history(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    policy: &RetentionPolicy,
+) -> Result<PruneReport, TrieCacheError> {
+    if policy.keep_last.is_none() && policy.newer_than.is_none() {
+        return Err(TrieCacheError::InvalidRetentionPolicy);
+    }
+
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let pruned_batches =
+        db::batch::get_prunable_batch_ids(conn, policy.keep_last, policy.newer_than)?;
+    for id in &pruned_batches {
+        db::batch::mark_pruned(conn, *id)?;
+    }
+    let gc = sweep(conn)?;
+    tx.commit()?;
+
+    let report = PruneReport { pruned_batches, gc };
+    info!("History pruning finished: {:?}", report);
+    Ok(report)
+}
+
+/// Marks the nodes reachable from the live roots and deletes everything else.
+fn sweep(conn: &PooledConnection<SqliteConnectionManager>) -> Result<GcReport, TrieCacheError> {
+    let root_idxs = db::trie::get_live_root_idxs(conn)?;
+    let marked = mark_reachable(&TrieDB::new(conn), &root_idxs)?;
+    let (nodes_deleted, node_bytes) = db::trie::delete_unmarked_nodes(conn, &marked)?;
+    let (reverted_leaves, reverted_bytes) = db::trie::delete_reverted_leaves(conn)?;
+    let (superseded_leaves, superseded_bytes) = db::trie::delete_superseded_leaves(conn)?;
+
+    Ok(GcReport {
+        live_roots: root_idxs.len() as u64,
+        nodes_kept: marked.len() as u64,
+        nodes_deleted,
+        leaves_deleted: reverted_leaves + superseded_leaves,
+        bytes_reclaimed: node_bytes + reverted_bytes + superseded_bytes,
+    })
+}
+
+/// Returns the indices of all nodes reachable from the given roots.
+fn mark_reachable(storage: &TrieDB, root_idxs: &[u64]) -> Result<HashSet<u64>, TrieCacheError> {
+    let mut marked = HashSet::new();
+    let mut pending = root_idxs.to_vec();
+    while let Some(index) = pending.pop() {
+        if index == EMPTY_ROOT_IDX || !marked.insert(index) {
+            continue;
+        }
+
+        match storage.get(index)?.ok_or(TrieCacheError::NodeNotFound)? {
+            StoredNode::Binary { left, right } => pending.extend([left, right]),
+            StoredNode::Edge { child, .. } => pending.push(child),
+            StoredNode::LeafBinary | StoredNode::LeafEdge { .. } => {}
+        }
+    }
+
+    Ok(marked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::batch::BatchStatus;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+
+    fn count(conn: &PooledConnection<SqliteConnectionManager>, table: &str) -> u64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let items: Vec<_> = (0..4).map(|value| CachedItem::new(vec![value])).collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        let nodes = count(&conn, "trie_nodes");
+        let leaves = count(&conn, "leaves");
+
+        // Nothing is collected while every batch is live
+        let report = collect_garbage(&conn).unwrap();
+        assert_eq!(report.nodes_deleted, 0);
+        assert_eq!(report.nodes_kept, nodes);
+
+        let items: Vec<_> = (4..8).map(|value| CachedItem::new(vec![value])).collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        TrieCache::update_batch_status(&conn, 2, BatchStatus::Reverted, None).unwrap();
+
+        let report = collect_garbage(&conn).unwrap();
+        assert!(report.nodes_deleted > 0);
+        assert_eq!(report.leaves_deleted, 4);
+        assert!(report.bytes_reclaimed > 0);
+        assert_eq!(count(&conn, "trie_nodes"), nodes);
+        assert_eq!(count(&conn, "leaves"), leaves);
+
+        // The nodes shared with the live batch are kept, so its proofs can still be generated
+        TrieCache::get_range_proof(&conn, 1, 1).unwrap();
+
+        // A batch created on top of the collected trie links to the live batch
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![9])], None, None).unwrap();
+        assert_eq!(db::item::get_batch_keys(&conn, 3).unwrap().len(), 1);
+        assert_eq!(collect_garbage(&conn).unwrap().nodes_deleted, 0);
+        TrieCache::get_range_proof(&conn, 1, 3).unwrap();
+    }
+
+    #[test]
+    fn test_prune_history() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0])], None, None).unwrap();
+        let items = vec![CachedItem::new(vec![0]), CachedItem::new(vec![1])];
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![2])], None, None).unwrap();
+        TrieCache::finalize_batch(&conn, 3, true, None).unwrap();
+
+        assert!(matches!(
+            prune_history(&conn, &RetentionPolicy::default()),
+            Err(TrieCacheError::InvalidRetentionPolicy)
+        ));
+
+        // The latest finalized batch is retained even if the policy would prune it
+        let policy = RetentionPolicy {
+            keep_last: Some(0),
+            newer_than: None,
+        };
+        let report = prune_history(&conn, &policy).unwrap();
+        assert_eq!(report.pruned_batches, vec![1, 2]);
+        assert!(report.gc.nodes_deleted > 0);
+        // Only the version of the first batch was overwritten before the pre-state of the retained batch
+        assert_eq!(report.gc.leaves_deleted, 1);
+        assert!(db::batch::get_batch(&conn, 1).unwrap().pruned_at.is_some());
+
+        // The retained batch can still be proven from its pruned parent's root
+        TrieCache::get_range_proof(&conn, 3, 3).unwrap();
+        assert!(matches!(
+            TrieCache::get_range_proof(&conn, 1, 3),
+            Err(TrieCacheError::RootPruned)
+        ));
+
+        // Pruning again is a no-op
+        let report = prune_history(&conn, &policy).unwrap();
+        assert!(report.pruned_batches.is_empty());
+        assert_eq!(report.gc.nodes_deleted, 0);
+        assert_eq!(report.gc.leaves_deleted, 0);
+    }
+}
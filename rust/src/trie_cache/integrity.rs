@@ -0,0 +1,570 @@
+use bitvec::prelude::*;
+use pathfinder_common::hash::FeltHash;
+use pathfinder_common::trie::TrieNode;
+use pathfinder_crypto::Felt;
+use pathfinder_merkle_tree::storage::Storage;
+use pathfinder_merkle_tree::tree::MerkleTree;
+use pathfinder_storage::StoredNode;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+use crate::db;
+use crate::db::trie::TrieDB;
+use crate::errors::TrieCacheError;
+use crate::models::batch::{Batch, BatchQuery, BatchStatus};
+use crate::models::page::SortOrder;
+use crate::trie_cache::trie::EMPTY_ROOT_IDX;
+
+/// The outcome of an integrity check of the trie.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct IntegrityReport {
+    /// The number of batches whose roots were walked.
+    pub batches_checked: u64,
+    /// The number of distinct nodes whose hash was recomputed.
+    pub nodes_checked: u64,
+    /// The number of leaf versions that were looked up from their batch's root.
+    pub leaves_checked: u64,
+    /// Every inconsistency found, in the order the batches were checked.
+    pub issues: Vec<IntegrityIssue>,
+}
+
+/// An inconsistency found by the integrity check.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct IntegrityIssue {
+    pub kind: IntegrityIssueKind,
+    /// The batch whose trie the inconsistency was found in.
+    pub batch_id: u64,
+    /// The index of the node the inconsistency was found at, if any.
+    pub trie_idx: Option<u64>,
+    /// The key of the leaf the inconsistency concerns, if any.
+    pub key: Option<String>,
+    /// The value recomputed by the check, if any.
+    pub expected: Option<String>,
+    /// The value found in the database, if any.
+    pub found: Option<String>,
+}
+
+/// The kind of an inconsistency found by the integrity check.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityIssueKind {
+    /// A root or child index doesn't reference a stored node.
+    MissingNode,
+    /// The stored node can't be decoded.
+    CorruptNode,
+    /// The hash recomputed from the node's children doesn't match `trie_nodes.hash`.
+    HashMismatch,
+    /// The root hash stored on the batch doesn't match the hash of the node at its root index.
+    RootHashMismatch,
+    /// A node references a child with an index past the root of the batch it was reached from.
+    ForwardReference,
+    /// A leaf-level node references a leaf that isn't stored.
+    MissingLeaf,
+    /// A stored leaf can't be reached from the root of the batch that wrote it.
+    UnreachableLeaf,
+    /// A stored leaf doesn't match the value its batch's root commits to.
+    LeafMismatch,
+    /// A node on the path of a stored leaf, above the node referencing the leaf, doesn't hash to
+    /// the value its parent, or the root of the leaf's batch, references.
+    PathHashMismatch,
+}
+
+/// Checks that the stored trie is consistent with the batches built on it.
+///
+/// The pre- and post-state roots of every batch that is neither reverted nor pruned are walked in
+/// ID order. The hash of every reachable node is recomputed with `H` from its children and the
+/// leaves it references, and compared with the stored one. Child indices must not point past the
+/// root of the batch the node is reached from, as nodes are only ever written before the root that
+/// references them. Each node is walked once, from the first batch it's reachable from. Finally,
+/// every stored leaf version is looked up from the root of the batch that wrote it, and must be
+/// reachable and committed to by that root.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a pooled SQLite connection.
+///
+/// # Returns
+///
+/// An `IntegrityReport` listing every inconsistency found, or a TrieCacheError if the database can't be read.
+pub fn verify_db<H: FeltHash>(
+    conn: &PooledConnection<SqliteConnectionManager>,
+) -> Result<IntegrityReport, TrieCacheError> {
+    // The check reads from a single snapshot of the database
+    let _tx = conn.unchecked_transaction()?;
+
+    let mut report = IntegrityReport {
+        batches_checked: 0,
+        nodes_checked: 0,
+        leaves_checked: 0,
+        issues: vec![],
+    };
+    let mut visited = HashSet::new();
+    // The root every batch's leaves are looked up from, with the genesis leaf written as batch 0
+    let mut roots: HashMap<u64, (u64, String)> = HashMap::new();
+
+    let mut query = BatchQuery {
+        limit: Some(1000),
+        order: SortOrder::Asc,
+        ..Default::default()
+    };
+    loop {
+        let page = db::batch::get_batches(conn, &query)?;
+        for batch in page.items {
+            if batch.status == BatchStatus::Reverted || batch.pruned_at.is_some() {
+                continue;
+            }
+
+            let pre_batch_id = batch.parent_id.unwrap_or(0);
+            roots
+                .entry(pre_batch_id)
+                .or_insert((batch.pre_root_idx, batch.pre_root.clone()));
+            roots.insert(batch.id, (batch.root_idx, batch.root_hash.clone()));
+
+            let mut walk = TrieWalk::<H> {
+                batch: &batch,
+                visited: &mut visited,
+                report: &mut report,
+                hasher: Default::default(),
+            };
+            walk.check_root(
+                &TrieDB::at_batch(conn, pre_batch_id),
+                batch.pre_root_idx,
+                &batch.pre_root,
+            )?;
+            walk.check_root(
+                &TrieDB::at_batch(conn, batch.id),
+                batch.root_idx,
+                &batch.root_hash,
+            )?;
+            report.batches_checked += 1;
+        }
+
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    report.nodes_checked = visited.len() as u64;
+
+    for (key, commitment, batch_id) in db::trie::get_live_leaves(conn)? {
+        report.leaves_checked += 1;
+        let issue = |kind, expected, found| IntegrityIssue {
+            kind,
+            batch_id,
+            trie_idx: None,
+            key: Some(hex::encode(key.to_be_bytes())),
+            expected,
+            found,
+        };
+
+        let Some((root_idx, root_hash)) = roots.get(&batch_id) else {
+            report
+                .issues
+                .push(issue(IntegrityIssueKind::UnreachableLeaf, None, None));
+            continue;
+        };
+        let storage = TrieDB::at_batch(conn, batch_id);
+        let proof = MerkleTree::<H, 251>::get_proof(*root_idx, &storage, key.view_bits())
+            .ok()
+            .flatten();
+        let Some(value) = proof.as_deref().and_then(|proof| leaf_value(proof, &key)) else {
+            report
+                .issues
+                .push(issue(IntegrityIssueKind::UnreachableLeaf, None, None));
+            continue;
+        };
+
+        if value != commitment {
+            report.issues.push(issue(
+                IntegrityIssueKind::LeafMismatch,
+                Some(hex::encode(commitment.to_be_bytes())),
+                Some(hex::encode(value.to_be_bytes())),
+            ));
+        } else if let Some((position, expected, found)) = proof
+            .as_deref()
+            .and_then(|proof| first_mismatch::<H>(proof, &key, root_hash))
+        {
+            // Only the last node of the proof is hashed from the leaf itself
+            let kind = match proof.as_deref() {
+                Some(proof) if position + 1 == proof.len() => IntegrityIssueKind::LeafMismatch,
+                _ => IntegrityIssueKind::PathHashMismatch,
+            };
+            report.issues.push(issue(
+                kind,
+                Some(hex::encode(expected.to_be_bytes())),
+                Some(hex::encode(found.to_be_bytes())),
+            ));
+        }
+    }
+
+    info!(
+        "Integrity check finished: {} batches, {} nodes, {} leaves, {} issues",
+        report.batches_checked,
+        report.nodes_checked,
+        report.leaves_checked,
+        report.issues.len()
+    );
+    Ok(report)
+}
+
+/// Walks the tries of one batch, recording the inconsistencies it finds.
+struct TrieWalk<'a, H: FeltHash> {
+    batch: &'a Batch,
+    visited: &'a mut HashSet<u64>,
+    report: &'a mut IntegrityReport,
+    hasher: std::marker::PhantomData<H>,
+}
+
+impl<H: FeltHash> TrieWalk<'_, H> {
+    /// Checks the stored hash of a root and every node reachable from it.
+    fn check_root(
+        &mut self,
+        storage: &TrieDB,
+        root_idx: u64,
+        root_hash: &str,
+    ) -> Result<(), TrieCacheError> {
+        if root_idx == EMPTY_ROOT_IDX {
+            let empty_root = hex::encode(Felt::ZERO.to_be_bytes());
+            if root_hash != empty_root {
+                self.issue(
+                    IntegrityIssueKind::RootHashMismatch,
+                    Some(root_idx),
+                    None,
+                    Some(root_hash.to_string()),
+                    Some(empty_root),
+                );
+            }
+            return Ok(());
+        }
+
+        match storage.hash(root_idx)? {
+            Some(hash) if hex::encode(hash.to_be_bytes()) != root_hash => self.issue(
+                IntegrityIssueKind::RootHashMismatch,
+                Some(root_idx),
+                None,
+                Some(root_hash.to_string()),
+                Some(hex::encode(hash.to_be_bytes())),
+            ),
+            Some(_) => {}
+            None => {
+                self.issue(
+                    IntegrityIssueKind::MissingNode,
+                    Some(root_idx),
+                    None,
+                    None,
+                    None,
+                );
+                return Ok(());
+            }
+        }
+
+        let mut pending = vec![(root_idx, BitVec::<u8, Msb0>::new())];
+        while let Some((index, path)) = pending.pop() {
+            if !self.visited.insert(index) {
+                continue;
+            }
+
+            let node = match storage.get(index) {
+                Ok(Some(node)) => node,
+                Ok(None) => {
+                    self.issue(
+                        IntegrityIssueKind::MissingNode,
+                        Some(index),
+                        None,
+                        None,
+                        None,
+                    );
+                    continue;
+                }
+                Err(_) => {
+                    self.issue(
+                        IntegrityIssueKind::CorruptNode,
+                        Some(index),
+                        None,
+                        None,
+                        None,
+                    );
+                    continue;
+                }
+            };
+
+            let children = match &node {
+                StoredNode::Binary { left, right } => vec![(*left, false), (*right, true)],
+                StoredNode::Edge { child, .. } => vec![(*child, false)],
+                StoredNode::LeafBinary | StoredNode::LeafEdge { .. } => vec![],
+            };
+            for (child, _) in &children {
+                if *child > root_idx {
+                    self.issue(
+                        IntegrityIssueKind::ForwardReference,
+                        Some(index),
+                        None,
+                        Some(format!("<= {}", root_idx)),
+                        Some(child.to_string()),
+                    );
+                }
+            }
+
+            let computed = match &node {
+                StoredNode::Binary { left, right } => {
+                    match (storage.hash(*left)?, storage.hash(*right)?) {
+                        (Some(left), Some(right)) => Some(TrieNode::Binary { left, right }),
+                        _ => None,
+                    }
+                }
+                StoredNode::Edge { child, path: edge } => {
+                    storage.hash(*child)?.map(|child| TrieNode::Edge {
+                        child,
+                        path: edge.clone(),
+                    })
+                }
+                StoredNode::LeafBinary => {
+                    let left = self.leaf(storage, index, &path, bits![u8, Msb0; 0])?;
+                    let right = self.leaf(storage, index, &path, bits![u8, Msb0; 1])?;
+                    left.zip(right)
+                        .map(|(left, right)| TrieNode::Binary { left, right })
+                }
+                StoredNode::LeafEdge { path: edge } => {
+                    self.leaf(storage, index, &path, edge)?
+                        .map(|child| TrieNode::Edge {
+                            child,
+                            path: edge.clone(),
+                        })
+                }
+            }
+            .map(|node| node.hash::<H>());
+
+            // Nodes whose children are missing are reported through their children
+            if let (Some(computed), Some(stored)) = (computed, storage.hash(index)?) {
+                if computed != stored {
+                    self.issue(
+                        IntegrityIssueKind::HashMismatch,
+                        Some(index),
+                        None,
+                        Some(hex::encode(computed.to_be_bytes())),
+                        Some(hex::encode(stored.to_be_bytes())),
+                    );
+                }
+            }
+
+            for (child, bit) in children {
+                if child > root_idx {
+                    continue;
+                }
+                let mut child_path = path.clone();
+                match &node {
+                    StoredNode::Edge { path: edge, .. } => child_path.extend_from_bitslice(edge),
+                    _ => child_path.push(bit),
+                }
+                pending.push((child, child_path));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the leaf below a leaf-level node, reporting it if it's missing.
+    fn leaf(
+        &mut self,
+        storage: &TrieDB,
+        index: u64,
+        path: &BitSlice<u8, Msb0>,
+        suffix: &BitSlice<u8, Msb0>,
+    ) -> Result<Option<Felt>, TrieCacheError> {
+        let mut leaf_path = path.to_bitvec();
+        leaf_path.extend_from_bitslice(suffix);
+        let value = storage.leaf(&leaf_path)?;
+        if value.is_none() {
+            let key = Felt::from_bits(&leaf_path).ok();
+            self.issue(
+                IntegrityIssueKind::MissingLeaf,
+                Some(index),
+                key.map(|key| hex::encode(key.to_be_bytes())),
+                None,
+                None,
+            );
+        }
+
+        Ok(value)
+    }
+
+    fn issue(
+        &mut self,
+        kind: IntegrityIssueKind,
+        trie_idx: Option<u64>,
+        key: Option<String>,
+        expected: Option<String>,
+        found: Option<String>,
+    ) {
+        self.report.issues.push(IntegrityIssue {
+            kind,
+            batch_id: self.batch.id,
+            trie_idx,
+            key,
+            expected,
+            found,
+        });
+    }
+}
+
+/// Follows the path of a key through a proof and returns the leaf value it ends at, if it reaches the key.
+fn leaf_value(proof: &[TrieNode], key: &Felt) -> Option<Felt> {
+    let bits = key.view_bits();
+    let mut depth = 0;
+    let mut value = None;
+    for node in proof {
+        match node {
+            TrieNode::Binary { left, right } => {
+                value = Some(if *bits.get(depth)? { *right } else { *left });
+                depth += 1;
+            }
+            TrieNode::Edge { child, path } => {
+                if bits.get(depth..depth + path.len())? != path {
+                    return None;
+                }
+                value = Some(*child);
+                depth += path.len();
+            }
+        }
+    }
+
+    value.filter(|_| depth == bits.len())
+}
+
+/// Follows the path of a key through a proof and returns the first node that doesn't hash to the
+/// value referenced by its parent, or by the root for the first node, as its position in the
+/// proof together with the referenced and the recomputed hash.
+fn first_mismatch<H: FeltHash>(
+    proof: &[TrieNode],
+    key: &Felt,
+    root_hash: &str,
+) -> Option<(usize, Felt, Felt)> {
+    let bits = key.view_bits();
+    let mut depth = 0;
+    let mut expected = Felt::from_hex_str(root_hash).unwrap_or(Felt::ZERO);
+    for (position, node) in proof.iter().enumerate() {
+        let hash = node.hash::<H>();
+        if hash != expected {
+            return Some((position, expected, hash));
+        }
+
+        // The next node is the child the key's path takes, which the parent references by hash
+        match node {
+            TrieNode::Binary { left, right } => {
+                expected = if *bits.get(depth)? { *right } else { *left };
+                depth += 1;
+            }
+            TrieNode::Edge { child, path } => {
+                expected = *child;
+                depth += path.len();
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trie_cache::item::CachedItem;
+    use crate::trie_cache::TrieCache;
+    use pathfinder_common::hash::PoseidonHash;
+    use rusqlite::params;
+
+    #[test]
+    fn test_verify_db() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let items: Vec<_> = (0..4).map(|value| CachedItem::new(vec![value])).collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        let items: Vec<_> = (4..8).map(|value| CachedItem::new(vec![value])).collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+
+        let report = verify_db::<PoseidonHash>(&conn).unwrap();
+        assert_eq!(report.issues, vec![]);
+        assert_eq!(report.batches_checked, 2);
+        assert!(report.nodes_checked > 0);
+        // The leaves of both batches
+        assert_eq!(report.leaves_checked, 8);
+
+        // A tampered node hash is detected where it's stored
+        let root_idx = db::batch::get_batch(&conn, 2).unwrap().root_idx;
+        let set_hash = |hash: Vec<u8>| {
+            conn.execute(
+                "UPDATE trie_nodes SET hash = ? WHERE trie_idx = ?",
+                params![hash, root_idx - 1],
+            )
+            .unwrap();
+        };
+        let hash: Vec<u8> = conn
+            .query_row(
+                "SELECT hash FROM trie_nodes WHERE trie_idx = ?",
+                params![root_idx - 1],
+                |row| row.get(0),
+            )
+            .unwrap();
+        set_hash(Felt::from_u64(1).to_be_bytes().to_vec());
+        let report = verify_db::<PoseidonHash>(&conn).unwrap();
+        assert!(report.issues.iter().any(|issue| {
+            issue.kind == IntegrityIssueKind::HashMismatch && issue.trie_idx == Some(root_idx - 1)
+        }));
+        // The leaves below it no longer hash up to the root of their batch
+        let root_hash = db::batch::get_batch(&conn, 2).unwrap().root_hash;
+        assert!(report.issues.iter().any(|issue| {
+            issue.kind == IntegrityIssueKind::PathHashMismatch
+                && issue.expected == Some(root_hash.clone())
+        }));
+        assert!(report
+            .issues
+            .iter()
+            .all(|issue| issue.kind != IntegrityIssueKind::LeafMismatch));
+        set_hash(hash);
+        assert_eq!(verify_db::<PoseidonHash>(&conn).unwrap().issues, vec![]);
+
+        // A tampered leaf no longer matches the root of its batch
+        let item = CachedItem::new(vec![4]);
+        conn.execute(
+            "UPDATE leaves SET commitment = ? WHERE key = ?",
+            params![
+                Felt::from_u64(1).to_be_bytes().to_vec(),
+                item.key.to_be_bytes().to_vec()
+            ],
+        )
+        .unwrap();
+        let report = verify_db::<PoseidonHash>(&conn).unwrap();
+        assert!(report.issues.iter().any(|issue| {
+            issue.kind == IntegrityIssueKind::LeafMismatch
+                && issue.key == Some(hex::encode(item.key.to_be_bytes()))
+        }));
+    }
+
+    #[test]
+    fn test_first_mismatch_follows_the_key() {
+        // The key takes the right child of the root, then an edge down to the leaf
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0x04;
+        let key = Felt::from_be_slice(&bytes).unwrap();
+        let edge = TrieNode::Edge {
+            child: Felt::from_u64(3),
+            path: key.view_bits()[1..].to_bitvec(),
+        };
+        let root = TrieNode::Binary {
+            left: edge.hash::<PoseidonHash>(),
+            right: Felt::from_u64(2),
+        };
+        let root_hash = hex::encode(root.hash::<PoseidonHash>().to_be_bytes());
+        let proof = [root, edge.clone()];
+
+        // The edge matches the left child, but the key's path references the right one
+        assert_eq!(
+            first_mismatch::<PoseidonHash>(&proof, &key, &root_hash),
+            Some((1, Felt::from_u64(2), edge.hash::<PoseidonHash>()))
+        );
+        assert_eq!(leaf_value(&proof, &key), Some(Felt::from_u64(3)));
+    }
+}
@@ -1,12 +1,25 @@
+pub mod backup;
 pub mod batch_proof;
+pub mod chain;
+pub mod codec;
+pub mod cost;
+pub mod gc;
+pub mod integrity;
 pub mod item;
+pub mod rebuild;
+pub mod signer;
+pub mod snapshot;
 pub mod trie;
-use crate::models::batch::BatchStatus;
+use crate::db::trie::TrieDB;
+use crate::models::batch::{Batch, BatchStatus, NewBatch};
 use crate::trie_cache::batch_proof::BatchProof;
 use crate::trie_cache::item::CachedItem;
+use crate::trie_cache::signer::Signer;
 use crate::{db, errors::TrieCacheError};
+use pathfinder_crypto::Felt;
 use r2d2::PooledConnection;
 use r2d2_sqlite::SqliteConnectionManager;
+use std::collections::BTreeSet;
 use tracing::info;
 use trie::Trie;
 pub struct TrieCache {}
@@ -14,54 +27,115 @@ pub struct TrieCache {}
 impl TrieCache {
     /// Creates a batch in the TrieCache.
     ///
+    /// The batch is built on top of the latest batch that isn't reverted, whether it's created or
+    /// finalized, and only starts a new trie if there is none.
+    ///
+    /// Batches larger than `max_items_per_proof` are committed as a chain of sub-batches, each
+    /// with its own proof starting from the root the previous one ended at. The sub-batches share
+    /// the ID of the first one as their `group_id`. Either all sub-batches are created or none are.
+    /// With a signer, every sub-batch gets a signed creation receipt, which is attached to its proof.
+    ///
     /// # Arguments
     ///
     /// * `conn` - A reference to a pooled SQLite connection.
     /// * `items` - A vector of CachedItem objects.
+    /// * `max_items_per_proof` - The maximum number of items per sub-batch, or `None` (or `Some(0)`) for no limit.
+    /// * `signer` - The signer of the batch receipts, if any.
     ///
     /// # Returns
     ///
-    /// Returns a Result containing a BatchProof if successful, or a TrieCacheError if an error occurs.
+    /// Returns a Result containing the BatchProof of every sub-batch in order if successful, or a TrieCacheError if an error occurs.
     // ToDo: This should have a mutex to prevent concurrent access
     pub fn create_batch(
         conn: &PooledConnection<SqliteConnectionManager>,
         items: Vec<CachedItem>,
-    ) -> Result<BatchProof, TrieCacheError> {
-        match db::batch::get_latest_batch_by_status(conn, BatchStatus::Created) {
-            Ok(Some(latest_batch)) => {
-                let (storage, trie) = Trie::load(latest_batch.root_idx, conn);
-                let batch_id = latest_batch.id + 1;
-                let (batch_proof, root_idx) = Trie::persist_batch_and_generate_proofs(
-                    storage,
-                    trie,
-                    latest_batch.root_idx,
-                    items,
-                    &batch_id,
-                )?;
-                db::batch::create_batch(conn, Some(latest_batch.id), root_idx)?;
-                info!("Batch created with id: {}", batch_id);
-                Ok(batch_proof)
-            }
-            Ok(None) => {
-                let batch_id = 1;
-                let (storage, trie) = Trie::new(conn);
-                let (batch_proof, root_idx) =
-                    Trie::persist_batch_and_generate_proofs(storage, trie, 1, items, &batch_id)?;
-
-                db::batch::create_batch(conn, None, root_idx)?;
-                Ok(batch_proof)
-            }
-            Err(e) => Err(e),
+        max_items_per_proof: Option<usize>,
+        signer: Option<&Signer>,
+    ) -> Result<Vec<BatchProof>, TrieCacheError> {
+        let chunk_size = max_items_per_proof
+            .filter(|max| *max > 0)
+            .unwrap_or(items.len())
+            .max(1);
+        let mut chunks: Vec<Vec<CachedItem>> =
+            items.chunks(chunk_size).map(<[_]>::to_vec).collect();
+        if chunks.is_empty() {
+            chunks.push(vec![]);
         }
+
+        let tx = conn.unchecked_transaction()?;
+        let mut parent = db::batch::get_latest_unreverted_batch(conn)?;
+        let grouped = chunks.len() > 1;
+        let mut group_id = None;
+
+        let mut proofs = vec![];
+        for chunk in chunks {
+            let (mut proof, batch_id) = TrieCache::create_sub_batch(conn, parent, chunk, group_id)?;
+            // The group takes the ID of its first sub-batch, which is only known once it's stored
+            if grouped && group_id.is_none() {
+                db::batch::set_group_id(conn, batch_id, batch_id)?;
+                group_id = Some(batch_id);
+            }
+            let batch = db::batch::get_batch(conn, batch_id)?;
+            if let Some(signer) = signer {
+                let receipt = signer.sign(&batch, BatchStatus::Created)?;
+                db::receipt::insert_receipt(conn, &receipt)?;
+                proof.receipt = Some(receipt);
+            }
+            parent = Some(batch);
+            proofs.push(proof);
+        }
+        tx.commit()?;
+
+        Ok(proofs)
+    }
+
+    /// Persists a batch on top of its parent, or on top of a new trie if it has none.
+    fn create_sub_batch(
+        conn: &PooledConnection<SqliteConnectionManager>,
+        parent: Option<Batch>,
+        items: Vec<CachedItem>,
+        group_id: Option<u64>,
+    ) -> Result<(BatchProof, u64), TrieCacheError> {
+        let (storage, trie, pre_root_idx) = match &parent {
+            Some(parent) => {
+                let (storage, trie) = Trie::load(parent.root_idx, conn);
+                (storage, trie, parent.root_idx)
+            }
+            None => Trie::new(conn)?,
+        };
+        let item_count = items.len() as u64;
+        let persisted =
+            Trie::persist_batch_and_generate_proofs(storage, trie, pre_root_idx, items, |roots| {
+                db::batch::create_batch(
+                    conn,
+                    &NewBatch {
+                        parent_id: parent.map(|parent| parent.id),
+                        root_idx: roots.root_idx,
+                        root_hash: roots.root_hash,
+                        pre_root_idx,
+                        pre_root: roots.pre_root,
+                        item_count,
+                        nodes_added: roots.nodes_added,
+                        group_id,
+                    },
+                )
+            })?;
+        info!("Batch created with id: {}", persisted.id);
+
+        Ok((persisted.proof, persisted.id))
     }
 
     /// Updates the status of a batch in the TrieCache.
     ///
+    /// A batch can only be finalized once its parent is finalized. Reverting a batch also
+    /// reverts every batch built on top of it. All updates are applied in one transaction.
+    ///
     /// # Arguments
     ///
     /// * `conn` - A reference to a pooled SQLite connection.
     /// * `batch_id` - The ID of the batch to update.
     /// * `status` - The new status of the batch.
+    /// * `signer` - The signer of the finalization receipt, if any.
     ///
     /// # Returns
     ///
@@ -70,42 +144,323 @@ impl TrieCache {
         conn: &PooledConnection<SqliteConnectionManager>,
         batch_id: u64,
         status: BatchStatus,
+        signer: Option<&Signer>,
     ) -> Result<(), TrieCacheError> {
         info!("Updating batch # {:?} status to {:?}", batch_id, status);
+        let tx = conn.unchecked_transaction()?;
         match status {
             BatchStatus::Finalized => {
-                let batch = db::batch::get_batch(conn, batch_id)?;
-                match batch.parent_id {
-                    Some(parent_id) => {
-                        let parent_batch = db::batch::get_batch(conn, parent_id)?;
-                        match parent_batch.status {
-                            BatchStatus::Finalized => {
-                                db::batch::update_batch_status(
-                                    conn,
-                                    &batch_id,
-                                    BatchStatus::Finalized,
-                                )?;
-                                info!("Update Complete");
-                                Ok(())
-                            }
-                            _ => Err(TrieCacheError::BatchParentNotFinalized),
-                        }
-                    }
-                    None => {
-                        db::batch::update_batch_status(conn, &batch_id, BatchStatus::Finalized)?;
-                        info!("Update Complete");
-                        Ok(())
+                TrieCache::finalize(conn, batch_id, false, signer)?;
+            }
+            BatchStatus::Reverted => {
+                db::batch::update_batch_status(conn, &batch_id, BatchStatus::Reverted)?;
+                for descendant_id in db::batch::get_descendant_ids(conn, batch_id)? {
+                    let descendant = db::batch::get_batch(conn, descendant_id)?;
+                    if descendant.status != BatchStatus::Reverted {
+                        db::batch::update_batch_status(
+                            conn,
+                            &descendant_id,
+                            BatchStatus::Reverted,
+                        )?;
                     }
                 }
             }
-            _ => todo!(),
+            BatchStatus::Created => {
+                let batch = db::batch::get_batch(conn, batch_id)?;
+                return Err(TrieCacheError::InvalidStatusTransition {
+                    from: batch.status,
+                    to: status,
+                });
+            }
+        }
+        tx.commit()?;
+        info!("Update Complete");
+        Ok(())
+    }
+
+    /// Finalizes a batch, optionally together with all of its unfinalized ancestors.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - A reference to a pooled SQLite connection.
+    /// * `batch_id` - The ID of the batch to finalize.
+    /// * `cascade` - Whether created ancestors should be finalized as well. Without it,
+    ///   the parent of the batch must already be finalized.
+    /// * `signer` - The signer of the finalization receipts, if any.
+    ///
+    /// # Returns
+    ///
+    /// Returns the IDs of the finalized batches, oldest first, or a TrieCacheError if an error occurs.
+    /// Either all batches are finalized or none are.
+    pub fn finalize_batch(
+        conn: &PooledConnection<SqliteConnectionManager>,
+        batch_id: u64,
+        cascade: bool,
+        signer: Option<&Signer>,
+    ) -> Result<Vec<u64>, TrieCacheError> {
+        info!("Finalizing batch # {:?} (cascade: {})", batch_id, cascade);
+        let tx = conn.unchecked_transaction()?;
+        let finalized = TrieCache::finalize(conn, batch_id, cascade, signer)?;
+        tx.commit()?;
+        info!("Finalized batches {:?}", finalized);
+        Ok(finalized)
+    }
+
+    /// Generates a single proof for the transition from the pre-state of one batch to the post-state of another.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - A reference to a pooled SQLite connection.
+    /// * `from_id` - The ID of the first batch of the range.
+    /// * `to_id` - The ID of the last batch of the range. It must descend from `from_id`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a BatchProof covering every key written in the range, or a TrieCacheError if the
+    /// batches don't form a chain of batches that aren't reverted, or if one of them was pruned.
+    pub fn get_range_proof(
+        conn: &PooledConnection<SqliteConnectionManager>,
+        from_id: u64,
+        to_id: u64,
+    ) -> Result<BatchProof, TrieCacheError> {
+        let from = db::batch::get_batch(conn, from_id)?;
+        let to = db::batch::get_batch(conn, to_id)?;
+
+        // Walk up from the last batch to the first one
+        let mut chain = vec![];
+        let mut batch = to;
+        let to_root_idx = batch.root_idx;
+        loop {
+            if batch.status == BatchStatus::Reverted {
+                return Err(TrieCacheError::InvalidBatchRange);
+            }
+            if batch.pruned_at.is_some() {
+                return Err(TrieCacheError::RootPruned);
+            }
+            chain.push(batch.id);
+            if batch.id == from_id {
+                break;
+            }
+            match batch.parent_id {
+                Some(parent_id) if parent_id >= from_id => {
+                    batch = db::batch::get_batch(conn, parent_id)?;
+                }
+                _ => return Err(TrieCacheError::InvalidBatchRange),
+            }
+        }
+
+        let mut keys = BTreeSet::<Felt>::new();
+        for batch_id in &chain {
+            keys.extend(db::item::get_batch_keys(conn, *batch_id)?);
+        }
+        let keys: Vec<Felt> = keys.into_iter().collect();
+
+        // The first batch was applied on top of its parent, or the empty trie of a new database
+        let pre_storage = TrieDB::at_batch(conn, from.parent_id.unwrap_or(0));
+        let post_storage = TrieDB::at_batch(conn, to_id);
+
+        Trie::generate_range_proof(
+            pre_storage,
+            post_storage,
+            from.pre_root_idx,
+            to_root_idx,
+            &keys,
+            &to_id,
+        )
+    }
+
+    /// Finalizes a batch and, if `cascade` is set, its created ancestors, signing a receipt for
+    /// each of them if there is a signer. Must run inside a transaction.
+    fn finalize(
+        conn: &PooledConnection<SqliteConnectionManager>,
+        batch_id: u64,
+        cascade: bool,
+        signer: Option<&Signer>,
+    ) -> Result<Vec<u64>, TrieCacheError> {
+        let batch = db::batch::get_batch(conn, batch_id)?;
+
+        // Walk up the parents until a finalized batch or the first batch is reached
+        let mut path = vec![batch_id];
+        let mut parent_id = batch.parent_id;
+        while let Some(id) = parent_id {
+            let parent = db::batch::get_batch(conn, id)?;
+            match parent.status {
+                BatchStatus::Finalized => break,
+                BatchStatus::Reverted => return Err(TrieCacheError::BatchAncestorReverted),
+                BatchStatus::Created if cascade => {
+                    path.push(id);
+                    parent_id = parent.parent_id;
+                }
+                BatchStatus::Created => return Err(TrieCacheError::BatchParentNotFinalized),
+            }
+        }
+
+        path.reverse();
+        for id in &path {
+            db::batch::update_batch_status(conn, id, BatchStatus::Finalized)?;
+            if let Some(signer) = signer {
+                let receipt =
+                    signer.sign(&db::batch::get_batch(conn, *id)?, BatchStatus::Finalized)?;
+                db::receipt::insert_receipt(conn, &receipt)?;
+            }
         }
+
+        Ok(path)
     }
 }
 
+/// Parses a hex-encoded felt, as stored for roots, commitments and signatures.
+///
+/// # Arguments
+///
+/// * `hex_value` - The hex string, with or without `0x` prefix.
+///
+/// # Returns
+///
+/// The felt, or `TrieCacheError::InvalidHexString` if the string isn't a valid felt.
+pub(crate) fn parse_felt(hex_value: &str) -> Result<Felt, TrieCacheError> {
+    Felt::from_hex_str(hex_value).map_err(|_| TrieCacheError::InvalidHexString)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use bitvec::prelude::*;
+    use pathfinder_common::hash::PoseidonHash;
+    use pathfinder_common::trie::TrieNode;
+    use rusqlite::OptionalExtension;
+    use serde_json::{Map, Value};
+    use std::collections::HashSet;
+
+    /// The part of a trie that the preimage of a cairo0 input reveals.
+    enum Partial {
+        Empty,
+        Leaf(Felt),
+        /// A subtree known by its hash only.
+        Opaque(Felt),
+        Binary(Box<Partial>, Box<Partial>),
+        Edge(BitVec<u8, Msb0>, Box<Partial>),
+    }
+
+    fn felt(value: &Value) -> Felt {
+        parse_felt(value.as_str().unwrap()).unwrap()
+    }
+
+    fn expand(preimage: &Map<String, Value>, hash: Felt, height: usize) -> Partial {
+        if hash == Felt::ZERO {
+            return Partial::Empty;
+        }
+        if height == 0 {
+            return Partial::Leaf(hash);
+        }
+        let Some(values) = preimage.get(&hex::encode(hash.to_be_bytes())) else {
+            return Partial::Opaque(hash);
+        };
+        match values.as_array().unwrap().as_slice() {
+            [left, right] => Partial::Binary(
+                Box::new(expand(preimage, felt(left), height - 1)),
+                Box::new(expand(preimage, felt(right), height - 1)),
+            ),
+            [length, path, child] => {
+                let length = felt(length).to_be_bytes()[31] as usize;
+                let path = felt(path);
+                let bits = path.view_bits();
+                Partial::Edge(
+                    bits[bits.len() - length..].to_bitvec(),
+                    Box::new(expand(preimage, felt(child), height - length)),
+                )
+            }
+            _ => panic!("malformed preimage of {:?}", hash),
+        }
+    }
+
+    fn get(node: &Partial, bits: &BitSlice<u8, Msb0>) -> Felt {
+        match node {
+            Partial::Empty => Felt::ZERO,
+            Partial::Leaf(value) => *value,
+            Partial::Opaque(hash) => panic!("the preimage lacks {:?}", hash),
+            Partial::Binary(left, right) => get(if bits[0] { right } else { left }, &bits[1..]),
+            Partial::Edge(path, child) if bits.starts_with(path) => get(child, &bits[path.len()..]),
+            Partial::Edge(..) => Felt::ZERO,
+        }
+    }
+
+    fn set(node: Partial, bits: &BitSlice<u8, Msb0>, value: Felt) -> Partial {
+        match node {
+            Partial::Empty if bits.is_empty() => Partial::Leaf(value),
+            Partial::Empty => Partial::Edge(bits.to_bitvec(), Box::new(Partial::Leaf(value))),
+            Partial::Leaf(_) => Partial::Leaf(value),
+            Partial::Opaque(hash) => panic!("the preimage lacks {:?}", hash),
+            Partial::Binary(left, right) if bits[0] => {
+                Partial::Binary(left, Box::new(set(*right, &bits[1..], value)))
+            }
+            Partial::Binary(left, right) => {
+                Partial::Binary(Box::new(set(*left, &bits[1..], value)), right)
+            }
+            Partial::Edge(path, child) => {
+                let common = path
+                    .iter()
+                    .zip(bits.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if common == path.len() {
+                    return Partial::Edge(path, Box::new(set(*child, &bits[common..], value)));
+                }
+
+                // Split the edge where the key leaves it
+                let old = if path.len() > common + 1 {
+                    Partial::Edge(path[common + 1..].to_bitvec(), child)
+                } else {
+                    *child
+                };
+                let new = set(Partial::Empty, &bits[common + 1..], value);
+                let binary = if path[common] {
+                    Partial::Binary(Box::new(new), Box::new(old))
+                } else {
+                    Partial::Binary(Box::new(old), Box::new(new))
+                };
+                if common == 0 {
+                    binary
+                } else {
+                    Partial::Edge(path[..common].to_bitvec(), Box::new(binary))
+                }
+            }
+        }
+    }
+
+    fn hash(node: &Partial) -> Felt {
+        match node {
+            Partial::Empty => Felt::ZERO,
+            Partial::Leaf(hash) | Partial::Opaque(hash) => *hash,
+            Partial::Binary(left, right) => TrieNode::Binary {
+                left: hash(left),
+                right: hash(right),
+            }
+            .hash::<PoseidonHash>(),
+            Partial::Edge(path, child) => TrieNode::Edge {
+                child: hash(child),
+                path: path.clone(),
+            }
+            .hash::<PoseidonHash>(),
+        }
+    }
+
+    /// Checks a cairo0 input the way `cairo0/src/mpt.cairo` does: the pre-state root must hold
+    /// every `pre_value`, and writing every `post_value` into it must yield the post-state root.
+    /// Only the nodes of the preimage can be walked, every other subtree is known by its hash.
+    fn verify_cairo0_input(input: &Value) {
+        let preimage = input["preimage"].as_object().unwrap();
+        let mut trie = expand(preimage, felt(&input["pre_root"]), 251);
+
+        let mut keys = HashSet::new();
+        for update in input["leaf_updates"].as_array().unwrap() {
+            let key = felt(&update["key"]);
+            assert!(keys.insert(key), "{:?} is updated twice", key);
+            assert_eq!(get(&trie, key.view_bits()), felt(&update["pre_value"]));
+            trie = set(trie, key.view_bits(), felt(&update["post_value"]));
+        }
+        assert_eq!(hash(&trie), felt(&input["post_root"]));
+    }
 
     #[test]
     fn test_batch() {
@@ -113,29 +468,357 @@ mod test {
         let conn = test_ctx.manager.get_connection().unwrap();
 
         let items: Vec<_> = (0..10).map(|_| CachedItem::default()).collect();
-        let result = TrieCache::create_batch(&conn, items).unwrap();
+        let result = TrieCache::create_batch(&conn, items, None, None)
+            .unwrap()
+            .remove(0);
         assert_eq!(result.id, 1);
-        assert_eq!(
-            result.pre_root,
-            "07020e0f5c03f535f90ed3789c7f6e1aaa9694328a00a48a349d65d2f9870e72"
-        );
-        assert_eq!(
-            result.post_root,
-            "01737140f9fb422940bf92518c92455d6c08df6ef8f02333eec546512dd69ec4"
-        );
+        // The first batch starts from the empty trie
+        assert_eq!(result.pre_root, hex::encode(Felt::ZERO.to_be_bytes()));
+        assert!(result.proofs.iter().all(|proof| proof.pre.is_empty()));
+        assert_ne!(result.post_root, result.pre_root);
 
         let items_two = (0..10).map(|_| CachedItem::default()).collect();
-        let result_two = TrieCache::create_batch(&conn, items_two).unwrap();
+        let result_two = TrieCache::create_batch(&conn, items_two, None, None)
+            .unwrap()
+            .remove(0);
         assert_eq!(result_two.id, 2);
         assert_eq!(result.post_root, result_two.pre_root);
 
         // Parent not finalized
-        assert!(TrieCache::update_batch_status(&conn, 2, BatchStatus::Finalized).is_err());
+        assert!(TrieCache::update_batch_status(&conn, 2, BatchStatus::Finalized, None).is_err());
 
         // Finalize parent
-        assert!(TrieCache::update_batch_status(&conn, 1, BatchStatus::Finalized).is_ok());
+        assert!(TrieCache::update_batch_status(&conn, 1, BatchStatus::Finalized, None).is_ok());
 
         // Finalize child
-        assert!(TrieCache::update_batch_status(&conn, 2, BatchStatus::Finalized).is_ok());
+        assert!(TrieCache::update_batch_status(&conn, 2, BatchStatus::Finalized, None).is_ok());
+
+        // Finalized batches can't be finalized again or reverted
+        assert!(matches!(
+            TrieCache::update_batch_status(&conn, 2, BatchStatus::Finalized, None),
+            Err(TrieCacheError::InvalidStatusTransition { .. })
+        ));
+        assert!(matches!(
+            TrieCache::update_batch_status(&conn, 1, BatchStatus::Reverted, None),
+            Err(TrieCacheError::InvalidStatusTransition { .. })
+        ));
+    }
+
+    #[test]
+    fn test_batch_with_genesis_leaf() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        db::migration::set_flag(&conn, db::migration::GENESIS_LEAF_FLAG).unwrap();
+
+        // Legacy databases keep starting their tries with the genesis leaf
+        let result = TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 1])], None, None)
+            .unwrap()
+            .remove(0);
+        assert_ne!(result.pre_root, hex::encode(Felt::ZERO.to_be_bytes()));
+        assert!(result.preimage.contains_key(&result.pre_root));
+    }
+
+    #[test]
+    fn test_create_batch_in_chunks() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![1])], None, None).unwrap();
+
+        let items: Vec<_> = (2..7).map(|value| CachedItem::new(vec![value])).collect();
+        let proofs = TrieCache::create_batch(&conn, items, Some(2), None).unwrap();
+        assert_eq!(proofs.len(), 3);
+        assert_eq!(
+            proofs
+                .iter()
+                .map(|proof| proof.leaf_updates.len())
+                .collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+
+        // Every chunk starts from the root the previous one ended at
+        for pair in proofs.windows(2) {
+            assert_eq!(pair[0].post_root, pair[1].pre_root);
+        }
+
+        let batches = [2, 3, 4].map(|id| db::batch::get_batch(&conn, id).unwrap());
+        assert!(batches.iter().all(|batch| batch.group_id == Some(2)));
+        assert_eq!(
+            batches
+                .iter()
+                .map(|batch| batch.parent_id)
+                .collect::<Vec<_>>(),
+            vec![Some(1), Some(2), Some(3)]
+        );
+        assert_eq!(db::batch::get_batch(&conn, 1).unwrap().group_id, None);
+    }
+
+    #[test]
+    fn test_finalize_batch_cascade() {
+        let test_ctx = db::test::TestContext::new();
+        let _ = test_ctx.batch_seeding();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        assert!(matches!(
+            TrieCache::finalize_batch(&conn, 3, false, None),
+            Err(TrieCacheError::BatchParentNotFinalized)
+        ));
+        assert_eq!(
+            TrieCache::finalize_batch(&conn, 3, true, None).unwrap(),
+            vec![2, 3]
+        );
+        assert_eq!(
+            db::batch::get_batch(&conn, 2).unwrap().status,
+            BatchStatus::Finalized
+        );
+    }
+
+    #[test]
+    fn test_revert_cascades_to_descendants() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+        for value in 1..=3 {
+            TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, value])], None, None)
+                .unwrap();
+        }
+        TrieCache::update_batch_status(&conn, 3, BatchStatus::Reverted, None).unwrap();
+        // Batch 4 is built on batch 2, next to the reverted batch 3
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 4])], None, None).unwrap();
+        assert_eq!(db::batch::get_batch(&conn, 4).unwrap().parent_id, Some(2));
+
+        // Reverting batch 2 reverts both branches built on it, but not its parent
+        TrieCache::update_batch_status(&conn, 2, BatchStatus::Reverted, None).unwrap();
+        let statuses = [1, 2, 3, 4].map(|id| db::batch::get_batch(&conn, id).unwrap().status);
+        assert_eq!(
+            statuses,
+            [
+                BatchStatus::Created,
+                BatchStatus::Reverted,
+                BatchStatus::Reverted,
+                BatchStatus::Reverted
+            ]
+        );
+
+        // The batch reverted earlier keeps a single reverted entry in its history
+        let history = db::batch::get_status_history(&conn, 3).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .filter(|change| change.status == BatchStatus::Reverted)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_batch_after_finalize() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 1])], None, None).unwrap();
+        TrieCache::finalize_batch(&conn, 1, false, None).unwrap();
+
+        // A finalized batch is the parent of the next batch, rather than a new trie
+        let proof = TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 2])], None, None)
+            .unwrap()
+            .remove(0);
+        let finalized = db::batch::get_batch(&conn, 1).unwrap();
+        let batch = db::batch::get_batch(&conn, 2).unwrap();
+        assert_eq!(batch.parent_id, Some(finalized.id));
+        assert_eq!(batch.pre_root_idx, finalized.root_idx);
+        assert_eq!(batch.pre_root, finalized.root_hash);
+        assert_eq!(proof.pre_root, finalized.root_hash);
+    }
+
+    #[test]
+    fn test_parent_is_latest_unreverted_batch() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let kept = CachedItem::new(vec![0, 1]);
+        TrieCache::create_batch(&conn, vec![kept.clone()], None, None).unwrap();
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 2])], None, None).unwrap();
+        TrieCache::finalize_batch(&conn, 1, false, None).unwrap();
+        TrieCache::update_batch_status(&conn, 2, BatchStatus::Reverted, None).unwrap();
+
+        // Picking the latest created batch, as new batches used to, finds none and would start
+        // a new trie, dropping the finalized state of batch 1
+        let latest_created: Option<u64> = conn
+            .query_row(
+                "SELECT id FROM batches WHERE status = ? ORDER BY id DESC LIMIT 1",
+                [BatchStatus::Created.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap();
+        assert_eq!(latest_created, None);
+
+        let proof = TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 3])], None, None)
+            .unwrap()
+            .remove(0);
+        let finalized = db::batch::get_batch(&conn, 1).unwrap();
+        assert_eq!(db::batch::get_batch(&conn, 3).unwrap().parent_id, Some(1));
+        assert_eq!(proof.pre_root, finalized.root_hash);
+        assert!(db::item::get_item(&conn, &kept.key, Some(3))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_finalize_batch_cascade_reverted_ancestor() {
+        let test_ctx = db::test::TestContext::new();
+        let _ = test_ctx.batch_seeding();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        // Revert batch 2 only, leaving batch 3 created on top of it
+        db::batch::update_batch_status(&conn, &2, BatchStatus::Reverted).unwrap();
+
+        assert!(matches!(
+            TrieCache::finalize_batch(&conn, 3, true, None),
+            Err(TrieCacheError::BatchAncestorReverted)
+        ));
+        assert_eq!(
+            db::batch::get_batch(&conn, 3).unwrap().status,
+            BatchStatus::Created
+        );
+    }
+
+    #[test]
+    fn test_range_proof() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let shared = CachedItem::new(vec![1, 2, 3]);
+        let items = vec![shared.clone(), CachedItem::new(vec![4])];
+        let first = TrieCache::create_batch(&conn, items, None, None)
+            .unwrap()
+            .remove(0);
+        TrieCache::create_batch(&conn, vec![CachedItem::new(vec![5])], None, None).unwrap();
+        let items = vec![shared.clone(), CachedItem::new(vec![6])];
+        let last = TrieCache::create_batch(&conn, items, None, None)
+            .unwrap()
+            .remove(0);
+
+        let proof = TrieCache::get_range_proof(&conn, 1, 3).unwrap();
+        assert_eq!(proof.id, 3);
+        assert_eq!(proof.pre_root, first.pre_root);
+        assert_eq!(proof.post_root, last.post_root);
+        // The range starts from the empty trie, which the verifier doesn't look up, so the
+        // preimage holds the nodes of the post-update paths
+        assert_eq!(proof.pre_root, hex::encode(Felt::ZERO.to_be_bytes()));
+        assert!(!proof.preimage.contains_key(&proof.pre_root));
+        assert!(proof.preimage.contains_key(&proof.post_root));
+        let post_nodes: HashSet<_> = proof
+            .proofs
+            .iter()
+            .flat_map(|proof| {
+                assert!(proof.pre.is_empty());
+                proof.post.iter()
+            })
+            .map(|node| hex::encode(node.hash::<PoseidonHash>().to_be_bytes()))
+            .collect();
+        assert_eq!(
+            proof.preimage.keys().cloned().collect::<HashSet<_>>(),
+            post_nodes
+        );
+
+        // Each key appears once, going from its value before the range to its value after it
+        assert_eq!(proof.leaf_updates.len(), 4);
+        let shared_key = hex::encode(shared.key.to_be_bytes());
+        let update = proof
+            .leaf_updates
+            .iter()
+            .find(|update| update.key == shared_key)
+            .unwrap();
+        assert_eq!(update.pre_value, hex::encode(Felt::ZERO.to_be_bytes()));
+        assert_eq!(
+            update.post_value,
+            hex::encode(shared.commitment.to_be_bytes())
+        );
+
+        // The proof takes the empty trie to the post-state of the last batch, as the cairo0
+        // verifier reads it
+        verify_cairo0_input(&serde_json::to_value(&proof).unwrap());
+
+        // A single batch range matches the transition of that batch
+        let single = TrieCache::get_range_proof(&conn, 2, 2).unwrap();
+        assert_eq!(single.pre_root, first.post_root);
+        assert!(single.preimage.contains_key(&single.pre_root));
+        verify_cairo0_input(&serde_json::to_value(&single).unwrap());
+        verify_cairo0_input(
+            &serde_json::to_value(TrieCache::get_range_proof(&conn, 2, 3).unwrap()).unwrap(),
+        );
+
+        assert!(matches!(
+            TrieCache::get_range_proof(&conn, 3, 1),
+            Err(TrieCacheError::InvalidBatchRange)
+        ));
+        TrieCache::update_batch_status(&conn, 3, BatchStatus::Reverted, None).unwrap();
+        assert!(matches!(
+            TrieCache::get_range_proof(&conn, 1, 3),
+            Err(TrieCacheError::InvalidBatchRange)
+        ));
+    }
+
+    #[test]
+    fn test_cairo0_fixture() {
+        let fixture: Value =
+            serde_json::from_str(include_str!("../../../cairo0/src/mpt_input.json")).unwrap();
+        verify_cairo0_input(&fixture);
+    }
+
+    #[test]
+    fn test_revert_batch() {
+        let test_ctx = db::test::TestContext::new();
+        let _ = test_ctx.batch_seeding();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        // Reverting a batch reverts the batches built on top of it
+        assert!(TrieCache::update_batch_status(&conn, 2, BatchStatus::Reverted, None).is_ok());
+        assert_eq!(
+            db::batch::get_batch(&conn, 2).unwrap().status,
+            BatchStatus::Reverted
+        );
+        assert_eq!(
+            db::batch::get_batch(&conn, 3).unwrap().status,
+            BatchStatus::Reverted
+        );
+        assert!(db::batch::get_batch(&conn, 3)
+            .unwrap()
+            .reverted_at
+            .is_some());
+    }
+
+    #[test]
+    fn test_batch_after_revert() {
+        let test_ctx = db::test::TestContext::new();
+        let conn = test_ctx.manager.get_connection().unwrap();
+
+        let items: Vec<_> = (1..5)
+            .map(|value| CachedItem::new(vec![0, value]))
+            .collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        let items: Vec<_> = (5..9)
+            .map(|value| CachedItem::new(vec![0, value]))
+            .collect();
+        TrieCache::create_batch(&conn, items, None, None).unwrap();
+        TrieCache::update_batch_status(&conn, 2, BatchStatus::Reverted, None).unwrap();
+
+        // The parent's root isn't the highest index, as the reverted batch wrote nodes after it
+        let proof = TrieCache::create_batch(&conn, vec![CachedItem::new(vec![0, 9])], None, None)
+            .unwrap()
+            .remove(0);
+        let parent = db::batch::get_batch(&conn, 1).unwrap();
+        let reverted = db::batch::get_batch(&conn, 2).unwrap();
+        let batch = db::batch::get_batch(&conn, 3).unwrap();
+        assert_eq!(batch.pre_root_idx, parent.root_idx);
+        assert!(batch.root_idx > reverted.root_idx);
+        let root_hash = Trie::root_hash(&TrieDB::new(&conn), batch.root_idx).unwrap();
+        assert_eq!(hex::encode(root_hash.to_be_bytes()), batch.root_hash);
+        assert_eq!(proof.post_root, batch.root_hash);
+        assert_eq!(
+            TrieCache::get_range_proof(&conn, 3, 3).unwrap().post_root,
+            batch.root_hash
+        );
     }
 }
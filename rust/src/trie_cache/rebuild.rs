@@ -0,0 +1,341 @@
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::info;
+
+use crate::db;
+use crate::db::migration::{self, GENESIS_LEAF_FLAG};
+use crate::db::trie::TrieDB;
+use crate::errors::TrieCacheError;
+use crate::models::batch::{Batch, BatchQuery, BatchStatus};
+use crate::models::page::SortOrder;
+use crate::trie_cache::item::CachedItem;
+use crate::trie_cache::trie::Trie;
+
+/// The outcome of rebuilding the trie from the leaves of a database.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RebuildReport {
+    /// The number of batches whose leaves were replayed before the first mismatch, if any,
+    /// reverted batches included.
+    pub batches_replayed: u64,
+    /// The number of reverted batches whose leaves were collected, copied without being replayed.
+    pub batches_tombstoned: u64,
+    pub leaves_replayed: u64,
+    pub nodes_written: u64,
+    /// The first batch whose recomputed root doesn't match the recorded one. Nothing is written
+    /// to the target if there is one.
+    pub first_mismatch: Option<RootMismatch>,
+}
+
+/// A batch whose recomputed root doesn't match the root recorded for it.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RootMismatch {
+    pub batch_id: u64,
+    pub recorded_root: String,
+    pub recomputed_root: String,
+}
+
+/// Rebuilds the trie of a database into another one by replaying its batches from their leaves.
+///
+/// Batches are replayed in ID order through `Trie::persist_batch_and_generate_proofs`, each on
+/// top of its parent's rebuilt root, or on top of a new trie if it has none. Every recomputed root
+/// must match the recorded one, except for pruned batches, whose overwritten leaves may have been
+/// collected. Reverted batches are replayed as well, so their root index points at their recorded
+/// root. Reverted batches whose leaves were collected are copied as tombstones instead, with both
+/// root indices pointing at the rebuilt pre-state root, as their trie can no longer be read. Batches
+/// keep their IDs, status history, commitments and receipts, while their node indices are those of
+/// the rebuilt trie.
+///
+/// The source is only read, and must already be at the schema version of this binary. The target
+/// must be an empty, migrated database, and is written in a single transaction.
+///
+/// # Arguments
+///
+/// * `source` - A reference to a pooled connection to the database to rebuild.
+/// * `target` - A reference to a pooled connection to the database to rebuild into.
+///
+/// # Returns
+///
+/// A `RebuildReport` describing the replay and the first root mismatch, if any, or a TrieCacheError if an error occurs.
+pub fn rebuild(
+    source: &PooledConnection<SqliteConnectionManager>,
+    target: &PooledConnection<SqliteConnectionManager>,
+) -> Result<RebuildReport, TrieCacheError> {
+    let found = migration::schema_version(source)?;
+    if found != migration::SCHEMA_VERSION {
+        return Err(TrieCacheError::SchemaOutdated {
+            found,
+            required: migration::SCHEMA_VERSION,
+        });
+    }
+    if db::batch::get_latest_batch_id(target)?.is_some() || TrieDB::new(target).get_node_idx()? != 0
+    {
+        return Err(TrieCacheError::TargetNotEmpty);
+    }
+
+    let _snapshot = source.unchecked_transaction()?;
+    let tx = target.unchecked_transaction()?;
+    // New tries of the target must start out like the ones of the source
+    if migration::has_flag(source, GENESIS_LEAF_FLAG)? {
+        migration::set_flag(target, GENESIS_LEAF_FLAG)?;
+    }
+
+    let mut report = RebuildReport {
+        batches_replayed: 0,
+        batches_tombstoned: 0,
+        leaves_replayed: 0,
+        nodes_written: 0,
+        first_mismatch: None,
+    };
+    // The rebuilt root index of every copied batch
+    let mut root_idxs: HashMap<u64, u64> = HashMap::new();
+
+    let mut query = BatchQuery {
+        limit: Some(1000),
+        order: SortOrder::Asc,
+        ..Default::default()
+    };
+    loop {
+        let page = db::batch::get_batches(source, &query)?;
+        for batch in page.items {
+            let pre_root_idx = match batch.parent_id {
+                Some(parent_id) => Some(
+                    *root_idxs
+                        .get(&parent_id)
+                        .ok_or(TrieCacheError::BatchNotFound)?,
+                ),
+                None => None,
+            };
+
+            let items = db::item::get_batch_items(source, batch.id)?;
+            // Garbage collection deletes every leaf of reverted batches
+            let collected =
+                batch.status == BatchStatus::Reverted && items.len() as u64 != batch.item_count;
+            let rebuilt = if collected {
+                report.batches_tombstoned += 1;
+                tombstone(batch, pre_root_idx)
+            } else {
+                match replay(target, batch, items, pre_root_idx, &mut report)? {
+                    Some(rebuilt) => rebuilt,
+                    None => return Ok(report),
+                }
+            };
+
+            let history = db::batch::get_status_history(source, rebuilt.id)?;
+            db::batch::restore_batch(target, &rebuilt, &history)?;
+            for receipt in db::receipt::get_receipts(source, rebuilt.id)? {
+                db::receipt::insert_receipt(target, &receipt)?;
+            }
+            root_idxs.insert(rebuilt.id, rebuilt.root_idx);
+        }
+
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+
+    tx.commit()?;
+    info!("Rebuild finished: {:?}", report);
+    Ok(report)
+}
+
+/// Replays the leaves of a batch on top of its rebuilt pre-state root.
+///
+/// Returns the batch with its rebuilt node indices, or `None` if its recomputed root doesn't
+/// match the recorded one, in which case the mismatch is added to the report.
+fn replay(
+    target: &PooledConnection<SqliteConnectionManager>,
+    batch: Batch,
+    items: Vec<CachedItem>,
+    pre_root_idx: Option<u64>,
+    report: &mut RebuildReport,
+) -> Result<Option<Batch>, TrieCacheError> {
+    let (storage, trie, pre_root_idx) = match pre_root_idx {
+        Some(pre_root_idx) => {
+            let (storage, trie) = Trie::load(pre_root_idx, target);
+            (storage, trie, pre_root_idx)
+        }
+        None => Trie::new(target)?,
+    };
+
+    let leaf_count = items.len() as u64;
+    let persisted =
+        Trie::persist_batch_and_generate_proofs(storage, trie, pre_root_idx, items, |_| {
+            Ok(batch.id)
+        })?;
+
+    let recomputed_root = hex::encode(persisted.root_hash.to_be_bytes());
+    if recomputed_root != batch.root_hash && batch.pruned_at.is_none() {
+        report.first_mismatch = Some(RootMismatch {
+            batch_id: batch.id,
+            recorded_root: batch.root_hash,
+            recomputed_root,
+        });
+        return Ok(None);
+    }
+
+    report.batches_replayed += 1;
+    report.leaves_replayed += leaf_count;
+    report.nodes_written += persisted.nodes_added;
+    Ok(Some(Batch {
+        root_idx: persisted.root_idx,
+        pre_root_idx,
+        nodes_added: Some(persisted.nodes_added),
+        ..batch
+    }))
+}
+
+/// Copies a reverted batch whose leaves were collected without replaying it.
+///
+/// Both root indices point at the rebuilt pre-state root, or at 0 for a first batch, so the
+/// tombstone doesn't reference nodes of another batch.
+fn tombstone(batch: Batch, pre_root_idx: Option<u64>) -> Batch {
+    let pre_root_idx = pre_root_idx.unwrap_or(0);
+    Batch {
+        root_idx: pre_root_idx,
+        pre_root_idx,
+        nodes_added: Some(0),
+        ..batch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trie_cache::TrieCache;
+    use crate::trie_cache::{gc, integrity};
+    use pathfinder_common::hash::PoseidonHash;
+    use pathfinder_crypto::Felt;
+    use rusqlite::params;
+
+    #[test]
+    fn test_rebuild() {
+        let source_ctx = db::test::TestContext::new();
+        let source = source_ctx.manager.get_connection().unwrap();
+        let items: Vec<_> = (0..4).map(|value| CachedItem::new(vec![value])).collect();
+        TrieCache::create_batch(&source, items, None, None).unwrap();
+        let items: Vec<_> = (4..8).map(|value| CachedItem::new(vec![value])).collect();
+        TrieCache::create_batch(&source, items, None, None).unwrap();
+        TrieCache::create_batch(&source, vec![CachedItem::new(vec![8])], None, None).unwrap();
+        TrieCache::update_batch_status(&source, 3, BatchStatus::Reverted, None).unwrap();
+        TrieCache::finalize_batch(&source, 2, true, None).unwrap();
+
+        let target_ctx = db::test::TestContext::new();
+        let target = target_ctx.manager.get_connection().unwrap();
+        let report = rebuild(&source, &target).unwrap();
+        assert_eq!(report.first_mismatch, None);
+        assert_eq!(report.batches_replayed, 3);
+        assert_eq!(report.leaves_replayed, 9);
+
+        // The rebuilt database holds the same batches on a consistent trie
+        for id in 1..=3 {
+            let recorded = db::batch::get_batch(&source, id).unwrap();
+            let rebuilt = db::batch::get_batch(&target, id).unwrap();
+            assert_eq!(rebuilt.status, recorded.status);
+            assert_eq!(rebuilt.root_hash, recorded.root_hash);
+            assert_eq!(rebuilt.batch_commitment, recorded.batch_commitment);
+            assert_eq!(
+                db::batch::get_status_history(&target, id).unwrap(),
+                db::batch::get_status_history(&source, id).unwrap()
+            );
+        }
+        let integrity = integrity::verify_db::<PoseidonHash>(&target).unwrap();
+        assert_eq!(integrity.issues, vec![]);
+        TrieCache::get_range_proof(&target, 1, 2).unwrap();
+
+        // The root index of the reverted batch points at its recorded root
+        let reverted = db::batch::get_batch(&target, 3).unwrap();
+        let root_hash: Vec<u8> = target
+            .query_row(
+                "SELECT hash FROM trie_nodes WHERE trie_idx = ?",
+                params![reverted.root_idx],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(hex::encode(root_hash), reverted.root_hash);
+
+        // A database can only be rebuilt into an empty one
+        assert!(matches!(
+            rebuild(&source, &target),
+            Err(TrieCacheError::TargetNotEmpty)
+        ));
+
+        // A tampered leaf changes the recomputed root of its batch, and nothing is written
+        source
+            .execute(
+                "UPDATE leaves SET commitment = ? WHERE batch_id = 2",
+                params![Felt::from_u64(1).to_be_bytes().to_vec()],
+            )
+            .unwrap();
+        let target_ctx = db::test::TestContext::new();
+        let target = target_ctx.manager.get_connection().unwrap();
+        let mismatch = rebuild(&source, &target).unwrap().first_mismatch.unwrap();
+        assert_eq!(mismatch.batch_id, 2);
+        assert_eq!(db::batch::get_latest_batch_id(&target).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rebuild_tombstones_collected_batches() {
+        let source_ctx = db::test::TestContext::new();
+        let source = source_ctx.manager.get_connection().unwrap();
+        TrieCache::create_batch(&source, vec![CachedItem::new(vec![0, 1])], None, None).unwrap();
+        TrieCache::create_batch(&source, vec![CachedItem::new(vec![0, 2])], None, None).unwrap();
+        TrieCache::update_batch_status(&source, 2, BatchStatus::Reverted, None).unwrap();
+        TrieCache::create_batch(&source, vec![CachedItem::new(vec![0, 3])], None, None).unwrap();
+
+        // The leaves of the reverted batch are gone once garbage was collected
+        gc::collect_garbage(&source).unwrap();
+        let target_ctx = db::test::TestContext::new();
+        let target = target_ctx.manager.get_connection().unwrap();
+        let report = rebuild(&source, &target).unwrap();
+        assert_eq!(report.first_mismatch, None);
+        assert_eq!(report.batches_replayed, 2);
+        assert_eq!(report.batches_tombstoned, 1);
+        assert_eq!(report.leaves_replayed, 2);
+
+        // The tombstone keeps its recorded roots and history, and points at its parent's trie
+        let recorded = db::batch::get_batch(&source, 2).unwrap();
+        let tombstone = db::batch::get_batch(&target, 2).unwrap();
+        assert_eq!(tombstone.status, BatchStatus::Reverted);
+        assert_eq!(tombstone.root_hash, recorded.root_hash);
+        assert_eq!(tombstone.batch_commitment, recorded.batch_commitment);
+        assert_eq!(
+            db::batch::get_status_history(&target, 2).unwrap(),
+            db::batch::get_status_history(&source, 2).unwrap()
+        );
+        let parent = db::batch::get_batch(&target, 1).unwrap();
+        assert_eq!(tombstone.root_idx, parent.root_idx);
+        assert_eq!(tombstone.pre_root_idx, parent.root_idx);
+        assert_eq!(
+            db::batch::get_batch(&target, 3).unwrap().root_hash,
+            db::batch::get_batch(&source, 3).unwrap().root_hash
+        );
+        let integrity = integrity::verify_db::<PoseidonHash>(&target).unwrap();
+        assert_eq!(integrity.issues, vec![]);
+    }
+
+    #[test]
+    fn test_rebuild_refuses_outdated_sources() {
+        let source_ctx = db::test::TestContext::new();
+        let source = source_ctx.manager.get_connection().unwrap();
+        TrieCache::create_batch(&source, vec![CachedItem::new(vec![0, 1])], None, None).unwrap();
+        let target_ctx = db::test::TestContext::new();
+        let target = target_ctx.manager.get_connection().unwrap();
+
+        // The source isn't migrated
+        source
+            .execute(
+                "DELETE FROM schema_version WHERE version = ?",
+                params![migration::SCHEMA_VERSION],
+            )
+            .unwrap();
+        assert!(matches!(
+            rebuild(&source, &target),
+            Err(TrieCacheError::SchemaOutdated { found, required })
+                if found == migration::SCHEMA_VERSION - 1 && required == migration::SCHEMA_VERSION
+        ));
+        assert_eq!(db::batch::get_latest_batch_id(&target).unwrap(), None);
+    }
+}
@@ -0,0 +1,212 @@
+use pathfinder_crypto::hash::poseidon_hash_many;
+use pathfinder_crypto::signature::{ecdsa_sign, ecdsa_verify, get_pk};
+use pathfinder_crypto::{Felt, MontFelt};
+use std::path::Path;
+
+use crate::errors::TrieCacheError;
+use crate::models::batch::{Batch, BatchStatus};
+use crate::models::receipt::Receipt;
+use crate::trie_cache::parse_felt;
+
+/// The environment variable naming the file that holds the Stark private key of the operator.
+pub const SIGNING_KEY_FILE_ENV: &str = "SN_MPT_SIGNING_KEY_FILE";
+
+/// Signs batch receipts with the Stark private key of the operator.
+pub struct Signer {
+    private_key: Felt,
+    public_key: Felt,
+}
+
+impl Signer {
+    /// Creates a signer for a Stark private key.
+    ///
+    /// # Arguments
+    ///
+    /// * `private_key` - The private key.
+    ///
+    /// # Returns
+    ///
+    /// The signer, or a `TrieCacheError` if the key isn't a valid private key.
+    pub fn new(private_key: Felt) -> Result<Self, TrieCacheError> {
+        let public_key = get_pk(private_key).ok_or(TrieCacheError::InvalidSigningKey)?;
+        Ok(Signer {
+            private_key,
+            public_key,
+        })
+    }
+
+    /// Loads the private key from a file holding it as a hex string, with or without `0x` prefix.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the key file.
+    ///
+    /// # Returns
+    ///
+    /// The signer, or a `TrieCacheError` if the file can't be read or holds no valid private key.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TrieCacheError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|_| TrieCacheError::InvalidSigningKey)?;
+        let hex_key = contents.trim().trim_start_matches("0x");
+        let private_key =
+            Felt::from_hex_str(hex_key).map_err(|_| TrieCacheError::InvalidSigningKey)?;
+        Signer::new(private_key)
+    }
+
+    /// Loads the signer from the key file named by `SN_MPT_SIGNING_KEY_FILE`.
+    ///
+    /// # Returns
+    ///
+    /// The signer, `None` if the variable isn't set, or a `TrieCacheError` if the key can't be loaded.
+    pub fn from_env() -> Result<Option<Self>, TrieCacheError> {
+        match std::env::var_os(SIGNING_KEY_FILE_ENV) {
+            Some(path) => Signer::from_file(path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the hex-encoded public key of the signer.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.public_key.to_be_bytes())
+    }
+
+    /// Signs a receipt attesting that a batch reached a status.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - The batch to sign.
+    /// * `status` - The status the receipt attests to.
+    ///
+    /// # Returns
+    ///
+    /// The signed receipt, or a `TrieCacheError` if signing fails.
+    pub fn sign(&self, batch: &Batch, status: BatchStatus) -> Result<Receipt, TrieCacheError> {
+        let mut receipt = Receipt {
+            batch_id: batch.id,
+            parent_id: batch.parent_id,
+            pre_root: batch.pre_root.clone(),
+            post_root: batch.root_hash.clone(),
+            status,
+            public_key: self.public_key(),
+            signature_r: String::new(),
+            signature_s: String::new(),
+        };
+
+        let (r, s) = ecdsa_sign(self.private_key, Signer::message_hash(&receipt)?)
+            .map_err(|_| TrieCacheError::SigningFailed)?;
+        receipt.signature_r = hex::encode(r.to_be_bytes());
+        receipt.signature_s = hex::encode(s.to_be_bytes());
+
+        Ok(receipt)
+    }
+
+    /// Computes the message signed for a receipt.
+    ///
+    /// The message is the Poseidon hash of `(batch_id, pre_root, post_root, parent_id, status)`,
+    /// with the parent ID of the first batch as 0 and the status as its numeric value (1 for
+    /// created, 2 for finalized). Signing the status keeps a creation receipt from standing in
+    /// for a finalization one.
+    ///
+    /// # Arguments
+    ///
+    /// * `receipt` - The receipt.
+    ///
+    /// # Returns
+    ///
+    /// The message hash, or a `TrieCacheError` if the roots of the receipt can't be parsed.
+    pub fn message_hash(receipt: &Receipt) -> Result<Felt, TrieCacheError> {
+        let felts = [
+            Felt::from_u64(receipt.batch_id),
+            parse_felt(&receipt.pre_root)?,
+            parse_felt(&receipt.post_root)?,
+            Felt::from_u64(receipt.parent_id.unwrap_or(0)),
+            Felt::from_u64(receipt.status.code()),
+        ];
+
+        Ok(poseidon_hash_many(&felts.map(MontFelt::from)).into())
+    }
+}
+
+/// Verifies the signature of a receipt against the public key it names.
+///
+/// Consumers should also check that the public key is the one of the operator they trust.
+///
+/// # Arguments
+///
+/// * `receipt` - The receipt to verify.
+///
+/// # Returns
+///
+/// Ok(()) if the signature is valid, or a `TrieCacheError` if it isn't.
+pub fn verify_receipt(receipt: &Receipt) -> Result<(), TrieCacheError> {
+    ecdsa_verify(
+        parse_felt(&receipt.public_key)?,
+        Signer::message_hash(receipt)?,
+        parse_felt(&receipt.signature_r)?,
+        parse_felt(&receipt.signature_s)?,
+    )
+    .map_err(|_| TrieCacheError::InvalidReceiptSignature)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let test_ctx = db::test::TestContext::new();
+        let batches = test_ctx.batch_seeding();
+        let signer = Signer::new(Felt::from_u64(42)).unwrap();
+
+        let receipt = signer.sign(&batches[1], BatchStatus::Created).unwrap();
+        assert_eq!(receipt.parent_id, Some(1));
+        assert_eq!(receipt.pre_root, batches[1].pre_root);
+        assert_eq!(receipt.post_root, batches[1].root_hash);
+        assert!(verify_receipt(&receipt).is_ok());
+
+        // The signature covers the status it attests to
+        let finalized = signer.sign(&batches[1], BatchStatus::Finalized).unwrap();
+        assert!(verify_receipt(&finalized).is_ok());
+        assert_ne!(
+            Signer::message_hash(&finalized).unwrap(),
+            Signer::message_hash(&receipt).unwrap()
+        );
+        let reparented = Receipt {
+            parent_id: None,
+            ..finalized
+        };
+        assert!(matches!(
+            verify_receipt(&reparented),
+            Err(TrieCacheError::InvalidReceiptSignature)
+        ));
+
+        let tampered = Receipt {
+            post_root: batches[2].root_hash.clone(),
+            ..receipt
+        };
+        assert!(matches!(
+            verify_receipt(&tampered),
+            Err(TrieCacheError::InvalidReceiptSignature)
+        ));
+
+        assert!(matches!(
+            Signer::new(Felt::ZERO),
+            Err(TrieCacheError::InvalidSigningKey)
+        ));
+    }
+
+    #[test]
+    fn test_from_file() {
+        let file = format!("{}_signing.key", rand::random::<u32>());
+        std::fs::write(&file, "0x2a\n").unwrap();
+        let signer = Signer::from_file(&file);
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(
+            signer.unwrap().public_key(),
+            Signer::new(Felt::from_u64(42)).unwrap().public_key()
+        );
+        assert!(Signer::from_file(&file).is_err());
+    }
+}
@@ -0,0 +1,653 @@
+use bitvec::prelude::*;
+use pathfinder_common::trie::TrieNode;
+use pathfinder_crypto::Felt;
+use pathfinder_merkle_tree::storage::Storage;
+use pathfinder_storage::StoredNode;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use tracing::info;
+
+use crate::db;
+use crate::db::trie::TrieDB;
+use crate::errors::TrieCacheError;
+use crate::models::batch::{BatchStatus, NewBatch};
+use crate::trie_cache::item::CachedItem;
+use crate::trie_cache::parse_felt;
+use crate::trie_cache::trie::{Trie, TrieHash, EMPTY_ROOT_IDX};
+
+/// The bytes every snapshot starts with.
+const MAGIC: &[u8; 4] = b"SMPS";
+/// The version of the snapshot format, bumped on every incompatible change.
+const VERSION: u8 = 1;
+/// The hash function the nodes of a snapshot are hashed with.
+const HASH_FUNCTION: &str = "poseidon";
+/// The height of the trie, in bits of the leaf keys.
+const HEIGHT: u64 = 251;
+/// The ID SQLite gives the first row of the empty `batches` table.
+const FIRST_BATCH_ID: u64 = 1;
+
+const LEAF_TAG: u8 = 0;
+const BINARY_NODE_TAG: u8 = 1;
+const EDGE_NODE_TAG: u8 = 2;
+const LEAF_BINARY_NODE_TAG: u8 = 3;
+const LEAF_EDGE_NODE_TAG: u8 = 4;
+const END_TAG: u8 = 0xff;
+
+/// A summary of an exported or imported snapshot.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SnapshotSummary {
+    /// The batch the trie was exported at, or the genesis batch it was imported as.
+    pub batch_id: u64,
+    pub root_hash: String,
+    pub nodes: u64,
+    pub leaves: u64,
+}
+
+/// Exports the trie at a batch into a self-contained snapshot.
+///
+/// The snapshot starts with the `SMPS` magic and a version byte, followed by a header holding the
+/// root hash, the varint-prefixed name of the hash function, the varint height of the trie and the
+/// varint ID of the batch. The nodes reachable from the root follow in post-order, so every node
+/// comes after its children, and children are referenced by their varint position among the nodes
+/// of the snapshot. Every node record is a tag and the node's hash, followed by `left, right` for
+/// binary nodes, `child, length, path` for edge nodes, nothing for binary nodes over two leaves and
+/// `length, path` for edge nodes over a leaf. The leaves below a leaf-level node are written right
+/// before it, as its key, commitment and varint-prefixed value. The snapshot ends with a trailer
+/// holding the varint node and leaf counts. Felts are written as raw 32-byte big-endian values and
+/// varints as unsigned LEB128.
+///
+/// The trie is walked depth-first and written as it's walked, so only the path to the current
+/// node is held in memory.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a pooled SQLite connection.
+/// * `batch_id` - The ID of the batch whose post-state trie is exported.
+/// * `out` - The writer the snapshot is streamed into.
+///
+/// # Returns
+///
+/// A `SnapshotSummary` of the exported trie, or a TrieCacheError if the batch was reverted or pruned or an error occurs.
+pub fn export_snapshot(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    batch_id: u64,
+    out: &mut impl Write,
+) -> Result<SnapshotSummary, TrieCacheError> {
+    // The trie is read from a single snapshot of the database
+    let _tx = conn.unchecked_transaction()?;
+    let batch = db::batch::get_batch(conn, batch_id)?;
+    if batch.status == BatchStatus::Reverted {
+        return Err(TrieCacheError::BatchReverted);
+    }
+    if batch.pruned_at.is_some() {
+        return Err(TrieCacheError::RootPruned);
+    }
+    let root_hash = parse_felt(&batch.root_hash)?;
+
+    write(out, MAGIC)?;
+    write(out, &[VERSION])?;
+    write(out, &root_hash.to_be_bytes())?;
+    write_varint(out, HASH_FUNCTION.len() as u64)?;
+    write(out, HASH_FUNCTION.as_bytes())?;
+    write_varint(out, HEIGHT)?;
+    write_varint(out, batch_id)?;
+
+    let mut exporter = Exporter {
+        conn,
+        storage: TrieDB::at_batch(conn, batch_id),
+        batch_id,
+        out,
+        nodes: 0,
+        leaves: 0,
+    };
+    if batch.root_idx != EMPTY_ROOT_IDX {
+        exporter.write_node(batch.root_idx, BitVec::new())?;
+    }
+    let (nodes, leaves) = (exporter.nodes, exporter.leaves);
+
+    write(out, &[END_TAG])?;
+    write_varint(out, nodes)?;
+    write_varint(out, leaves)?;
+    out.flush().map_err(anyhow::Error::from)?;
+
+    let summary = SnapshotSummary {
+        batch_id,
+        root_hash: batch.root_hash,
+        nodes,
+        leaves,
+    };
+    info!("Snapshot exported: {:?}", summary);
+    Ok(summary)
+}
+
+/// Imports a snapshot into an empty database as a genesis batch.
+///
+/// The batch is created on top of a new trie, with every leaf of the snapshot as its items, and
+/// starts out as `created`. Records are written as they're read, so only the leaves of the current
+/// leaf-level node are held in memory. The commitment and key of every leaf are recomputed from its
+/// value, like `CachedItem::new` does, and every key must match the path of the leaf in the trie.
+/// Every node hash is recomputed from its children and leaves, and the import is rolled back if
+/// one doesn't match the snapshot, or if the last node doesn't hash to the root in the header.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a pooled SQLite connection to an empty, initialized database.
+/// * `input` - The reader the snapshot is streamed from.
+///
+/// # Returns
+///
+/// A `SnapshotSummary` of the imported trie, or a TrieCacheError if the snapshot is invalid or an error occurs.
+pub fn import_snapshot(
+    conn: &PooledConnection<SqliteConnectionManager>,
+    input: &mut impl Read,
+) -> Result<SnapshotSummary, TrieCacheError> {
+    if db::batch::get_latest_batch_id(conn)?.is_some() || TrieDB::new(conn).get_node_idx()? != 0 {
+        return Err(TrieCacheError::TargetNotEmpty);
+    }
+
+    if read(input, MAGIC.len())? != MAGIC || read(input, 1)?[0] != VERSION {
+        return Err(TrieCacheError::InvalidSnapshot);
+    }
+    let root_hash = read_felt(input)?;
+    let hash_function_length = read_varint(input)? as usize;
+    if read(input, hash_function_length)? != HASH_FUNCTION.as_bytes()
+        || read_varint(input)? != HEIGHT
+    {
+        return Err(TrieCacheError::InvalidSnapshot);
+    }
+    let source_batch_id = read_varint(input)?;
+
+    let tx = conn.unchecked_transaction()?;
+
+    // The batch is applied to a new trie, like the first batch of a database
+    let (storage, _, pre_root_idx) = Trie::new(conn)?;
+    let pre_root = Trie::root_hash(&storage, pre_root_idx)?;
+    // Allocating nothing takes the write lock, so the nodes get consecutive indices from here
+    let first_idx = storage.allocate_node_indices(0)?;
+    // Leaves are streamed before the batch is stored. The database holds no batches, so SQLite
+    // gives the batch the first ID, which is checked once it's stored
+    let batch_id = FIRST_BATCH_ID;
+
+    let mut nodes = 0u64;
+    let mut leaves: Vec<CachedItem> = vec![];
+    // The height and a leaf key of the subtrees that have no parent yet, by position
+    let mut subtrees: HashMap<u64, (usize, Felt)> = HashMap::new();
+    let mut leaf_count = 0u64;
+    let mut last_hash = None;
+    let mut root_idx = EMPTY_ROOT_IDX;
+    loop {
+        let tag = read(input, 1)?[0];
+        if tag == LEAF_TAG {
+            let key = read_felt(input)?;
+            let commitment = read_felt(input)?;
+            let value_length = read_varint(input)? as usize;
+            let item = CachedItem::new(read(input, value_length)?);
+            if item.key != key || item.commitment != commitment {
+                return Err(TrieCacheError::InvalidSnapshot);
+            }
+            storage.persist_leaves(&vec![item.clone()], batch_id)?;
+            leaves.push(item);
+            leaf_count += 1;
+            continue;
+        }
+        if tag == END_TAG {
+            break;
+        }
+
+        let hash = read_felt(input)?;
+        // Children must have been written before their parent, which is the only one to take them
+        let mut child = || -> Result<(u64, (usize, Felt)), TrieCacheError> {
+            let position = read_varint(input)?;
+            let subtree = subtrees
+                .remove(&position)
+                .ok_or(TrieCacheError::InvalidSnapshot)?;
+            Ok((first_idx + position, subtree))
+        };
+        // Every leaf key below a node shares the path to the node, so the key of one leaf is
+        // checked against the path of each node above it
+        let (node, trie_node, subtree) = match tag {
+            BINARY_NODE_TAG => {
+                let ((left, (height, left_key)), (right, (right_height, right_key))) =
+                    (child()?, child()?);
+                if height != right_height || !branches_at(&left_key, &right_key, height) {
+                    return Err(TrieCacheError::InvalidSnapshot);
+                }
+                let trie_node = TrieNode::Binary {
+                    left: stored_hash(&storage, left)?,
+                    right: stored_hash(&storage, right)?,
+                };
+                (
+                    StoredNode::Binary { left, right },
+                    trie_node,
+                    (height + 1, left_key),
+                )
+            }
+            EDGE_NODE_TAG => {
+                let (child, (height, key)) = child()?;
+                let path = read_path(input)?;
+                if !ends_with_path(&key, height, &path) {
+                    return Err(TrieCacheError::InvalidSnapshot);
+                }
+                let trie_node = TrieNode::Edge {
+                    child: stored_hash(&storage, child)?,
+                    path: path.clone(),
+                };
+                let height = height + path.len();
+                (StoredNode::Edge { child, path }, trie_node, (height, key))
+            }
+            LEAF_BINARY_NODE_TAG => match leaves.as_slice() {
+                [left, right] if branches_at(&left.key, &right.key, 0) => (
+                    StoredNode::LeafBinary,
+                    TrieNode::Binary {
+                        left: left.commitment,
+                        right: right.commitment,
+                    },
+                    (1, left.key),
+                ),
+                _ => return Err(TrieCacheError::InvalidSnapshot),
+            },
+            LEAF_EDGE_NODE_TAG => {
+                let path = read_path(input)?;
+                match leaves.as_slice() {
+                    [leaf] if ends_with_path(&leaf.key, 0, &path) => (
+                        StoredNode::LeafEdge { path: path.clone() },
+                        TrieNode::Edge {
+                            child: leaf.commitment,
+                            path: path.clone(),
+                        },
+                        (path.len(), leaf.key),
+                    ),
+                    _ => return Err(TrieCacheError::InvalidSnapshot),
+                }
+            }
+            _ => return Err(TrieCacheError::InvalidSnapshot),
+        };
+        if subtree.0 > HEIGHT as usize {
+            return Err(TrieCacheError::InvalidSnapshot);
+        }
+        if !leaves.is_empty()
+            && !matches!(node, StoredNode::LeafBinary | StoredNode::LeafEdge { .. })
+        {
+            return Err(TrieCacheError::InvalidSnapshot);
+        }
+        leaves.clear();
+
+        if trie_node.hash::<TrieHash>() != hash {
+            return Err(TrieCacheError::InvalidSnapshot);
+        }
+        root_idx = storage.allocate_node_indices(1)?;
+        storage.persist_nodes(vec![(node, hash, root_idx)])?;
+        subtrees.insert(nodes, subtree);
+        nodes += 1;
+        last_hash = Some(hash);
+    }
+
+    if read_varint(input)? != nodes
+        || read_varint(input)? != leaf_count
+        || !leaves.is_empty()
+        || last_hash.unwrap_or(Felt::ZERO) != root_hash
+        // Every node but the root has a parent, and the root is at the top of the trie
+        || subtrees.len() != usize::from(nodes > 0)
+        || subtrees
+            .values()
+            .any(|(height, _)| *height != HEIGHT as usize)
+    {
+        return Err(TrieCacheError::InvalidSnapshot);
+    }
+
+    let stored_id = db::batch::create_batch(
+        conn,
+        &NewBatch {
+            parent_id: None,
+            root_idx,
+            root_hash,
+            pre_root_idx,
+            pre_root,
+            item_count: leaf_count,
+            nodes_added: nodes,
+            group_id: None,
+        },
+    )?;
+    if stored_id != batch_id {
+        return Err(TrieCacheError::TargetNotEmpty);
+    }
+    tx.commit()?;
+
+    let summary = SnapshotSummary {
+        batch_id,
+        root_hash: hex::encode(root_hash.to_be_bytes()),
+        nodes,
+        leaves: leaf_count,
+    };
+    info!(
+        "Snapshot of batch #{} imported: {:?}",
+        source_batch_id, summary
+    );
+    Ok(summary)
+}
+
+/// Streams the nodes and leaves of a trie in post-order.
+struct Exporter<'a, W: Write> {
+    conn: &'a PooledConnection<SqliteConnectionManager>,
+    storage: TrieDB<'a>,
+    batch_id: u64,
+    out: &'a mut W,
+    nodes: u64,
+    leaves: u64,
+}
+
+impl<W: Write> Exporter<'_, W> {
+    /// Writes the subtree at a node and returns the node's position in the snapshot.
+    fn write_node(&mut self, index: u64, path: BitVec<u8, Msb0>) -> Result<u64, TrieCacheError> {
+        let node = self
+            .storage
+            .get(index)?
+            .ok_or(TrieCacheError::NodeNotFound)?;
+        let hash = self
+            .storage
+            .hash(index)?
+            .ok_or(TrieCacheError::NodeNotFound)?;
+
+        match node {
+            StoredNode::Binary { left, right } => {
+                let mut left_path = path.clone();
+                left_path.push(false);
+                let left = self.write_node(left, left_path)?;
+                let mut right_path = path;
+                right_path.push(true);
+                let right = self.write_node(right, right_path)?;
+
+                write(self.out, &[BINARY_NODE_TAG])?;
+                write(self.out, &hash.to_be_bytes())?;
+                write_varint(self.out, left)?;
+                write_varint(self.out, right)?;
+            }
+            StoredNode::Edge { child, path: edge } => {
+                let mut child_path = path;
+                child_path.extend_from_bitslice(&edge);
+                let child = self.write_node(child, child_path)?;
+
+                write(self.out, &[EDGE_NODE_TAG])?;
+                write(self.out, &hash.to_be_bytes())?;
+                write_varint(self.out, child)?;
+                write_path(self.out, &edge)?;
+            }
+            StoredNode::LeafBinary => {
+                for bit in [false, true] {
+                    let mut leaf_path = path.clone();
+                    leaf_path.push(bit);
+                    self.write_leaf(&leaf_path)?;
+                }
+
+                write(self.out, &[LEAF_BINARY_NODE_TAG])?;
+                write(self.out, &hash.to_be_bytes())?;
+            }
+            StoredNode::LeafEdge { path: edge } => {
+                let mut leaf_path = path;
+                leaf_path.extend_from_bitslice(&edge);
+                self.write_leaf(&leaf_path)?;
+
+                write(self.out, &[LEAF_EDGE_NODE_TAG])?;
+                write(self.out, &hash.to_be_bytes())?;
+                write_path(self.out, &edge)?;
+            }
+        }
+
+        self.nodes += 1;
+        Ok(self.nodes - 1)
+    }
+
+    fn write_leaf(&mut self, path: &BitSlice<u8, Msb0>) -> Result<(), TrieCacheError> {
+        let key = Felt::from_bits(path).map_err(|_| TrieCacheError::InvalidSnapshot)?;
+        let item = db::item::get_item(self.conn, &key, Some(self.batch_id))?
+            .ok_or(TrieCacheError::ItemNotFound)?;
+        let decode = |value: &str| hex::decode(value).map_err(|_| TrieCacheError::InvalidHexString);
+        let value = item.value.as_deref().map(decode).transpose()?;
+        let value = value.unwrap_or_default();
+
+        write(self.out, &[LEAF_TAG])?;
+        write(self.out, &key.to_be_bytes())?;
+        write(self.out, &decode(&item.commitment)?)?;
+        write_varint(self.out, value.len() as u64)?;
+        write(self.out, &value)?;
+        self.leaves += 1;
+        Ok(())
+    }
+}
+
+fn write(out: &mut impl Write, bytes: &[u8]) -> Result<(), TrieCacheError> {
+    Ok(out.write_all(bytes).map_err(anyhow::Error::from)?)
+}
+
+fn write_varint(out: &mut impl Write, mut value: u64) -> Result<(), TrieCacheError> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return write(out, &[byte]);
+        }
+        write(out, &[byte | 0x80])?;
+    }
+}
+
+fn write_path(out: &mut impl Write, path: &BitSlice<u8, Msb0>) -> Result<(), TrieCacheError> {
+    let felt = Felt::from_bits(path).map_err(|_| TrieCacheError::InvalidSnapshot)?;
+    write_varint(out, path.len() as u64)?;
+    write(out, &felt.to_be_bytes())
+}
+
+fn read(input: &mut impl Read, len: usize) -> Result<Vec<u8>, TrieCacheError> {
+    // The length is read from the snapshot, so the buffer only grows as bytes arrive
+    let mut bytes = vec![];
+    let read = input
+        .take(len as u64)
+        .read_to_end(&mut bytes)
+        .map_err(anyhow::Error::from)?;
+    if read != len {
+        return Err(TrieCacheError::InvalidSnapshot);
+    }
+
+    Ok(bytes)
+}
+
+fn read_varint(input: &mut impl Read) -> Result<u64, TrieCacheError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        input
+            .read_exact(&mut byte)
+            .map_err(|err| match err.kind() {
+                ErrorKind::UnexpectedEof => TrieCacheError::InvalidSnapshot,
+                _ => anyhow::Error::from(err).into(),
+            })?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(TrieCacheError::InvalidSnapshot)
+}
+
+fn read_felt(input: &mut impl Read) -> Result<Felt, TrieCacheError> {
+    Felt::from_be_slice(&read(input, 32)?).map_err(|_| TrieCacheError::InvalidSnapshot)
+}
+
+fn read_path(input: &mut impl Read) -> Result<BitVec<u8, Msb0>, TrieCacheError> {
+    let length = read_varint(input)? as usize;
+    let felt = read_felt(input)?;
+    let bits = felt.view_bits();
+    if length == 0 || length > HEIGHT as usize || length > bits.len() {
+        return Err(TrieCacheError::InvalidSnapshot);
+    }
+
+    Ok(bits[bits.len() - length..].to_bitvec())
+}
+
+/// Whether two keys share the path to a binary node `height` levels above the leaves, and take
+/// its left and right child respectively.
+fn branches_at(left: &Felt, right: &Felt, height: usize) -> bool {
+    let (left, right) = (left.view_bits(), right.view_bits());
+    let Some(depth) = left.len().checked_sub(height + 1) else {
+        return false;
+    };
+    left[..depth] == right[..depth] && !left[depth] && right[depth]
+}
+
+/// Whether the path of a key continues with an edge path right above a node `height` levels
+/// above the leaves.
+fn ends_with_path(key: &Felt, height: usize, path: &BitSlice<u8, Msb0>) -> bool {
+    let bits = key.view_bits();
+    let Some(start) = bits.len().checked_sub(height + path.len()) else {
+        return false;
+    };
+    bits[start..bits.len() - height] == *path
+}
+
+fn stored_hash(storage: &TrieDB, index: u64) -> Result<Felt, TrieCacheError> {
+    storage.hash(index)?.ok_or(TrieCacheError::NodeNotFound)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trie_cache::integrity;
+    use crate::trie_cache::TrieCache;
+
+    #[test]
+    fn test_export_and_import_snapshot() {
+        let source_ctx = db::test::TestContext::new();
+        let source = source_ctx.manager.get_connection().unwrap();
+        let items: Vec<_> = (1..9)
+            .map(|value| CachedItem::new(vec![0, value]))
+            .collect();
+        TrieCache::create_batch(&source, items, None, None).unwrap();
+        let items: Vec<_> = (9..13)
+            .map(|value| CachedItem::new(vec![0, value]))
+            .collect();
+        TrieCache::create_batch(&source, items, None, None).unwrap();
+
+        // The snapshot holds the state at the batch, without later writes
+        let mut snapshot = vec![];
+        let exported = export_snapshot(&source, 1, &mut snapshot).unwrap();
+        assert_eq!(exported.leaves, 8);
+        assert_eq!(
+            exported.root_hash,
+            db::batch::get_batch(&source, 1).unwrap().root_hash
+        );
+
+        let target_ctx = db::test::TestContext::new();
+        let target = target_ctx.manager.get_connection().unwrap();
+        let imported = import_snapshot(&target, &mut snapshot.as_slice()).unwrap();
+        assert_eq!(imported.batch_id, 1);
+        assert_eq!(imported.root_hash, exported.root_hash);
+        assert_eq!(imported.nodes, exported.nodes);
+        assert_eq!(imported.leaves, exported.leaves);
+
+        let genesis = db::batch::get_batch(&target, 1).unwrap();
+        assert_eq!(genesis.root_hash, exported.root_hash);
+        assert_eq!(genesis.status, BatchStatus::Created);
+        assert_eq!(
+            integrity::verify_db::<TrieHash>(&target).unwrap().issues,
+            vec![]
+        );
+        let item = CachedItem::new(vec![0, 3]);
+        let imported_item = db::item::get_item(&target, &item.key, None).unwrap();
+        assert_eq!(imported_item.unwrap().value, Some(hex::encode([0, 3])));
+
+        // New batches build on the imported trie
+        TrieCache::create_batch(&target, vec![CachedItem::new(vec![0, 13])], None, None).unwrap();
+        TrieCache::get_range_proof(&target, 2, 2).unwrap();
+
+        // A database can only be imported into an empty one
+        assert!(matches!(
+            import_snapshot(&target, &mut snapshot.as_slice()),
+            Err(TrieCacheError::TargetNotEmpty)
+        ));
+
+        // Truncated and corrupted snapshots are rejected, and nothing is written
+        let target_ctx = db::test::TestContext::new();
+        let target = target_ctx.manager.get_connection().unwrap();
+        let truncated = &snapshot[..snapshot.len() - 3];
+        assert!(matches!(
+            import_snapshot(&target, &mut &truncated[..]),
+            Err(TrieCacheError::InvalidSnapshot)
+        ));
+        // The last byte of the root hash in the header
+        let mut corrupted = snapshot.clone();
+        corrupted[36] ^= 1;
+        assert!(matches!(
+            import_snapshot(&target, &mut corrupted.as_slice()),
+            Err(TrieCacheError::InvalidSnapshot)
+        ));
+        assert_eq!(db::batch::get_latest_batch_id(&target).unwrap(), None);
+        assert_eq!(TrieDB::new(&target).get_node_idx().unwrap(), 0);
+
+        // A leaf value that doesn't hash to the commitment and key of its record, right after the
+        // header and the first leaf's tag, key, commitment and length
+        let value_offset = 4 + 1 + 32 + 1 + HASH_FUNCTION.len() + 2 + 1 + 1 + 32 + 32 + 1;
+        assert_eq!(snapshot[value_offset - 66], LEAF_TAG);
+        let mut corrupted = snapshot.clone();
+        corrupted[value_offset + 1] ^= 1;
+        assert!(matches!(
+            import_snapshot(&target, &mut corrupted.as_slice()),
+            Err(TrieCacheError::InvalidSnapshot)
+        ));
+    }
+
+    /// Writes the snapshot of a trie holding a single leaf, below an edge with the given path.
+    fn single_leaf_snapshot(item: &CachedItem, path: &BitSlice<u8, Msb0>) -> Vec<u8> {
+        let node = TrieNode::Edge {
+            child: item.commitment,
+            path: path.to_bitvec(),
+        };
+        let hash = node.hash::<TrieHash>();
+
+        let mut out = vec![];
+        write(&mut out, MAGIC).unwrap();
+        write(&mut out, &[VERSION]).unwrap();
+        write(&mut out, &hash.to_be_bytes()).unwrap();
+        write_varint(&mut out, HASH_FUNCTION.len() as u64).unwrap();
+        write(&mut out, HASH_FUNCTION.as_bytes()).unwrap();
+        write_varint(&mut out, HEIGHT).unwrap();
+        write_varint(&mut out, 1).unwrap();
+        write(&mut out, &[LEAF_TAG]).unwrap();
+        write(&mut out, &item.key.to_be_bytes()).unwrap();
+        write(&mut out, &item.commitment.to_be_bytes()).unwrap();
+        write_varint(&mut out, item.value.len() as u64).unwrap();
+        write(&mut out, &item.value).unwrap();
+        write(&mut out, &[LEAF_EDGE_NODE_TAG]).unwrap();
+        write(&mut out, &hash.to_be_bytes()).unwrap();
+        write_path(&mut out, path).unwrap();
+        write(&mut out, &[END_TAG]).unwrap();
+        write_varint(&mut out, 1).unwrap();
+        write_varint(&mut out, 1).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_import_checks_leaf_paths() {
+        let item = CachedItem::new(vec![0, 1]);
+        let other = CachedItem::new(vec![0, 2]);
+
+        // The hashes of the snapshot are consistent, but the leaf sits at the path of another key
+        let target_ctx = db::test::TestContext::new();
+        let target = target_ctx.manager.get_connection().unwrap();
+        let misplaced = single_leaf_snapshot(&item, other.key.view_bits());
+        assert!(matches!(
+            import_snapshot(&target, &mut misplaced.as_slice()),
+            Err(TrieCacheError::InvalidSnapshot)
+        ));
+
+        let snapshot = single_leaf_snapshot(&item, item.key.view_bits());
+        let imported = import_snapshot(&target, &mut snapshot.as_slice()).unwrap();
+        assert_eq!(imported.leaves, 1);
+        assert_eq!(
+            integrity::verify_db::<TrieHash>(&target).unwrap().issues,
+            vec![]
+        );
+    }
+}
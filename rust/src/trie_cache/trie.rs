@@ -1,7 +1,8 @@
 use super::item::CachedItem;
+use crate::db::migration::{self, GENESIS_LEAF_FLAG};
 use crate::db::trie::TrieDB;
 use crate::errors::TrieCacheError;
-use crate::trie_cache::batch_proof::{BatchProof, LeafUpdate};
+use crate::trie_cache::batch_proof::{BatchProof, LeafProof, LeafUpdate};
 use pathfinder_common::hash::PoseidonHash;
 use pathfinder_common::trie::TrieNode;
 use pathfinder_crypto::Felt;
@@ -13,13 +14,38 @@ use r2d2_sqlite::SqliteConnectionManager;
 
 pub struct Trie {}
 
+/// The hash function of the trie nodes.
+pub type TrieHash = PoseidonHash;
+
+/// The root index of the empty trie. Stored nodes are indexed from 1, so no node is at this index.
+pub const EMPTY_ROOT_IDX: u64 = 0;
+
+/// The roots of a batch whose nodes were written, before the batch itself is stored.
+pub struct BatchRoots {
+    /// The hash of the root the batch was applied to.
+    pub pre_root: Felt,
+    pub root_idx: u64,
+    pub root_hash: Felt,
+    pub nodes_added: u64,
+}
+
+/// The outcome of persisting a batch of items to the trie.
+pub struct PersistedBatch {
+    /// The ID the batch was stored with.
+    pub id: u64,
+    pub proof: BatchProof,
+    pub root_idx: u64,
+    pub root_hash: Felt,
+    pub nodes_added: u64,
+}
+
 /// The Trie struct represents a Merkle Trie data structure.
 impl Trie {
     /// Loads a Trie from the given root index and database connection.
     ///
     /// # Arguments
     ///
-    /// * `root_idx` - The root index of the Trie.
+    /// * `root_idx` - The root index of the Trie, `EMPTY_ROOT_IDX` for the empty trie.
     /// * `conn` - The database connection.
     ///
     /// # Returns
@@ -28,134 +54,217 @@ impl Trie {
     pub fn load(
         root_idx: u64,
         conn: &PooledConnection<SqliteConnectionManager>,
-    ) -> (TrieDB, MerkleTree<PoseidonHash, 251>) {
+    ) -> (TrieDB, MerkleTree<TrieHash, 251>) {
         let storage = TrieDB::new(conn);
-        let trie = MerkleTree::<PoseidonHash, 251>::new(root_idx);
+        let trie = match root_idx {
+            EMPTY_ROOT_IDX => MerkleTree::<TrieHash, 251>::empty(),
+            root_idx => MerkleTree::<TrieHash, 251>::new(root_idx),
+        };
 
         (storage, trie)
     }
 
     /// Creates a new Trie using the given database connection.
     ///
+    /// New tries are empty, except in databases flagged with `genesis_leaf`. These were created
+    /// when every trie started out with a dummy genesis leaf, and keep writing it so their roots
+    /// are unchanged.
+    ///
     /// # Arguments
     ///
     /// * `conn` - The database connection.
     ///
     /// # Returns
     ///
-    /// A tuple containing the TrieDB and the MerkleTree.
+    /// A tuple containing the TrieDB, the MerkleTree and its root index, or a TrieCacheError if an error occurs.
     pub fn new(
         conn: &PooledConnection<SqliteConnectionManager>,
-    ) -> (TrieDB, MerkleTree<PoseidonHash, 251>) {
-        let mut trie = MerkleTree::<PoseidonHash, 251>::empty();
+    ) -> Result<(TrieDB, MerkleTree<TrieHash, 251>, u64), TrieCacheError> {
+        let mut trie = MerkleTree::<TrieHash, 251>::empty();
         let storage = TrieDB::new(conn);
-        // We need to insert and persist a dummy item to initialize the storage for now.
-        // ToDo: figure out how to get around this
+        if !migration::has_flag(conn, GENESIS_LEAF_FLAG)? {
+            return Ok((storage, trie, EMPTY_ROOT_IDX));
+        }
+
         let item = CachedItem::new(vec![0; 32]);
-        let _ = trie.set(&storage, item.key.view_bits().to_bitvec(), item.commitment);
-        let update = trie.clone().commit(&storage).unwrap();
-        let _ = Trie::persist_batch_items(storage, &update, &vec![item], &0);
+        trie.set(&storage, item.key.view_bits().to_bitvec(), item.commitment)?;
+        let update = trie.clone().commit(&storage)?;
+        let root_idx = Trie::persist_update_nodes(storage, &update, EMPTY_ROOT_IDX)?;
+        storage.persist_leaves(&vec![item], 0)?;
 
-        (storage, trie)
+        Ok((storage, trie, root_idx))
+    }
+
+    /// Returns the hash of the root at `root_idx`, zero for the empty trie.
+    pub fn root_hash(storage: &TrieDB, root_idx: u64) -> Result<Felt, TrieCacheError> {
+        match root_idx {
+            EMPTY_ROOT_IDX => Ok(Felt::ZERO),
+            root_idx => storage.hash(root_idx)?.ok_or(TrieCacheError::NodeNotFound),
+        }
     }
 
     /// Adds a batch of items to the Database, and generates the required proofs verifying the transition.
     ///
+    /// The nodes are written first, then `store_batch` stores the batch and returns its ID, which
+    /// the leaves are written with. Post-update proofs read the leaves, so they come last.
+    ///
     /// # Arguments
     ///
     /// * `storage` - The TrieDB.
     /// * `trie` - The MerkleTree.
     /// * `root_idx` - The root index of the Trie.
     /// * `items` - The items to be persisted.
-    /// * `batch_id` - The batch ID.
+    /// * `store_batch` - Stores the batch with its roots and returns its ID.
     ///
     /// # Returns
     ///
-    /// A Result containing the BatchProof and the next index.
+    /// A Result containing the `PersistedBatch` with the batch ID, the BatchProof, the roots and the number of nodes added.
     pub fn persist_batch_and_generate_proofs(
         storage: TrieDB,
-        mut trie: MerkleTree<PoseidonHash, 251>,
+        mut trie: MerkleTree<TrieHash, 251>,
         root_idx: u64,
         items: Vec<CachedItem>,
-        batch_id: &u64,
-    ) -> Result<(BatchProof, u64), TrieCacheError> {
+        store_batch: impl FnOnce(&BatchRoots) -> Result<u64, TrieCacheError>,
+    ) -> Result<PersistedBatch, TrieCacheError> {
         let mut leaf_updates: Vec<LeafUpdate> = vec![];
-        let mut proofs: Vec<Vec<TrieNode>> = vec![];
+        let mut pre_proofs: Vec<Vec<TrieNode>> = vec![];
 
-        let pre_root = match storage.hash(root_idx) {
-            Ok(Some(root)) => root,
-            Ok(None) => return Err(TrieCacheError::NodeNotFound),
-            Err(e) => return Err(TrieCacheError::from(e)),
-        };
+        let pre_root = Trie::root_hash(&storage, root_idx)?;
 
         // Write new leafs to tree and generate pre-insert proofs
         items.iter().try_for_each(|item| {
-            let proof = MerkleTree::<PoseidonHash, 251>::get_proof(
-                root_idx,
-                &storage,
-                &item.key.view_bits().to_bitvec(),
-            )
-            .map_err(|_| TrieCacheError::ProofGenerationError)?
-            .ok_or(TrieCacheError::ProofGenerationError)?;
+            let proof = Trie::get_proof(&storage, root_idx, &item.key)?;
 
             trie.set(&storage, item.key.view_bits().to_bitvec(), item.commitment)
                 .map_err(TrieCacheError::from)?;
 
             leaf_updates.push(item.into());
-            proofs.push(proof);
+            pre_proofs.push(proof);
 
             Ok::<(), TrieCacheError>(())
         })?;
 
-        // Commit update and persist new leafs to storage
+        // Commit update, store the batch and persist new leafs to storage
         let update = trie.commit(&storage)?;
-        Trie::persist_batch_items(storage, &update, &items, batch_id)?;
-        let next_index = root_idx + update.nodes_added.len() as u64;
+        let post_root_idx = Trie::persist_update_nodes(storage, &update, root_idx)?;
+        let batch_id = store_batch(&BatchRoots {
+            pre_root,
+            root_idx: post_root_idx,
+            root_hash: update.root_commitment,
+            nodes_added: update.nodes_added.len() as u64,
+        })?;
+        storage.persist_leaves(&items, batch_id)?;
 
         // Generate post-insert proofs
-        items.iter().try_for_each(|item| {
-            let proof = MerkleTree::<PoseidonHash, 251>::get_proof(
-                next_index,
-                &storage,
-                &item.key.view_bits().to_bitvec(),
-            )
-            .map_err(|_| TrieCacheError::ProofGenerationError)?
-            .ok_or(TrieCacheError::ProofGenerationError)?;
-            proofs.push(proof);
-            Ok::<(), TrieCacheError>(())
-        })?;
+        let proofs = items
+            .iter()
+            .zip(pre_proofs)
+            .map(|(item, pre)| {
+                Ok(LeafProof {
+                    key: item.key,
+                    pre,
+                    post: Trie::get_proof(&storage, post_root_idx, &item.key)?,
+                })
+            })
+            .collect::<Result<Vec<_>, TrieCacheError>>()?;
 
-        Ok((
-            BatchProof::new::<PoseidonHash>(
+        Ok(PersistedBatch {
+            id: batch_id,
+            proof: BatchProof::new::<TrieHash>(
                 pre_root,
                 update.root_commitment,
                 leaf_updates,
                 proofs,
-                batch_id,
-            ),
-            next_index,
-        ))
+                &batch_id,
+            )?,
+            root_idx: post_root_idx,
+            root_hash: update.root_commitment,
+            nodes_added: update.nodes_added.len() as u64,
+        })
+    }
+
+    /// Generates a single proof for the transition over a range of consecutive batches.
+    ///
+    /// # Arguments
+    ///
+    /// * `pre_storage` - The TrieDB reading leaves as they were before the first batch.
+    /// * `post_storage` - The TrieDB reading leaves as they were after the last batch.
+    /// * `pre_root_idx` - The root index before the first batch.
+    /// * `post_root_idx` - The root index after the last batch.
+    /// * `keys` - The keys written by the batches, without duplicates.
+    /// * `batch_id` - The ID of the last batch.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the BatchProof, with one leaf update per key.
+    pub fn generate_range_proof(
+        pre_storage: TrieDB,
+        post_storage: TrieDB,
+        pre_root_idx: u64,
+        post_root_idx: u64,
+        keys: &[Felt],
+        batch_id: &u64,
+    ) -> Result<BatchProof, TrieCacheError> {
+        let pre_root = Trie::root_hash(&pre_storage, pre_root_idx)?;
+        let post_root = Trie::root_hash(&post_storage, post_root_idx)?;
+
+        let mut leaf_updates: Vec<LeafUpdate> = vec![];
+        let mut proofs: Vec<LeafProof> = vec![];
+        for key in keys {
+            let path = key.view_bits().to_bitvec();
+            let pre_value = pre_storage.leaf(&path)?.unwrap_or(Felt::ZERO);
+            let post_value = post_storage.leaf(&path)?.unwrap_or(Felt::ZERO);
+
+            leaf_updates.push(LeafUpdate {
+                key: hex::encode(key.to_be_bytes()),
+                pre_value: hex::encode(pre_value.to_be_bytes()),
+                post_value: hex::encode(post_value.to_be_bytes()),
+            });
+            proofs.push(LeafProof {
+                key: *key,
+                pre: Trie::get_proof(&pre_storage, pre_root_idx, key)?,
+                post: Trie::get_proof(&post_storage, post_root_idx, key)?,
+            });
+        }
+
+        BatchProof::new::<TrieHash>(pre_root, post_root, leaf_updates, proofs, batch_id)
+    }
+
+    /// Generates the proof of a key against the root at `root_idx`. The proof against the empty
+    /// trie holds no nodes.
+    fn get_proof(
+        storage: &TrieDB,
+        root_idx: u64,
+        key: &Felt,
+    ) -> Result<Vec<TrieNode>, TrieCacheError> {
+        if root_idx == EMPTY_ROOT_IDX {
+            return Ok(vec![]);
+        }
+
+        MerkleTree::<TrieHash, 251>::get_proof(root_idx, storage, &key.view_bits().to_bitvec())
+            .map_err(|_| TrieCacheError::ProofGenerationError)?
+            .ok_or(TrieCacheError::ProofGenerationError)
     }
 
-    /// Persists batch items and corresponding nodes to the TrieDB.
+    /// Persists the nodes added by an update to the TrieDB.
     ///
     /// # Arguments
     ///
     /// * `storage` - The TrieDB.
     /// * `update` - The TrieUpdate.
-    /// * `items` - The items to be persisted.
-    /// * `batch_id` - The batch ID.
+    /// * `root_idx` - The root index the update was applied to.
     ///
     /// # Returns
     ///
-    /// A Result indicating success or failure.
-    fn persist_batch_items(
+    /// A Result containing the root index after the update: the last node written, as the root
+    /// is committed last, or `root_idx` if the update added no nodes.
+    fn persist_update_nodes(
         storage: TrieDB,
         update: &TrieUpdate,
-        items: &Vec<CachedItem>,
-        batch_id: &u64,
-    ) -> Result<(), TrieCacheError> {
-        let next_index = storage.get_node_idx()? + 1;
+        root_idx: u64,
+    ) -> Result<u64, TrieCacheError> {
+        let node_count = update.nodes_added.len() as u64;
+        let next_index = storage.allocate_node_indices(node_count)?;
         let mut nodes_to_persist: Vec<(StoredNode, Felt, u64)> = vec![];
 
         // Insert new nodes into storage
@@ -194,8 +303,10 @@ impl Trie {
         }
 
         storage.persist_nodes(nodes_to_persist)?;
-        storage.persist_leaves(items, *batch_id)?;
 
-        Ok(())
+        Ok(match node_count {
+            0 => root_idx,
+            node_count => next_index + node_count - 1,
+        })
     }
 }